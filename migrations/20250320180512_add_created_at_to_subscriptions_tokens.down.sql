@@ -0,0 +1 @@
+ALTER TABLE subscriptions_tokens DROP COLUMN created_at;
@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TABLE subscriptions ADD COLUMN unsubscribe_token TEXT UNIQUE;
+UPDATE subscriptions
+    SET unsubscribe_token = md5(random()::text || id::text || clock_timestamp()::text)
+    WHERE unsubscribe_token IS NULL;
+ALTER TABLE subscriptions ALTER COLUMN unsubscribe_token SET NOT NULL;
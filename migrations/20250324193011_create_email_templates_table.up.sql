@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE email_templates (
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    name TEXT NOT NULL,
+    list TEXT,
+    version INT NOT NULL,
+    body TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX email_templates_name_list_version
+    ON email_templates (name, COALESCE(list, ''), version);
@@ -0,0 +1,4 @@
+ALTER TABLE subscriptions DROP COLUMN tracking_opt_out;
+DROP TABLE email_events;
+DROP TYPE EmailEventKind;
+DROP TABLE newsletter_issues;
@@ -0,0 +1,23 @@
+-- Add migration script here
+CREATE TABLE newsletter_issues (
+  id uuid PRIMARY KEY,
+  title TEXT NOT NULL,
+  message TEXT NOT NULL,
+  list TEXT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TYPE EmailEventKind AS ENUM ('Open', 'Click');
+
+CREATE TABLE email_events (
+  id uuid PRIMARY KEY,
+  issue_id uuid NOT NULL REFERENCES newsletter_issues(id),
+  subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+  kind EmailEventKind NOT NULL,
+  url TEXT,
+  occurred_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX email_events_issue_id_idx ON email_events (issue_id);
+
+ALTER TABLE subscriptions ADD COLUMN tracking_opt_out BOOLEAN NOT NULL DEFAULT false;
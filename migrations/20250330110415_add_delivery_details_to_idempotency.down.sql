@@ -0,0 +1,6 @@
+DROP INDEX idempotency_issue_id_idx;
+ALTER TABLE idempotency
+  DROP COLUMN issue_id,
+  DROP COLUMN error_class,
+  DROP COLUMN last_error,
+  DROP COLUMN sent_at;
@@ -0,0 +1,8 @@
+-- Add migration script here
+ALTER TABLE idempotency
+  ADD COLUMN issue_id uuid REFERENCES newsletter_issues(id),
+  ADD COLUMN error_class TEXT,
+  ADD COLUMN last_error TEXT,
+  ADD COLUMN sent_at TIMESTAMPTZ;
+
+CREATE INDEX idempotency_issue_id_idx ON idempotency (issue_id);
@@ -0,0 +1,6 @@
+-- Each background worker upserts its row periodically; readiness checks that
+-- the freshest one is recent.
+CREATE TABLE worker_heartbeats (
+    worker_id TEXT PRIMARY KEY,
+    last_seen_at TIMESTAMPTZ NOT NULL
+);
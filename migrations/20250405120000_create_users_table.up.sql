@@ -0,0 +1,7 @@
+-- Admin accounts, created with `zero2prod create-admin`.
+CREATE TABLE users (
+    user_id uuid PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,4 @@
+-- When the subscriber confirmed, so issues are only sent to whoever was
+-- confirmed when they were published.
+ALTER TABLE subscriptions ADD COLUMN confirmed_at TIMESTAMPTZ;
+UPDATE subscriptions SET confirmed_at = subscribed_at WHERE status = 'confirmed';
@@ -0,0 +1,3 @@
+-- The `Idempotency-Key` a publish request was sent with, so a retried
+-- request finds its issue instead of creating a second one.
+ALTER TABLE newsletter_issues ADD COLUMN idempotency_key TEXT UNIQUE;
@@ -0,0 +1,141 @@
+//! HTTP Basic authentication against the admin accounts created with
+//! `zero2prod create-admin`, for routes that expose subscriber details.
+
+use anyhow::Context;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        HeaderMap, HeaderValue,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use hyper::StatusCode;
+use sqlx::PgPool;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{redact::Secret, routes::error::ApiError, startup::AppState};
+
+/// Verified against when the username is unknown, so a login takes as long
+/// whether or not the account exists.
+const DUMMY_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$\
+    gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("invalid credentials")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error("unexpected error: `{0}`")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<AuthError> for ApiError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(e) => ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                "Admin credentials are required.",
+            )
+            .with_source(e),
+            AuthError::UnexpectedError(e) => ApiError::internal(e),
+        }
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let challenge = matches!(self, AuthError::InvalidCredentials(_));
+        let mut response = ApiError::from(self).into_response();
+        if challenge {
+            response.headers_mut().insert(
+                WWW_AUTHENTICATE,
+                HeaderValue::from_static(r#"Basic realm="admin""#),
+            );
+        }
+        response
+    }
+}
+
+/// Reads the `Authorization: Basic ...` header.
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header = headers
+        .get(AUTHORIZATION)
+        .context("The `Authorization` header is missing")?
+        .to_str()
+        .context("The `Authorization` header is not valid UTF-8")?;
+    let encoded = header
+        .strip_prefix("Basic ")
+        .context("The authorization scheme is not `Basic`")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .context("Failed to base64-decode the credentials")?;
+    let decoded = String::from_utf8(decoded).context("The credentials are not valid UTF-8")?;
+    let (username, password) = decoded
+        .split_once(':')
+        .context("The credentials have no `:` separator")?;
+    Ok(Credentials {
+        username: username.to_string(),
+        password: Secret::new(password.to_string()),
+    })
+}
+
+/// The id of the admin the credentials belong to.
+#[tracing::instrument(name = "Validate credentials", skip(pool, credentials))]
+pub async fn validate_credentials(
+    pool: &PgPool,
+    credentials: Credentials,
+) -> Result<Uuid, AuthError> {
+    let stored = sqlx::query!(
+        r#"SELECT user_id, password_hash FROM users WHERE username = $1"#,
+        credentials.username
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the admin")?;
+    let (user_id, expected_hash) = match stored {
+        Some(row) => (Some(row.user_id), row.password_hash),
+        None => (None, DUMMY_HASH.to_string()),
+    };
+
+    // Argon2 is deliberately slow; keep it off the async workers.
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || {
+        current_span.in_scope(|| verify_password(&expected_hash, &credentials.password))
+    })
+    .await
+    .context("Failed to spawn the password check")??;
+
+    user_id.ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username")))
+}
+
+fn verify_password(expected_hash: &str, password: &Secret<String>) -> Result<(), AuthError> {
+    let expected_hash =
+        PasswordHash::new(expected_hash).context("Failed to parse the stored password hash")?;
+    Argon2::default()
+        .verify_password(password.expose().as_bytes(), &expected_hash)
+        .context("Invalid password")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+/// Middleware letting only requests with valid admin credentials through.
+pub async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AuthError> {
+    let credentials =
+        basic_authentication(request.headers()).map_err(AuthError::InvalidCredentials)?;
+    let user_id = validate_credentials(&state.pool, credentials).await?;
+    tracing::info!("Authenticated as admin {}", user_id);
+    Ok(next.run(request).await)
+}
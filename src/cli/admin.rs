@@ -0,0 +1,55 @@
+use anyhow::{bail, Context};
+use argon2::{
+    password_hash::{PasswordHasher, SaltString},
+    Argon2,
+};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A random password for admins created without one.
+pub fn generate_password() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(24)
+        .collect()
+}
+
+/// Stores an admin account with an Argon2id hash of `password`.
+pub async fn create_admin(
+    pool: &PgPool,
+    username: &str,
+    password: &str,
+) -> Result<Uuid, anyhow::Error> {
+    if username.trim().is_empty() {
+        bail!("The username can't be empty");
+    }
+    if password.len() < 12 {
+        bail!("The password must be at least 12 characters long");
+    }
+    let salt = SaltString::generate(&mut thread_rng());
+    let password_hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash the password: {}", e))?
+        .to_string();
+
+    let user_id = Uuid::new_v4();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        user_id,
+        username,
+        password_hash
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the admin")?;
+    if inserted.rows_affected() == 0 {
+        bail!("An admin called `{}` already exists", username);
+    }
+    Ok(user_id)
+}
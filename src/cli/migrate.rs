@@ -0,0 +1,78 @@
+use std::fmt;
+
+use anyhow::{bail, Context};
+use sqlx::{migrate::Migrate, PgPool};
+
+use crate::configuration::MIGRATOR;
+
+pub async fn apply(pool: &PgPool) -> Result<(), anyhow::Error> {
+    MIGRATOR
+        .run(pool)
+        .await
+        .context("Failed to apply migrations")?;
+    tracing::info!("Database is up to date");
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+impl fmt::Display for MigrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.applied { "applied" } else { "pending" };
+        write!(f, "{} {:<8} {}", self.version, state, self.description)
+    }
+}
+
+/// Every up migration this build knows about, oldest first.
+pub async fn status(pool: &PgPool) -> Result<Vec<MigrationStatus>, anyhow::Error> {
+    let mut connection = pool.acquire().await?;
+    connection.ensure_migrations_table().await?;
+    let applied = connection.list_applied_migrations().await?;
+    Ok(MIGRATOR
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            applied: applied.iter().any(|a| a.version == m.version),
+        })
+        .collect())
+}
+
+/// Reverts the latest applied migration. Only migrations written as
+/// `<version>_<name>.up.sql`/`.down.sql` pairs can be reverted; plain
+/// `.sql` ones have to be undone by hand.
+pub async fn revert(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut connection = pool.acquire().await?;
+    connection.ensure_migrations_table().await?;
+    let applied = connection.list_applied_migrations().await?;
+    let Some(latest) = applied.iter().map(|m| m.version).max() else {
+        bail!("No migrations have been applied");
+    };
+    let Some(down) = MIGRATOR
+        .iter()
+        .find(|m| m.version == latest && m.migration_type.is_down_migration())
+    else {
+        bail!(
+            "Migration {} has no down script, so it can't be reverted automatically",
+            latest
+        );
+    };
+    let previous = applied
+        .iter()
+        .map(|m| m.version)
+        .filter(|version| *version < latest)
+        .max()
+        .unwrap_or(0);
+    MIGRATOR
+        .undo(pool, previous)
+        .await
+        .context("Failed to revert the migration")?;
+    tracing::info!("Reverted migration {} ({})", latest, down.description);
+    Ok(())
+}
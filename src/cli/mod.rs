@@ -0,0 +1,336 @@
+//! The `zero2prod` command line: the HTTP server plus the one-off jobs
+//! operators run from the same image, all configured by the same [`Settings`].
+
+use std::{io::Read, path::PathBuf};
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand};
+use sqlx::PgPool;
+
+use crate::{
+    configuration::{get_connection_pool, DeliveryMode, Settings},
+    delivery::{create_issue, deliver_issue, PreparedIssue, StoredIssue},
+    email_client::EmailBody,
+    pages::Pages,
+    startup::{shutdown_signal, AppState, Application},
+    tracking::Tracker,
+    worker::Worker,
+};
+
+pub mod admin;
+pub mod migrate;
+pub mod subscribers;
+
+#[derive(Parser, Debug)]
+#[command(name = "zero2prod", about = "Newsletter delivery service")]
+pub struct Cli {
+    /// Defaults to `serve`.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Serve the HTTP API until SIGTERM/SIGINT.
+    Serve,
+    /// Run the background worker, which resumes abandoned newsletter sends.
+    Worker,
+    /// Manage the database schema.
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Create an admin account.
+    CreateAdmin {
+        #[arg(long)]
+        username: String,
+        /// Read the password from stdin instead of generating one.
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Bulk-manage subscribers.
+    Subscribers {
+        #[command(subcommand)]
+        action: SubscribersAction,
+    },
+    /// Send newsletter issues.
+    Issues {
+        #[command(subcommand)]
+        action: IssuesAction,
+    },
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MigrateAction {
+    /// Apply every pending migration.
+    Apply,
+    /// List migrations and whether they're applied.
+    Status,
+    /// Revert the most recently applied migration.
+    Revert,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SubscribersAction {
+    /// Import subscribers from a CSV file with `email` and `name` columns,
+    /// and optionally `status` and `subscribed_at`; any other column becomes
+    /// a custom field.
+    Import { path: PathBuf },
+    /// Export subscribers as CSV, to stdout unless `--output` is given.
+    Export {
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Also export subscribers who have unsubscribed.
+        #[arg(long)]
+        include_unsubscribed: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum IssuesAction {
+    /// Publish a new issue to every confirmed subscriber.
+    Send {
+        #[arg(long)]
+        title: String,
+        /// Markdown file with the issue body.
+        #[arg(long)]
+        message_file: PathBuf,
+        /// Render with the templates saved for this list.
+        #[arg(long)]
+        list: Option<String>,
+        /// Overrides `delivery.mode`.
+        #[arg(long, value_enum)]
+        mode: Option<DeliveryMode>,
+        /// Reuse the issue stored by an earlier run with the same key, so a
+        /// retried job doesn't publish twice.
+        #[arg(long)]
+        idempotency_key: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Load the configuration and check it's usable, without connecting to
+    /// anything.
+    Check,
+    /// Print the effective configuration as JSON, with credentials
+    /// replaced by `[REDACTED]`.
+    Print {
+        /// Print the credentials as they are.
+        #[arg(long)]
+        show_secrets: bool,
+    },
+}
+
+impl Cli {
+    /// Whether the command writes its results to stdout, where the logs
+    /// would otherwise go.
+    pub fn writes_to_stdout(&self) -> bool {
+        matches!(
+            self.command,
+            Some(Command::Subscribers {
+                action: SubscribersAction::Export { output: None, .. }
+            })
+        )
+    }
+
+    pub async fn run(self, settings: Settings) -> Result<(), anyhow::Error> {
+        match self.command.unwrap_or(Command::Serve) {
+            Command::Serve => {
+                let application = Application::build(settings).await?;
+                let shutdown = application.shutdown_handle();
+                tokio::spawn(async move {
+                    shutdown_signal().await;
+                    shutdown.cancel();
+                });
+                application.run_until_stopped().await?;
+            }
+            Command::Worker => {
+                let worker_settings = settings.worker.clone();
+                let state = Application::builder(settings).build_state().await?;
+                let shutdown = state.shutdown.clone();
+                tokio::spawn(async move {
+                    shutdown_signal().await;
+                    shutdown.cancel();
+                });
+                Worker::new(state, worker_settings)
+                    .run_until_stopped()
+                    .await?;
+            }
+            Command::Migrate { action } => {
+                let pool = connect(&settings);
+                match action {
+                    MigrateAction::Apply => migrate::apply(&pool).await?,
+                    MigrateAction::Status => {
+                        for migration in migrate::status(&pool).await? {
+                            println!("{}", migration);
+                        }
+                    }
+                    MigrateAction::Revert => migrate::revert(&pool).await?,
+                }
+            }
+            Command::CreateAdmin {
+                username,
+                password_stdin,
+            } => {
+                let password = if password_stdin {
+                    let mut password = String::new();
+                    std::io::stdin().read_to_string(&mut password)?;
+                    password.trim_end_matches(['\r', '\n']).to_string()
+                } else {
+                    let password = admin::generate_password();
+                    println!("Generated password: {}", password);
+                    password
+                };
+                admin::create_admin(&connect(&settings), &username, &password).await?;
+                println!("Created admin `{}`", username);
+            }
+            Command::Subscribers { action } => {
+                let pool = connect(&settings);
+                match action {
+                    SubscribersAction::Import { path } => {
+                        let file = std::fs::File::open(&path)
+                            .with_context(|| format!("Failed to open {}", path.display()))?;
+                        let summary = subscribers::import(&pool, file).await?;
+                        println!("{}", summary);
+                    }
+                    SubscribersAction::Export {
+                        output: Some(path),
+                        include_unsubscribed,
+                    } => {
+                        let file = std::fs::File::create(&path)
+                            .with_context(|| format!("Failed to create {}", path.display()))?;
+                        subscribers::export(&pool, file, include_unsubscribed).await?;
+                    }
+                    SubscribersAction::Export {
+                        output: None,
+                        include_unsubscribed,
+                    } => {
+                        subscribers::export(&pool, std::io::stdout().lock(), include_unsubscribed)
+                            .await?;
+                    }
+                }
+            }
+            Command::Issues {
+                action:
+                    IssuesAction::Send {
+                        title,
+                        message_file,
+                        list,
+                        mode,
+                        idempotency_key,
+                    },
+            } => {
+                let message = std::fs::read_to_string(&message_file)
+                    .with_context(|| format!("Failed to read {}", message_file.display()))?;
+                let state = Application::builder(settings).build_state().await?;
+                let body = EmailBody {
+                    title,
+                    message,
+                    list,
+                };
+                let summary = send_issue(&state, body, mode, idempotency_key.as_deref()).await?;
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+            }
+            Command::Config {
+                action: ConfigAction::Check,
+            } => {
+                check_config(&settings)?;
+                println!("Configuration OK");
+            }
+            Command::Config {
+                action: ConfigAction::Print { show_secrets },
+            } => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&effective_config(&settings, show_secrets)?)?
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn connect(settings: &Settings) -> PgPool {
+    get_connection_pool(&settings.database)
+}
+
+/// Stores and delivers a new issue, as `POST /publish` does. Run again with
+/// the same `idempotency_key`, it finishes the same issue instead.
+async fn send_issue(
+    state: &AppState,
+    body: EmailBody,
+    mode: Option<DeliveryMode>,
+    idempotency_key: Option<&str>,
+) -> Result<crate::delivery::DeliverySummary, anyhow::Error> {
+    let issue = PreparedIssue::prepare(&state.pool, body, &state.merge_links()).await?;
+    let issue_id = match create_issue(&state.pool, &issue.body, idempotency_key)
+        .await
+        .context("Failed to store the newsletter issue")?
+    {
+        StoredIssue::New(id) | StoredIssue::Existing(id) => id,
+        StoredIssue::KeyReused => {
+            bail!("The idempotency key was already used for a different issue")
+        }
+    };
+    let summary =
+        deliver_issue(state, issue_id, &issue, mode.unwrap_or(state.delivery.mode)).await?;
+    Ok(summary)
+}
+
+/// `settings` as JSON, with the credentials redacted unless `show_secrets`.
+fn effective_config(
+    settings: &Settings,
+    show_secrets: bool,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let mut config = serde_json::to_value(settings)?;
+    if show_secrets {
+        for (pointer, secret) in settings.secrets() {
+            if let Some(value) = config.pointer_mut(pointer) {
+                *value = secret.expose().as_str().into();
+            }
+        }
+    }
+    Ok(config)
+}
+
+/// Checks what can be checked offline: the page templates compile and the
+/// tracking settings are complete.
+fn check_config(settings: &Settings) -> Result<(), anyhow::Error> {
+    Pages::new(&settings.pages).context("Invalid page templates")?;
+    Tracker::from_settings(&settings.tracking, &settings.application.base_url())
+        .context("Invalid tracking settings")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::{effective_config, Cli, Command, ConfigAction};
+    use crate::configuration::{load_configuration, Environment};
+
+    #[test]
+    fn config_print_redacts_credentials_by_default() {
+        let cli = Cli::parse_from(["zero2prod", "config", "print"]);
+        let Some(Command::Config {
+            action: ConfigAction::Print { show_secrets },
+        }) = cli.command
+        else {
+            panic!("Expected `config print`, got {:?}", cli.command);
+        };
+        let settings = load_configuration(Environment::Test).unwrap();
+
+        let config = effective_config(&settings, show_secrets).unwrap();
+
+        assert_eq!(config["database"]["password"], "[REDACTED]");
+        assert_eq!(config["tracking"]["secret"], "[REDACTED]");
+        let config = effective_config(&settings, true).unwrap();
+        assert_eq!(config["database"]["password"], "password");
+    }
+}
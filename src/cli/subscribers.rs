@@ -0,0 +1,186 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{Read, Write},
+};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use sqlx::{types::Json, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    domain::{SubscriberEmail, SubscriberName},
+    routes::unsubscribe::generate_unsubscribe_token,
+    templates::merge::custom_field_schema,
+};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub already_subscribed: usize,
+    pub invalid: usize,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Imported {} subscribers ({} already subscribed, {} invalid rows skipped)",
+            self.imported, self.already_subscribed, self.invalid
+        )
+    }
+}
+
+/// Statuses a subscriber can be imported with; an empty `status` means
+/// `confirmed`.
+const STATUSES: &[&str] = &["confirmed", "Pending", "unsubscribed"];
+
+/// Columns with a meaning of their own; every other one is a custom field.
+const KNOWN_COLUMNS: &[&str] = &["email", "name", "status", "subscribed_at"];
+
+/// Imports subscribers, as confirmed unless a `status` column says
+/// otherwise, since they opted in wherever the list comes from. Rows with an
+/// invalid email, name, status or `subscribed_at` are skipped and logged by
+/// line number; if the database fails, nothing is imported.
+pub async fn import(pool: &PgPool, reader: impl Read) -> Result<ImportSummary, anyhow::Error> {
+    let mut csv = csv::Reader::from_reader(reader);
+    let headers = csv
+        .headers()
+        .context("Failed to read the CSV header")?
+        .clone();
+    let column = |name: &str| headers.iter().position(|h| h.trim() == name);
+    let (Some(email_column), Some(name_column)) = (column("email"), column("name")) else {
+        bail!("The CSV must have `email` and `name` columns");
+    };
+    let (status_column, subscribed_at_column) = (column("status"), column("subscribed_at"));
+
+    let mut transaction = pool.begin().await?;
+    let mut summary = ImportSummary::default();
+    for record in csv.records() {
+        let record = record.context("Failed to read a CSV row")?;
+        let line = record.position().map_or(0, |p| p.line());
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|i| record.get(i))
+                .unwrap_or_default()
+                .trim()
+        };
+        let row = parse_row(
+            field(Some(email_column)),
+            field(Some(name_column)),
+            field(status_column),
+            field(subscribed_at_column),
+        );
+        let (email, name, status, subscribed_at) = match row {
+            Ok(row) => row,
+            Err(e) => {
+                tracing::warn!("Skipping line {}: {}", line, e);
+                summary.invalid += 1;
+                continue;
+            }
+        };
+        let custom_fields: BTreeMap<&str, &str> = headers
+            .iter()
+            .zip(record.iter())
+            .filter(|(header, value)| !KNOWN_COLUMNS.contains(&header.trim()) && !value.is_empty())
+            .map(|(header, value)| (header.trim(), value))
+            .collect();
+
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token, custom_fields, confirmed_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, CASE WHEN $5 = 'confirmed' THEN now() END)
+            ON CONFLICT (email) DO NOTHING
+            "#,
+            Uuid::new_v4(),
+            email.as_ref(),
+            name.as_ref(),
+            subscribed_at,
+            status,
+            generate_unsubscribe_token(),
+            Json(&custom_fields) as _
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to insert a subscriber")?;
+        if inserted.rows_affected() == 0 {
+            summary.already_subscribed += 1;
+        } else {
+            summary.imported += 1;
+        }
+    }
+    transaction.commit().await?;
+    Ok(summary)
+}
+
+fn parse_row(
+    email: &str,
+    name: &str,
+    status: &str,
+    subscribed_at: &str,
+) -> Result<(SubscriberEmail, SubscriberName, &'static str, DateTime<Utc>), String> {
+    let email = SubscriberEmail::parse(email).map_err(|e| e.to_string())?;
+    let name = SubscriberName::parse(name).map_err(|e| e.to_string())?;
+    let status = match status {
+        "" => "confirmed",
+        status => STATUSES
+            .iter()
+            .find(|known| **known == status)
+            .ok_or_else(|| format!("`{}` is not a subscriber status", status))?,
+    };
+    let subscribed_at = match subscribed_at {
+        "" => Utc::now(),
+        subscribed_at => DateTime::parse_from_rfc3339(subscribed_at)
+            .map_err(|e| format!("`{}` is not an RFC 3339 date: {}", subscribed_at, e))?
+            .with_timezone(&Utc),
+    };
+    Ok((email, name, status, subscribed_at))
+}
+
+/// Writes subscribers as CSV: `email`, `name`, `status`, `subscribed_at`,
+/// then one column per custom field, so the output can be imported again.
+/// Unsubscribed ones are left out unless `include_unsubscribed` is set.
+pub async fn export(
+    pool: &PgPool,
+    writer: impl Write,
+    include_unsubscribed: bool,
+) -> Result<(), anyhow::Error> {
+    let schema = custom_field_schema(pool)
+        .await
+        .context("Failed to get the custom field schema")?;
+    let rows = sqlx::query!(
+        r#"
+        SELECT email, name, status, subscribed_at,
+            custom_fields AS "custom_fields: Json<BTreeMap<String, String>>"
+        FROM subscriptions
+        WHERE $1 OR status <> 'unsubscribed'
+        ORDER BY subscribed_at, email
+        "#,
+        include_unsubscribed
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch subscribers")?;
+
+    let mut csv = csv::Writer::from_writer(writer);
+    let mut header = vec!["email", "name", "status", "subscribed_at"];
+    header.extend(schema.iter().map(String::as_str));
+    csv.write_record(&header)?;
+    for row in rows {
+        let mut record = vec![
+            row.email,
+            row.name,
+            row.status,
+            row.subscribed_at.to_rfc3339(),
+        ];
+        record.extend(
+            schema
+                .iter()
+                .map(|key| row.custom_fields.get(key).cloned().unwrap_or_default()),
+        );
+        csv.write_record(&record)?;
+    }
+    csv.flush()?;
+    Ok(())
+}
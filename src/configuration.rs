@@ -1,125 +0,0 @@
-use config::{Config, File, FileFormat};
-use dotenv::dotenv;
-use serde::Deserialize;
-use serde_aux::field_attributes::deserialize_number_from_string;
-use sqlx::postgres::{PgConnectOptions, PgSslMode};
-use sqlx::ConnectOptions;
-use sqlx::Executor;
-use sqlx::{Connection, PgConnection, PgPool};
-use tracing_log::log::LevelFilter;
-
-use crate::domain::{ParseError, SubscriberEmail};
-
-#[derive(Deserialize, Debug, Clone)]
-pub struct Settings {
-    pub database: DatabaseSettings,
-    pub application: ApplicationSettings,
-}
-
-#[derive(Deserialize, Debug, Clone)]
-pub struct DatabaseSettings {
-    pub username: String,
-    pub password: String,
-    #[serde(deserialize_with = "deserialize_number_from_string")]
-    pub port: u16,
-    pub host: String,
-    pub database_name: String,
-    pub require_ssl: bool,
-}
-
-#[derive(Deserialize, Debug, Clone)]
-pub struct ApplicationSettings {
-    #[serde(deserialize_with = "deserialize_number_from_string")]
-    pub port: u16,
-    pub host: String,
-}
-
-impl ApplicationSettings {
-    pub fn get_address(&self) -> String {
-        format!("{}:{}", self.host, self.port)
-    }
-}
-
-impl DatabaseSettings {
-    pub fn without_db(&self) -> PgConnectOptions {
-        let ssl = if self.require_ssl {
-            PgSslMode::Require
-        } else {
-            PgSslMode::Prefer
-        };
-
-        PgConnectOptions::new()
-            .host(&self.host)
-            .username(&self.username)
-            .password(&self.password)
-            .port(self.port)
-            .ssl_mode(ssl)
-    }
-
-    pub fn with_db(&self) -> PgConnectOptions {
-        let opts = self.without_db().database(&self.database_name);
-        opts.log_statements(LevelFilter::Trace)
-    }
-}
-
-pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
-    let mut connection = PgConnection::connect_with(&config.without_db())
-        .await
-        .expect("Failed to connect to Postgres");
-
-    connection
-        .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
-        .await
-        .expect("Failed to create database.");
-
-    let connection_pool = PgPool::connect_lazy_with(config.with_db());
-    sqlx::migrate!()
-        .run(&connection_pool)
-        .await
-        .expect("Failed to migrate the database");
-
-    connection_pool
-}
-
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
-    dotenv().ok();
-
-    let mut config = Config::builder()
-        .add_source(File::new("./config/base.yaml", FileFormat::Yaml).required(false));
-
-    let environment = std::env::var("APP_ENVIRONMENT")
-        .unwrap_or_else(|_| "local".into())
-        .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT");
-
-    match environment {
-        Environment::Local => {
-            config = config.add_source(File::new("./config/local.yaml", FileFormat::Yaml));
-        }
-        Environment::Production => {
-            config = config.add_source(File::new("./config/production.yaml", FileFormat::Yaml));
-        }
-    };
-    config = config.add_source(config::Environment::with_prefix("LOCAL").separator("_"));
-    // prod env var override
-    config = config.add_source(config::Environment::with_prefix("app").separator("__"));
-
-    config.build()?.try_deserialize()
-}
-
-enum Environment {
-    Local,
-    Production,
-}
-
-impl TryFrom<String> for Environment {
-    type Error = String;
-
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        match value.to_lowercase().as_str() {
-            "local" => Ok(Environment::Local),
-            "production" => Ok(Environment::Production),
-            e => Err(format!("{} is not supported Environment", e)),
-        }
-    }
-}
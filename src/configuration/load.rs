@@ -0,0 +1,768 @@
+//! Where [`Settings`] come from. Each layer overrides the ones before it:
+//!
+//! 1. `config/base.yaml`, shared by every profile;
+//! 2. `config/<profile>.yaml`, the profile being `APP_ENVIRONMENT` (`local`,
+//!    `test`, `staging` or `production`; `local` when unset);
+//! 3. `APP__` environment variables, with `__` between path segments, e.g.
+//!    `APP__DATABASE__PASSWORD` for `database.password`;
+//! 4. `APP__..._FILE` variables naming a file that holds the value, e.g.
+//!    `APP__DATABASE__PASSWORD_FILE=/run/secrets/db_password`.
+//!
+//! Loading reports everything wrong with the result at once, each problem
+//! with the layer its value came from.
+
+use std::{fmt, path::Path};
+
+use config::{Config, ConfigError, File, FileFormat, Map, Source, Value, ValueKind};
+use dotenv::dotenv;
+use serde::de::DeserializeOwned;
+
+use super::{
+    ApplicationSettings, CustomFieldSettings, DatabaseSettings, DeliverabilitySettings,
+    DeliverySettings, EmailBackendKind, EmailTemplateSettings, HealthSettings, IssueSettings,
+    LoggingSettings, MetricsSettings, PagesSettings, Settings, TelemetrySettings, TrackingSettings,
+    WorkerSettings,
+};
+
+const ENV_PREFIX: &str = "APP__";
+/// How `config` labels values taken from environment variables.
+const ENV_ORIGIN: &str = "the environment";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Environment {
+    Local,
+    Test,
+    Staging,
+    Production,
+}
+
+impl Environment {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Environment::Local => "local",
+            Environment::Test => "test",
+            Environment::Staging => "staging",
+            Environment::Production => "production",
+        }
+    }
+
+    /// Staging and production face real users, so they get stricter checks.
+    fn is_deployed(self) -> bool {
+        matches!(self, Environment::Staging | Environment::Production)
+    }
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<String> for Environment {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "local" => Ok(Environment::Local),
+            "test" => Ok(Environment::Test),
+            "staging" => Ok(Environment::Staging),
+            "production" => Ok(Environment::Production),
+            _ => Err(value),
+        }
+    }
+}
+
+/// One invalid or missing setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Dotted path of the setting, e.g. `database.port`.
+    pub key: String,
+    /// The file or environment variable the value came from; `default` or
+    /// `unset` when it came from neither.
+    pub source: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.key, self.source, self.message)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigurationError {
+    #[error("APP_ENVIRONMENT is `{0}`, expected `local`, `test`, `staging` or `production`")]
+    Environment(String),
+    #[error("failed to read the configuration files")]
+    Files(#[source] ConfigError),
+    #[error("invalid configuration:{}", .0.iter().map(|p| format!("\n  - {}", p)).collect::<String>())]
+    Invalid(Vec<Problem>),
+}
+
+/// Loads the settings for the profile named by `APP_ENVIRONMENT`.
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
+    dotenv().ok();
+    let environment = match std::env::var("APP_ENVIRONMENT") {
+        Ok(value) => Environment::try_from(value).map_err(ConfigurationError::Environment)?,
+        Err(_) => Environment::Local,
+    };
+    load_configuration(environment)
+}
+
+/// Loads the settings for `environment` from `./config` and the process
+/// environment.
+pub fn load_configuration(environment: Environment) -> Result<Settings, ConfigurationError> {
+    dotenv().ok();
+    load(Path::new("config"), environment, std::env::vars())
+}
+
+type Check = fn(Value) -> Result<(), Box<serde_path_to_error::Error<ConfigError>>>;
+
+fn parses<T: DeserializeOwned>(
+    value: Value,
+) -> Result<(), Box<serde_path_to_error::Error<ConfigError>>> {
+    serde_path_to_error::deserialize::<_, T>(value)
+        .map(|_| ())
+        .map_err(Box::new)
+}
+
+/// Every top-level section of [`Settings`], with the check that it parses.
+const SECTIONS: &[(&str, Check)] = &[
+    ("application", parses::<ApplicationSettings>),
+    ("custom_fields", parses::<CustomFieldSettings>),
+    ("database", parses::<DatabaseSettings>),
+    ("deliverability", parses::<DeliverabilitySettings>),
+    ("delivery", parses::<DeliverySettings>),
+    ("email_templates", parses::<EmailTemplateSettings>),
+    ("health", parses::<HealthSettings>),
+    ("issues", parses::<IssueSettings>),
+    ("logging", parses::<LoggingSettings>),
+    ("metrics", parses::<MetricsSettings>),
+    ("pages", parses::<PagesSettings>),
+    ("telemetry", parses::<TelemetrySettings>),
+    ("tracking", parses::<TrackingSettings>),
+    ("worker", parses::<WorkerSettings>),
+];
+
+/// Settings without a default.
+const REQUIRED: &[&str] = &[
+    "application.host",
+    "application.port",
+    "database.host",
+    "database.port",
+    "database.username",
+    "database.password",
+    "database.database_name",
+    "database.require_ssl",
+];
+
+fn load(
+    dir: &Path,
+    environment: Environment,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Settings, ConfigurationError> {
+    let mut problems = vec![];
+    let mut plain = Map::new();
+    let mut from_files = vec![];
+    for (name, value) in vars {
+        if !name.starts_with(ENV_PREFIX) {
+            continue;
+        }
+        match name.strip_suffix("_FILE") {
+            Some(target) => from_files.push((target.to_string(), value)),
+            None => {
+                plain.insert(name, value);
+            }
+        }
+    }
+    let secret_files = SecretFiles::read(from_files, &plain, &mut problems);
+
+    let config = Config::builder()
+        .add_source(File::from(dir.join("base.yaml")).format(FileFormat::Yaml))
+        .add_source(File::from(dir.join(format!("{}.yaml", environment))).format(FileFormat::Yaml))
+        .add_source(
+            config::Environment::with_prefix("APP")
+                .prefix_separator("__")
+                .separator("__")
+                .source(Some(plain)),
+        )
+        .add_source(secret_files)
+        .build()
+        .map_err(ConfigurationError::Files)?;
+
+    if let ValueKind::Table(sections) = &config.cache.kind {
+        for (section, value) in sections {
+            if !SECTIONS.iter().any(|(name, _)| name == section) {
+                problems.push(Problem {
+                    key: section.clone(),
+                    source: describe_origin(section, leaf_origin(value)),
+                    message: "unknown section".to_string(),
+                });
+            }
+        }
+    }
+    // A secret file that couldn't be read has already been reported.
+    let missing: Vec<&str> = REQUIRED
+        .iter()
+        .copied()
+        .filter(|key| lookup(&config, key).is_none() && !problems.iter().any(|p| p.key == *key))
+        .collect();
+    for key in &missing {
+        problems.push(Problem {
+            key: key.to_string(),
+            source: "unset".to_string(),
+            message: format!(
+                "is required; set it in a config file or with {}",
+                env_var(key)
+            ),
+        });
+    }
+    // Validation below runs on a copy without the fields that don't parse,
+    // so their problems are reported alongside the rest.
+    let mut parsed = config.cache.clone();
+    for (section, check) in SECTIONS {
+        let incomplete = REQUIRED
+            .iter()
+            .any(|key| key.split('.').next() == Some(*section) && lookup(&config, key).is_none());
+        if incomplete {
+            continue;
+        }
+        if let (Some(value), ValueKind::Table(sections)) = (
+            check_section(&config, section, *check, &mut problems),
+            &mut parsed.kind,
+        ) {
+            sections.insert(section.to_string(), value);
+        }
+    }
+
+    match parsed.try_deserialize::<Settings>() {
+        Ok(settings) => {
+            for (key, message) in validate(&settings, environment) {
+                if !problems.iter().any(|p| p.key == key) {
+                    problems.push(Problem {
+                        key: key.to_string(),
+                        source: source_of(&config, key),
+                        message,
+                    });
+                }
+            }
+            if problems.is_empty() {
+                return Ok(settings);
+            }
+        }
+        // Only unparseable when a required setting is missing or invalid,
+        // which is already reported.
+        Err(e) if problems.is_empty() => problems.push(Problem {
+            key: "settings".to_string(),
+            source: "all layers".to_string(),
+            message: e.to_string(),
+        }),
+        Err(_) => {}
+    }
+    Err(ConfigurationError::Invalid(problems))
+}
+
+/// Checks what parsing can't: values that are well-formed but unusable
+/// together or in `environment`.
+fn validate(settings: &Settings, environment: Environment) -> Vec<(&'static str, String)> {
+    let mut problems = vec![];
+    let mut check = |ok: bool, key: &'static str, message: String| {
+        if !ok {
+            problems.push((key, message));
+        }
+    };
+
+    match reqwest::Url::parse(&settings.application.base_url) {
+        Ok(url) => {
+            check(
+                matches!(url.scheme(), "http" | "https"),
+                "application.base_url",
+                "must be an http or https URL".to_string(),
+            );
+            check(
+                !environment.is_deployed()
+                    || !matches!(url.host_str(), Some("localhost" | "127.0.0.1")),
+                "application.base_url",
+                format!("must be the public URL in {}", environment),
+            );
+        }
+        Err(e) => check(false, "application.base_url", format!("invalid URL: {}", e)),
+    }
+    if let Some(admin_port) = settings.metrics.admin_port {
+        check(
+            admin_port == 0 || admin_port != settings.application.port,
+            "metrics.admin_port",
+            "must differ from application.port".to_string(),
+        );
+    }
+    let pool = &settings.database.pool;
+    check(
+        pool.max_connections > 0,
+        "database.pool.max_connections",
+        "must be at least 1".to_string(),
+    );
+    check(
+        pool.min_connections <= pool.max_connections,
+        "database.pool.min_connections",
+        "can't exceed database.pool.max_connections".to_string(),
+    );
+    check(
+        pool.acquire_timeout_ms > 0,
+        "database.pool.acquire_timeout_ms",
+        "must be positive".to_string(),
+    );
+    check(
+        !environment.is_deployed() || settings.database.require_ssl,
+        "database.require_ssl",
+        format!("must be true in {}", environment),
+    );
+    check(
+        !environment.is_deployed() || !settings.database.password.expose().is_empty(),
+        "database.password",
+        format!("can't be empty in {}", environment),
+    );
+    check(
+        environment != Environment::Production
+            || settings.delivery.backend != EmailBackendKind::Fake,
+        "delivery.backend",
+        "can't be `fake` in production".to_string(),
+    );
+    check(
+        settings.delivery.backend != EmailBackendKind::Ses
+            || settings.delivery.sender_email.is_some(),
+        "delivery.sender_email",
+        "is required by the `ses` backend".to_string(),
+    );
+    check(
+        settings.delivery.concurrency > 0,
+        "delivery.concurrency",
+        "must be at least 1".to_string(),
+    );
+    check(
+        settings
+            .delivery
+            .max_send_rate
+            .is_none_or(|rate| rate > 0.0),
+        "delivery.max_send_rate",
+        "must be positive".to_string(),
+    );
+    check(
+        !settings.tracking.enabled || !settings.tracking.secret.expose().is_empty(),
+        "tracking.secret",
+        "is required when tracking is enabled".to_string(),
+    );
+    check(
+        (0.0..=1.0).contains(&settings.telemetry.sampling_ratio),
+        "telemetry.sampling_ratio",
+        "must be between 0.0 and 1.0".to_string(),
+    );
+    if let Some(endpoint) = &settings.telemetry.otlp_endpoint {
+        if let Err(e) = reqwest::Url::parse(endpoint) {
+            check(
+                false,
+                "telemetry.otlp_endpoint",
+                format!("invalid URL: {}", e),
+            );
+        }
+    }
+    check(
+        settings.health.check_timeout_ms > 0,
+        "health.check_timeout_ms",
+        "must be positive".to_string(),
+    );
+    check(
+        settings.custom_fields.max_value_length > 0,
+        "custom_fields.max_value_length",
+        "must be positive".to_string(),
+    );
+    check(
+        settings.worker.poll_interval_secs > 0,
+        "worker.poll_interval_secs",
+        "must be positive".to_string(),
+    );
+    problems
+}
+
+/// Reports every field of `section` that doesn't parse: each bad field is
+/// dropped and the section parsed again, so one doesn't hide the next.
+/// Returns what's left of the section, unless it's unusable as a whole.
+fn check_section(
+    config: &Config,
+    section: &str,
+    check: Check,
+    problems: &mut Vec<Problem>,
+) -> Option<Value> {
+    let mut value = lookup(config, section)?.clone();
+    let reported = problems.len();
+    while let Err(e) = check(value.clone()) {
+        let path = e.path().to_string();
+        if path == "." || !remove(&mut value, &path) {
+            // Nothing left to blame a field for, e.g. a section that isn't
+            // a table.
+            if problems.len() == reported {
+                problems.push(Problem {
+                    key: section.to_string(),
+                    source: source_of(config, section),
+                    message: describe_error(e.into_inner()),
+                });
+            }
+            return None;
+        }
+        let key = format!("{}.{}", section, path);
+        problems.push(Problem {
+            source: source_of(config, &key),
+            key,
+            message: describe_error(e.into_inner()),
+        });
+    }
+    Some(value)
+}
+
+/// The error without the key and origin `config` adds, which problems
+/// report separately.
+fn describe_error(error: ConfigError) -> String {
+    match error {
+        ConfigError::At { error, .. } => describe_error(*error),
+        ConfigError::Type {
+            unexpected,
+            expected,
+            ..
+        } => format!("expected {}, found {}", expected, unexpected),
+        error => error.to_string(),
+    }
+}
+
+/// Removes the field at `path` (relative to `value`), or the list holding
+/// it for a path into a list.
+fn remove(value: &mut Value, path: &str) -> bool {
+    let path = path.split('[').next().unwrap_or(path);
+    let (parents, field) = match path.rsplit_once('.') {
+        Some((parents, field)) => (Some(parents), field),
+        None => (None, path),
+    };
+    let mut table = value;
+    for part in parents.into_iter().flat_map(|p| p.split('.')) {
+        match &mut table.kind {
+            ValueKind::Table(t) => match t.get_mut(part) {
+                Some(next) => table = next,
+                None => return false,
+            },
+            _ => return false,
+        }
+    }
+    match &mut table.kind {
+        ValueKind::Table(t) => t.remove(field).is_some(),
+        _ => false,
+    }
+}
+
+fn lookup<'a>(config: &'a Config, key: &str) -> Option<&'a Value> {
+    let key = key.split('[').next().unwrap_or(key);
+    key.split('.')
+        .try_fold(&config.cache, |value, part| match &value.kind {
+            ValueKind::Table(table) => table.get(part),
+            _ => None,
+        })
+}
+
+/// The file or environment variable the value at `key` came from.
+fn source_of(config: &Config, key: &str) -> String {
+    match lookup(config, key) {
+        Some(value) => describe_origin(key, value.origin()),
+        None => "default".to_string(),
+    }
+}
+
+/// Tables have no origin of their own, so this is the origin of a value in
+/// them.
+fn leaf_origin(value: &Value) -> Option<&str> {
+    match &value.kind {
+        ValueKind::Table(table) => table.values().find_map(leaf_origin),
+        _ => value.origin(),
+    }
+}
+
+fn describe_origin(key: &str, origin: Option<&str>) -> String {
+    match origin {
+        Some(ENV_ORIGIN) => env_var(key),
+        Some(origin) => origin.to_string(),
+        None => "default".to_string(),
+    }
+}
+
+/// The environment variable that sets `key`.
+fn env_var(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', "__").to_uppercase())
+}
+
+/// Values read from the files named by `APP__..._FILE` variables.
+#[derive(Debug, Clone)]
+struct SecretFiles(Map<String, Value>);
+
+impl SecretFiles {
+    /// `from_files` pairs the variable each file stands in for with the
+    /// file's path. Unreadable files, and values also set directly in
+    /// `plain`, are reported in `problems`.
+    fn read(
+        from_files: Vec<(String, String)>,
+        plain: &Map<String, String>,
+        problems: &mut Vec<Problem>,
+    ) -> Self {
+        let mut values = Map::new();
+        for (target, path) in from_files {
+            let key = target[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+            let source = format!("{} via {}_FILE", path, target);
+            if plain.contains_key(&target) {
+                problems.push(Problem {
+                    key,
+                    source,
+                    message: format!("also set by {}; use one or the other", target),
+                });
+                continue;
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(contents) => {
+                    let value = contents.trim_end_matches(['\r', '\n']).to_string();
+                    values.insert(key, Value::new(Some(&source), ValueKind::String(value)));
+                }
+                Err(e) => problems.push(Problem {
+                    key,
+                    source,
+                    message: format!("failed to read the file: {}", e),
+                }),
+            }
+        }
+        Self(values)
+    }
+}
+
+impl Source for SecretFiles {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<Map<String, Value>, ConfigError> {
+        Ok(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use uuid::Uuid;
+
+    use super::{load, ConfigurationError, Environment, Problem};
+
+    const BASE: &str = "
+application:
+  port: 8000
+  host: 127.0.0.1
+database:
+  host: localhost
+  port: 5432
+  username: postgres
+  database_name: newsletter
+  require_ssl: false
+delivery:
+  sender_email: newsletter@example.com
+";
+
+    /// A config directory with `base.yaml` and `<profile>.yaml` for the
+    /// given profiles.
+    fn config_dir(profiles: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("zero2prod-config-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("base.yaml"), BASE).unwrap();
+        for (profile, contents) in profiles {
+            std::fs::write(dir.join(format!("{}.yaml", profile)), contents).unwrap();
+        }
+        dir
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn problems(result: Result<impl std::fmt::Debug, ConfigurationError>) -> Vec<Problem> {
+        match result {
+            Err(ConfigurationError::Invalid(problems)) => problems,
+            other => panic!("Expected invalid settings, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let dir = config_dir(&[("local", "application:\n  port: 9000\n  host: 0.0.0.0\n")]);
+
+        let settings = load(
+            &dir,
+            Environment::Local,
+            vars(&[
+                ("APP__APPLICATION__PORT", "9100"),
+                ("APP__DATABASE__PASSWORD", "password"),
+                ("LOCAL_APPLICATION_PORT", "9200"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(settings.application.port, 9100);
+        assert_eq!(settings.application.host, "0.0.0.0");
+        assert_eq!(settings.database.password.expose(), "password");
+    }
+
+    #[test]
+    fn secrets_can_be_read_from_files() {
+        let dir = config_dir(&[("local", "")]);
+        let secret = dir.join("db_password");
+        std::fs::write(&secret, "hunter2hunter2\n").unwrap();
+
+        let settings = load(
+            &dir,
+            Environment::Local,
+            vars(&[("APP__DATABASE__PASSWORD_FILE", secret.to_str().unwrap())]),
+        )
+        .unwrap();
+
+        assert_eq!(settings.database.password.expose(), "hunter2hunter2");
+    }
+
+    #[test]
+    fn secret_files_must_exist_and_not_be_set_twice() {
+        let dir = config_dir(&[("local", "")]);
+
+        let problems = problems(load(
+            &dir,
+            Environment::Local,
+            vars(&[
+                ("APP__DATABASE__PASSWORD_FILE", "/nonexistent/db_password"),
+                ("APP__TRACKING__SECRET", "secret"),
+                ("APP__TRACKING__SECRET_FILE", "/nonexistent/tracking_secret"),
+            ]),
+        ));
+
+        let keys: Vec<&str> = problems.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, ["database.password", "tracking.secret"]);
+        assert_eq!(
+            problems[0].source,
+            "/nonexistent/db_password via APP__DATABASE__PASSWORD_FILE"
+        );
+        assert!(problems[0].message.starts_with("failed to read the file"));
+        assert!(problems[1].message.contains("APP__TRACKING__SECRET"));
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_its_source() {
+        let dir = config_dir(&[(
+            "local",
+            "emailclient:\n  sender_email: newsletter@example.com\n\
+             delivery:\n  concurrency: lots\n  backend: smtp\n",
+        )]);
+
+        let problems = problems(load(
+            &dir,
+            Environment::Local,
+            vars(&[("APP__APPLICATION__PORT", "eighty")]),
+        ));
+
+        let find = |key: &str| {
+            problems
+                .iter()
+                .find(|p| p.key == key)
+                .unwrap_or_else(|| panic!("No problem with {} in {:?}", key, problems))
+        };
+        assert_eq!(problems.len(), 5, "{:?}", problems);
+        assert!(find("emailclient").source.ends_with("local.yaml"));
+        assert_eq!(find("database.password").source, "unset");
+        assert!(find("database.password")
+            .message
+            .contains("APP__DATABASE__PASSWORD"));
+        assert_eq!(find("application.port").source, "APP__APPLICATION__PORT");
+        assert!(find("delivery.concurrency").source.ends_with("local.yaml"));
+        assert!(find("delivery.backend").message.contains("smtp"));
+    }
+
+    #[test]
+    fn deployed_profiles_are_checked_more_strictly_alongside_parsing() {
+        let dir = config_dir(&[("production", "delivery:\n  backend: fake\n")]);
+
+        let problems = problems(load(
+            &dir,
+            Environment::Production,
+            vars(&[
+                ("APP__DATABASE__PASSWORD", ""),
+                ("APP__DELIVERY__CONCURRENCY", "many"),
+            ]),
+        ));
+
+        let mut keys: Vec<&str> = problems.iter().map(|p| p.key.as_str()).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "application.base_url",
+                "database.password",
+                "database.require_ssl",
+                "delivery.backend",
+                "delivery.concurrency"
+            ]
+        );
+        let source = |key: &str| &problems.iter().find(|p| p.key == key).unwrap().source;
+        assert_eq!(source("application.base_url"), "default");
+        assert!(source("database.require_ssl").ends_with("base.yaml"));
+    }
+
+    #[test]
+    fn pool_limits_are_checked() {
+        let dir = config_dir(&[(
+            "local",
+            "database:\n  pool:\n    max_connections: 2\n    min_connections: 5\n",
+        )]);
+
+        let problems = problems(load(
+            &dir,
+            Environment::Local,
+            vars(&[("APP__DATABASE__PASSWORD", "password")]),
+        ));
+
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert_eq!(problems[0].key, "database.pool.min_connections");
+        assert!(problems[0].source.ends_with("local.yaml"));
+    }
+
+    #[test]
+    fn the_repo_profiles_load() {
+        let config = Path::new("config");
+        let deployed = vars(&[
+            ("APP__DATABASE__HOST", "db.internal"),
+            ("APP__DATABASE__USERNAME", "newsletter"),
+            ("APP__DATABASE__PASSWORD", "password"),
+            ("APP__DATABASE__DATABASE_NAME", "newsletter"),
+            (
+                "APP__APPLICATION__BASE_URL",
+                "https://newsletter.example.com",
+            ),
+        ]);
+        for environment in [Environment::Local, Environment::Test] {
+            load(config, environment, vec![]).unwrap();
+        }
+        for environment in [Environment::Staging, Environment::Production] {
+            let settings = load(config, environment, deployed.clone()).unwrap();
+            assert!(settings.database.require_ssl);
+        }
+    }
+
+    #[test]
+    fn unknown_environments_are_rejected() {
+        assert_eq!(
+            Environment::try_from("Staging".to_string()),
+            Ok(Environment::Staging)
+        );
+        assert_eq!(
+            Environment::try_from("prod".to_string()),
+            Err("prod".to_string())
+        );
+    }
+}
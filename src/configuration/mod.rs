@@ -0,0 +1,573 @@
+use serde::{Deserialize, Serialize};
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
+use sqlx::migrate::Migrator;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgSslMode};
+use sqlx::ConnectOptions;
+use sqlx::Executor;
+use sqlx::{Connection, PgConnection, PgPool};
+use tracing_log::log::LevelFilter;
+
+use crate::{domain::SubscriberEmail, pages::PageOutcome, redact::Secret};
+
+mod load;
+
+pub use load::{get_configuration, load_configuration, ConfigurationError, Environment, Problem};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Settings {
+    pub database: DatabaseSettings,
+    pub application: ApplicationSettings,
+    #[serde(default)]
+    pub deliverability: DeliverabilitySettings,
+    #[serde(default)]
+    pub pages: PagesSettings,
+    #[serde(default)]
+    pub email_templates: EmailTemplateSettings,
+    #[serde(default)]
+    pub issues: IssueSettings,
+    #[serde(default)]
+    pub custom_fields: CustomFieldSettings,
+    #[serde(default)]
+    pub tracking: TrackingSettings,
+    #[serde(default)]
+    pub delivery: DeliverySettings,
+    #[serde(default)]
+    pub metrics: MetricsSettings,
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    #[serde(default)]
+    pub logging: LoggingSettings,
+    #[serde(default)]
+    pub health: HealthSettings,
+    #[serde(default)]
+    pub worker: WorkerSettings,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DatabaseSettings {
+    pub username: String,
+    pub password: Secret<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub host: String,
+    pub database_name: String,
+    pub require_ssl: bool,
+    #[serde(default)]
+    pub pool: PoolSettings,
+    /// Postgres cancels statements running longer than this; unset for no
+    /// limit.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub statement_timeout_ms: Option<u64>,
+    /// Shown in `pg_stat_activity`, to tell our connections apart.
+    #[serde(default = "default_application_name")]
+    pub application_name: String,
+    /// Level every statement is logged at.
+    #[serde(default)]
+    pub log_statements: StatementLogLevel,
+    /// Statements slower than this are logged as warnings.
+    #[serde(
+        default = "default_slow_statement_threshold_ms",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub slow_statement_threshold_ms: u64,
+}
+
+fn default_application_name() -> String {
+    "zero2prod".to_string()
+}
+
+fn default_slow_statement_threshold_ms() -> u64 {
+    1000
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PoolSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_connections: u32,
+    /// Connections kept open even when idle.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_connections: u32,
+    /// How long a query waits for a free connection before failing; requests
+    /// that give up get a `503`.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub acquire_timeout_ms: u64,
+    /// Idle connections above `min_connections` are closed after this; unset
+    /// to keep them.
+    #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    pub idle_timeout_secs: Option<u64>,
+    /// Connections are replaced after this long; unset to keep them.
+    #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    pub max_lifetime_secs: Option<u64>,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_ms: 5000,
+            idle_timeout_secs: Some(600),
+            max_lifetime_secs: Some(1800),
+        }
+    }
+}
+
+impl PoolSettings {
+    pub fn options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(std::time::Duration::from_millis(self.acquire_timeout_ms))
+            .idle_timeout(self.idle_timeout_secs.map(std::time::Duration::from_secs))
+            .max_lifetime(self.max_lifetime_secs.map(std::time::Duration::from_secs))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementLogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    #[default]
+    Debug,
+    Trace,
+}
+
+impl From<StatementLogLevel> for LevelFilter {
+    fn from(level: StatementLogLevel) -> Self {
+        match level {
+            StatementLogLevel::Off => LevelFilter::Off,
+            StatementLogLevel::Error => LevelFilter::Error,
+            StatementLogLevel::Warn => LevelFilter::Warn,
+            StatementLogLevel::Info => LevelFilter::Info,
+            StatementLogLevel::Debug => LevelFilter::Debug,
+            StatementLogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApplicationSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub host: String,
+    #[serde(
+        default = "default_confirmation_token_ttl_hours",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub confirmation_token_ttl_hours: i64,
+    /// Public URL of this deployment, used to build links in emails.
+    #[serde(default = "default_base_url")]
+    pub base_url: String,
+    /// How long in-flight requests, including newsletter sends, get to finish
+    /// after SIGTERM/SIGINT before the server stops anyway.
+    #[serde(
+        default = "default_drain_timeout_secs",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub drain_timeout_secs: u64,
+}
+
+fn default_confirmation_token_ttl_hours() -> i64 {
+    72
+}
+
+fn default_base_url() -> String {
+    "http://localhost:8000".to_string()
+}
+
+fn default_drain_timeout_secs() -> u64 {
+    30
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DeliverabilitySettings {
+    pub enabled: bool,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_cache_ttl_secs: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub negative_cache_ttl_secs: u64,
+}
+
+impl Default for DeliverabilitySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_cache_ttl_secs: 3600,
+            negative_cache_ttl_secs: 300,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PagesSettings {
+    /// Directory with `<page>.html` files overriding the built-in templates.
+    pub template_dir: Option<String>,
+    pub theme: ThemeSettings,
+    pub redirects: PageRedirects,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ThemeSettings {
+    pub site_name: String,
+    pub primary_color: String,
+    pub logo_url: Option<String>,
+}
+
+impl Default for ThemeSettings {
+    fn default() -> Self {
+        Self {
+            site_name: "Newsletter".to_string(),
+            primary_color: "#4a86e8".to_string(),
+            logo_url: None,
+        }
+    }
+}
+
+/// Per-outcome URLs browsers are redirected to instead of our own pages.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PageRedirects {
+    pub confirmed: Option<String>,
+    pub already_confirmed: Option<String>,
+    pub link_expired: Option<String>,
+    pub unsubscribed: Option<String>,
+    pub error: Option<String>,
+}
+
+impl PageRedirects {
+    pub fn for_outcome(&self, outcome: PageOutcome) -> Option<&str> {
+        match outcome {
+            PageOutcome::Confirmed => self.confirmed.as_deref(),
+            PageOutcome::AlreadyConfirmed => self.already_confirmed.as_deref(),
+            PageOutcome::LinkExpired => self.link_expired.as_deref(),
+            PageOutcome::Unsubscribed => self.unsubscribed.as_deref(),
+            // The forms have to be served by us to post back here.
+            PageOutcome::ConfirmUnsubscribe | PageOutcome::ConfirmOptOut => None,
+            PageOutcome::Error => self.error.as_deref(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EmailTemplateSettings {
+    /// Directory whose files override the built-in email templates by name.
+    pub directory: Option<String>,
+    pub load_from_database: bool,
+}
+
+impl Default for EmailTemplateSettings {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            load_from_database: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct IssueSettings {
+    /// Internal addresses `POST /issues/test-send` delivers to.
+    pub seed_addresses: Vec<SubscriberEmail>,
+    /// Target of the `{{preferences_link}}` merge field.
+    pub preferences_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CustomFieldSettings {
+    /// Custom fields subscribers may set when signing up. Anything else is
+    /// rejected, so visitors can't add merge fields of their own.
+    pub names: Vec<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_value_length: usize,
+}
+
+impl Default for CustomFieldSettings {
+    fn default() -> Self {
+        Self {
+            names: vec![],
+            max_value_length: 256,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TrackingSettings {
+    /// Rewrite newsletter links through the click redirect and add an open pixel.
+    pub enabled: bool,
+    /// Key used to sign tracking URLs; required when tracking is enabled.
+    pub secret: Secret<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailBackendKind {
+    Ses,
+    /// Records emails in memory instead of sending them.
+    Fake,
+}
+
+/// How a newsletter is handed to the backend.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryMode {
+    /// One API call per recipient.
+    Individual,
+    /// Up to 50 recipients per call (SES `SendBulkTemplatedEmail`).
+    Bulk,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DeliverySettings {
+    pub backend: EmailBackendKind,
+    /// The `From` address; required by the SES backend.
+    pub sender_email: Option<SubscriberEmail>,
+    /// Used unless a send asks for a mode with `?mode=`.
+    pub mode: DeliveryMode,
+    /// How many newsletter emails are in flight at once.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub concurrency: usize,
+    /// Emails per second; queried from the backend's send quota when unset.
+    #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    pub max_send_rate: Option<f64>,
+    /// How often a throttled send is retried before it counts as failed.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_throttle_retries: u32,
+}
+
+impl Default for DeliverySettings {
+    fn default() -> Self {
+        Self {
+            backend: EmailBackendKind::Ses,
+            sender_email: None,
+            mode: DeliveryMode::Individual,
+            concurrency: 8,
+            max_send_rate: None,
+            max_throttle_retries: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct MetricsSettings {
+    /// Serve `/metrics` on this port (same host) instead of the public one.
+    #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    pub admin_port: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TelemetrySettings {
+    /// OTLP/HTTP collector base URL, e.g. `http://localhost:4318`; spans are
+    /// only exported when it's set.
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+    /// Fraction of new traces to sample, from 0.0 to 1.0.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub sampling_ratio: f64,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "zero2prod".to_string(),
+            sampling_ratio: 1.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HealthSettings {
+    /// Each readiness check counts as down if it takes longer than this.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub check_timeout_ms: u64,
+    /// Workers count as down when none has reported in this long. That's
+    /// reported but doesn't make the app unready.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub worker_heartbeat_max_age_secs: i64,
+    /// How long the email backend check's result is reused; the SES check
+    /// is an API call.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub email_backend_cache_secs: u64,
+}
+
+impl Default for HealthSettings {
+    fn default() -> Self {
+        Self {
+            check_timeout_ms: 2000,
+            worker_heartbeat_max_age_secs: 60,
+            email_backend_cache_secs: 30,
+        }
+    }
+}
+
+impl HealthSettings {
+    pub fn check_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.check_timeout_ms)
+    }
+
+    pub fn worker_heartbeat_max_age(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.worker_heartbeat_max_age_secs)
+    }
+
+    pub fn email_backend_cache_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.email_backend_cache_secs)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WorkerSettings {
+    /// How often the worker reports its heartbeat and looks for work.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub poll_interval_secs: u64,
+    /// Deliveries `Pending` for longer than this were abandoned by a sender
+    /// that died, and their issue is resumed.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub stale_after_secs: i64,
+}
+
+impl Default for WorkerSettings {
+    fn default() -> Self {
+        Self {
+            poll_interval_secs: 30,
+            stale_after_secs: 600,
+        }
+    }
+}
+
+impl WorkerSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_secs)
+    }
+
+    pub fn stale_after(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.stale_after_secs)
+    }
+}
+
+/// How subscriber emails and names appear in logs and spans.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PiiMode {
+    /// A short SHA-256 prefix: lines about one subscriber can still be
+    /// correlated.
+    Hash,
+    /// First character and email domain only, e.g. `l***@gmail.com`.
+    #[default]
+    Mask,
+    /// `[REDACTED]`.
+    Redact,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LoggingSettings {
+    pub pii: PiiMode,
+}
+
+impl Settings {
+    /// Every credential in the settings, by JSON pointer into their
+    /// serialized form, where they appear redacted.
+    pub fn secrets(&self) -> [(&'static str, &Secret<String>); 2] {
+        [
+            ("/database/password", &self.database.password),
+            ("/tracking/secret", &self.tracking.secret),
+        ]
+    }
+}
+
+impl ApplicationSettings {
+    /// `base_url` without a trailing slash, ready to have paths appended.
+    pub fn base_url(&self) -> String {
+        self.base_url.trim_end_matches('/').to_string()
+    }
+
+    pub fn get_address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    pub fn confirmation_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.confirmation_token_ttl_hours)
+    }
+
+    pub fn drain_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.drain_timeout_secs)
+    }
+}
+
+impl DatabaseSettings {
+    pub fn without_db(&self) -> PgConnectOptions {
+        let ssl = if self.require_ssl {
+            PgSslMode::Require
+        } else {
+            PgSslMode::Prefer
+        };
+
+        PgConnectOptions::new()
+            .host(&self.host)
+            .username(&self.username)
+            .password(self.password.expose())
+            .port(self.port)
+            .ssl_mode(ssl)
+            .application_name(&self.application_name)
+    }
+
+    pub fn with_db(&self) -> PgConnectOptions {
+        let mut opts = self
+            .without_db()
+            .database(&self.database_name)
+            .log_statements(self.log_statements.into())
+            .log_slow_statements(
+                LevelFilter::Warn,
+                std::time::Duration::from_millis(self.slow_statement_threshold_ms),
+            );
+        if let Some(timeout) = self.statement_timeout_ms {
+            opts = opts.options([("statement_timeout", format!("{}ms", timeout))]);
+        }
+        opts
+    }
+}
+
+/// A pool tuned by `config.pool` that connects on first use.
+pub fn get_connection_pool(config: &DatabaseSettings) -> PgPool {
+    config.pool.options().connect_lazy_with(config.with_db())
+}
+
+/// Every migration in `migrations/`, embedded at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
+    let mut connection = PgConnection::connect_with(&config.without_db())
+        .await
+        .expect("Failed to connect to Postgres");
+
+    connection
+        .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
+        .await
+        .expect("Failed to create database.");
+
+    let connection_pool = get_connection_pool(config);
+    MIGRATOR
+        .run(&connection_pool)
+        .await
+        .expect("Failed to migrate the database");
+
+    connection_pool
+}
@@ -0,0 +1,207 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::configuration::DeliverabilitySettings;
+
+mod resolver;
+mod suggestions;
+
+pub use resolver::{DomainResolver, FakeResolver, HickoryResolver, Lookup, ResolveError};
+pub use suggestions::suggest_domain;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomainStatus {
+    Deliverable,
+    Undeliverable {
+        suggestion: Option<String>,
+    },
+    /// The resolver failed for reasons unrelated to the domain (timeouts, SERVFAIL).
+    Unknown,
+}
+
+#[derive(Debug)]
+struct CachedStatus {
+    status: DomainStatus,
+    expires_at: Instant,
+}
+
+/// Checks whether an email domain can receive mail, caching answers by their DNS TTL.
+#[derive(Debug, Clone)]
+pub struct DomainChecker {
+    resolver: Arc<dyn DomainResolver>,
+    cache: Arc<Mutex<HashMap<String, CachedStatus>>>,
+    enabled: bool,
+    max_ttl: Duration,
+    negative_ttl: Duration,
+}
+
+impl DomainChecker {
+    pub fn new(resolver: Arc<dyn DomainResolver>, settings: &DeliverabilitySettings) -> Self {
+        Self {
+            resolver,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            enabled: settings.enabled,
+            max_ttl: Duration::from_secs(settings.max_cache_ttl_secs),
+            negative_ttl: Duration::from_secs(settings.negative_cache_ttl_secs),
+        }
+    }
+
+    #[tracing::instrument(name = "Checking email domain", skip(self))]
+    pub async fn check(&self, domain: &str) -> DomainStatus {
+        if !self.enabled {
+            return DomainStatus::Deliverable;
+        }
+
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        if let Some(status) = self.cached(&domain) {
+            return status;
+        }
+
+        let (status, ttl) = self.resolve(&domain).await;
+        if let Some(ttl) = ttl {
+            self.cache.lock().unwrap().insert(
+                domain,
+                CachedStatus {
+                    status: status.clone(),
+                    expires_at: Instant::now() + ttl.min(self.max_ttl),
+                },
+            );
+        }
+        status
+    }
+
+    fn cached(&self, domain: &str) -> Option<DomainStatus> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(domain) {
+            Some(entry) if entry.expires_at > Instant::now() => Some(entry.status.clone()),
+            Some(_) => {
+                cache.remove(domain);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// MX first, then A/AAAA as the implicit MX (RFC 5321 section 5.1).
+    /// Returns the status and how long it may be cached, `None` meaning not at all.
+    async fn resolve(&self, domain: &str) -> (DomainStatus, Option<Duration>) {
+        match self.resolver.lookup_mx(domain).await {
+            Ok(lookup) if is_null_mx(&lookup) => {
+                return (self.undeliverable(domain), Some(lookup.ttl));
+            }
+            Ok(lookup) if !lookup.records.is_empty() => {
+                return (DomainStatus::Deliverable, Some(lookup.ttl));
+            }
+            Ok(_) | Err(ResolveError::NotFound { .. }) => {}
+            Err(ResolveError::Failed(e)) => {
+                tracing::warn!("MX lookup for `{}` failed: {}", domain, e);
+                return (DomainStatus::Unknown, None);
+            }
+        }
+
+        match self.resolver.lookup_ip(domain).await {
+            Ok(lookup) if !lookup.records.is_empty() => {
+                (DomainStatus::Deliverable, Some(lookup.ttl))
+            }
+            Ok(_) => (self.undeliverable(domain), Some(self.negative_ttl)),
+            Err(ResolveError::NotFound { negative_ttl }) => (
+                self.undeliverable(domain),
+                Some(negative_ttl.unwrap_or(self.negative_ttl)),
+            ),
+            Err(ResolveError::Failed(e)) => {
+                tracing::warn!("A/AAAA lookup for `{}` failed: {}", domain, e);
+                (DomainStatus::Unknown, None)
+            }
+        }
+    }
+
+    fn undeliverable(&self, domain: &str) -> DomainStatus {
+        DomainStatus::Undeliverable {
+            suggestion: suggest_domain(domain).map(str::to_string),
+        }
+    }
+}
+
+/// RFC 7505: a single MX record with an empty exchange means "accepts no mail".
+fn is_null_mx(lookup: &Lookup) -> bool {
+    lookup.records.len() == 1 && lookup.records[0] == "."
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{DomainChecker, DomainStatus, FakeResolver};
+    use crate::configuration::DeliverabilitySettings;
+
+    fn checker(resolver: &FakeResolver) -> DomainChecker {
+        DomainChecker::new(
+            Arc::new(resolver.clone()),
+            &DeliverabilitySettings::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn domain_with_mx_is_deliverable() {
+        let resolver = FakeResolver::new().with_mx("gmail.com", Duration::from_secs(60));
+        let status = checker(&resolver).check("gmail.com").await;
+        assert_eq!(status, DomainStatus::Deliverable);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_address_records() {
+        let resolver = FakeResolver::new().with_address("example.com", Duration::from_secs(60));
+        let status = checker(&resolver).check("example.com").await;
+        assert_eq!(status, DomainStatus::Deliverable);
+        assert_eq!(resolver.lookups(), 2);
+    }
+
+    #[tokio::test]
+    async fn null_mx_is_undeliverable() {
+        let resolver = FakeResolver::new().with_null_mx("example.com");
+        let status = checker(&resolver).check("example.com").await;
+        assert_eq!(status, DomainStatus::Undeliverable { suggestion: None });
+    }
+
+    #[tokio::test]
+    async fn unknown_domain_is_undeliverable_with_suggestion() {
+        let resolver = FakeResolver::new();
+        let status = checker(&resolver).check("gmial.com").await;
+        assert_eq!(
+            status,
+            DomainStatus::Undeliverable {
+                suggestion: Some("gmail.com".to_string())
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn resolver_failure_is_unknown_and_not_cached() {
+        let resolver = FakeResolver::new().with_failure("example.com");
+        let checker = checker(&resolver);
+        assert_eq!(checker.check("example.com").await, DomainStatus::Unknown);
+        assert_eq!(checker.check("example.com").await, DomainStatus::Unknown);
+        assert_eq!(resolver.lookups(), 2);
+    }
+
+    #[tokio::test]
+    async fn answers_are_cached_until_their_ttl_expires() {
+        let resolver = FakeResolver::new()
+            .with_mx("gmail.com", Duration::from_secs(60))
+            .with_mx("short.example", Duration::ZERO);
+        let checker = checker(&resolver);
+
+        checker.check("gmail.com").await;
+        checker.check("GMAIL.com").await;
+        assert_eq!(resolver.lookups(), 1);
+
+        checker.check("short.example").await;
+        checker.check("short.example").await;
+        assert_eq!(resolver.lookups(), 3);
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use hickory_resolver::{
+    config::{ResolverConfig, ResolverOpts},
+    error::{ResolveError as HickoryError, ResolveErrorKind},
+    TokioAsyncResolver,
+};
+use thiserror::Error;
+
+/// Records returned for a single lookup together with how long they may be cached.
+#[derive(Debug, Clone)]
+pub struct Lookup {
+    pub records: Vec<String>,
+    pub ttl: Duration,
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum ResolveError {
+    #[error("no records found")]
+    NotFound { negative_ttl: Option<Duration> },
+    #[error("lookup failed: {0}")]
+    Failed(String),
+}
+
+/// Source of DNS answers for the deliverability check.
+///
+/// `lookup_mx` returns exchange host names (a null MX is returned as `"."`),
+/// `lookup_ip` returns the domain's A/AAAA addresses.
+#[async_trait]
+pub trait DomainResolver: Send + Sync + std::fmt::Debug {
+    async fn lookup_mx(&self, domain: &str) -> Result<Lookup, ResolveError>;
+    async fn lookup_ip(&self, domain: &str) -> Result<Lookup, ResolveError>;
+}
+
+#[derive(Debug, Clone)]
+pub struct HickoryResolver(TokioAsyncResolver);
+
+impl HickoryResolver {
+    pub fn from_system_conf() -> Self {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+            tracing::warn!(
+                "Failed to read system resolver config, using defaults: {:?}",
+                e
+            );
+            TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+        });
+        Self(resolver)
+    }
+}
+
+fn fqdn(domain: &str) -> String {
+    format!("{}.", domain.trim_end_matches('.'))
+}
+
+fn ttl_until(valid_until: std::time::Instant) -> Duration {
+    valid_until.saturating_duration_since(std::time::Instant::now())
+}
+
+impl From<HickoryError> for ResolveError {
+    fn from(e: HickoryError) -> Self {
+        match e.kind() {
+            ResolveErrorKind::NoRecordsFound { negative_ttl, .. } => ResolveError::NotFound {
+                negative_ttl: negative_ttl.map(|ttl| Duration::from_secs(ttl.into())),
+            },
+            _ => ResolveError::Failed(e.to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl DomainResolver for HickoryResolver {
+    async fn lookup_mx(&self, domain: &str) -> Result<Lookup, ResolveError> {
+        let lookup = self.0.mx_lookup(fqdn(domain)).await?;
+        Ok(Lookup {
+            records: lookup.iter().map(|mx| mx.exchange().to_utf8()).collect(),
+            ttl: ttl_until(lookup.valid_until()),
+        })
+    }
+
+    async fn lookup_ip(&self, domain: &str) -> Result<Lookup, ResolveError> {
+        let lookup = self.0.lookup_ip(fqdn(domain)).await?;
+        Ok(Lookup {
+            records: lookup.iter().map(|ip| ip.to_string()).collect(),
+            ttl: ttl_until(lookup.valid_until()),
+        })
+    }
+}
+
+/// In-memory resolver for tests; unknown domains resolve to `NotFound`.
+#[derive(Debug, Clone, Default)]
+pub struct FakeResolver {
+    mx: HashMap<String, Lookup>,
+    ip: HashMap<String, Lookup>,
+    failing: Vec<String>,
+    lookups: Arc<AtomicUsize>,
+}
+
+impl FakeResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_mx(mut self, domain: &str, ttl: Duration) -> Self {
+        self.mx.insert(
+            domain.to_string(),
+            Lookup {
+                records: vec![format!("mx.{}.", domain)],
+                ttl,
+            },
+        );
+        self
+    }
+
+    pub fn with_null_mx(mut self, domain: &str) -> Self {
+        self.mx.insert(
+            domain.to_string(),
+            Lookup {
+                records: vec![".".to_string()],
+                ttl: Duration::from_secs(3600),
+            },
+        );
+        self
+    }
+
+    pub fn with_address(mut self, domain: &str, ttl: Duration) -> Self {
+        self.ip.insert(
+            domain.to_string(),
+            Lookup {
+                records: vec!["192.0.2.1".to_string()],
+                ttl,
+            },
+        );
+        self
+    }
+
+    pub fn with_failure(mut self, domain: &str) -> Self {
+        self.failing.push(domain.to_string());
+        self
+    }
+
+    /// Number of lookups (MX and A/AAAA) served so far.
+    pub fn lookups(&self) -> usize {
+        self.lookups.load(Ordering::SeqCst)
+    }
+
+    fn answer(
+        &self,
+        records: &HashMap<String, Lookup>,
+        domain: &str,
+    ) -> Result<Lookup, ResolveError> {
+        self.lookups.fetch_add(1, Ordering::SeqCst);
+        if self.failing.iter().any(|d| d == domain) {
+            return Err(ResolveError::Failed("SERVFAIL".to_string()));
+        }
+        records
+            .get(domain)
+            .cloned()
+            .ok_or(ResolveError::NotFound { negative_ttl: None })
+    }
+}
+
+#[async_trait]
+impl DomainResolver for FakeResolver {
+    async fn lookup_mx(&self, domain: &str) -> Result<Lookup, ResolveError> {
+        self.answer(&self.mx, domain)
+    }
+
+    async fn lookup_ip(&self, domain: &str) -> Result<Lookup, ResolveError> {
+        self.answer(&self.ip, domain)
+    }
+}
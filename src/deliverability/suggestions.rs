@@ -0,0 +1,90 @@
+const COMMON_DOMAINS: &[&str] = &[
+    "gmail.com",
+    "googlemail.com",
+    "yahoo.com",
+    "hotmail.com",
+    "outlook.com",
+    "live.com",
+    "icloud.com",
+    "me.com",
+    "aol.com",
+    "proton.me",
+    "protonmail.com",
+    "gmx.com",
+    "wp.pl",
+    "o2.pl",
+    "onet.pl",
+    "interia.pl",
+];
+
+const MAX_DISTANCE: usize = 2;
+
+/// Closest well-known mail domain to `domain`, if it looks like a typo of one.
+pub fn suggest_domain(domain: &str) -> Option<&'static str> {
+    let domain = domain.to_lowercase();
+    if COMMON_DOMAINS.contains(&domain.as_str()) {
+        return None;
+    }
+
+    COMMON_DOMAINS
+        .iter()
+        .map(|candidate| (*candidate, edit_distance(&domain, candidate)))
+        .filter(|(_, distance)| *distance <= MAX_DISTANCE)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(candidate, _)| candidate)
+}
+
+/// Optimal string alignment distance: Levenshtein plus adjacent transpositions,
+/// so `gmial.com` is one edit away from `gmail.com`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::suggest_domain;
+    use claim::{assert_none, assert_some_eq};
+
+    #[test]
+    fn transposed_letters_are_suggested() {
+        assert_some_eq!(suggest_domain("gmial.com"), "gmail.com");
+    }
+
+    #[test]
+    fn missing_letter_is_suggested() {
+        assert_some_eq!(suggest_domain("hotmai.com"), "hotmail.com");
+    }
+
+    #[test]
+    fn known_domain_has_no_suggestion() {
+        assert_none!(suggest_domain("gmail.com"));
+    }
+
+    #[test]
+    fn unrelated_domain_has_no_suggestion() {
+        assert_none!(suggest_domain("example.org"));
+    }
+}
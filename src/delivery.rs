@@ -0,0 +1,479 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    configuration::DeliveryMode,
+    domain::{SubscriberEmail, SubscriberName},
+    email_client::{render_newsletter, EmailBody, EmailClientError, OutgoingEmail, MAX_BATCH_SIZE},
+    idempotency::persistance::{
+        claim_delivery, generate_idempotency_key, record_delivery_failure, update_job_status,
+        EmailStatus,
+    },
+    redact::Pii,
+    startup::AppState,
+    templates::{
+        merge::{custom_field_schema, IssueTemplate, MergeFields, MergeLinks},
+        TemplateError,
+    },
+};
+
+#[derive(Debug, Error)]
+pub enum DeliveryError {
+    #[error("the issue is invalid")]
+    InvalidIssue(#[source] TemplateError),
+    #[error("unexpected error: `{0}`")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[derive(Debug)]
+struct ConfirmedSubscriber {
+    email: SubscriberEmail,
+    name: SubscriberName,
+    id: Uuid,
+    subscribed_at: DateTime<Utc>,
+    unsubscribe_token: String,
+    custom_fields: Value,
+    tracking_opt_out: bool,
+}
+
+impl ConfirmedSubscriber {
+    fn merge_fields(&self, schema: &[String], links: &MergeLinks) -> MergeFields {
+        MergeFields::new(
+            self.name.as_ref(),
+            self.email.as_ref(),
+            self.subscribed_at,
+            &self.custom_fields,
+            &self.unsubscribe_token,
+            schema,
+            links,
+        )
+    }
+}
+
+/// Everyone who was confirmed when the issue was published, so resuming an
+/// old issue doesn't reach people who confirmed since.
+#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
+async fn get_confirmed_subscribers(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
+    // Rows are decoded one by one, so a single invalid stored email or name
+    // only fails that row instead of the whole query.
+    let rows = sqlx::query_as!(
+        ConfirmedSubscriber,
+        r#"
+        SELECT email as "email: SubscriberEmail", name as "name: SubscriberName", id,
+            subscribed_at, unsubscribe_token, custom_fields, tracking_opt_out
+        FROM subscriptions
+        WHERE status = 'confirmed'
+            AND confirmed_at <= (SELECT created_at FROM newsletter_issues WHERE id = $1)
+        ORDER BY id
+        "#,
+        issue_id
+    )
+    .fetch(pool)
+    .map(|row| row.context("Failed to decode confirmed subscriber"))
+    .collect::<Vec<Result<ConfirmedSubscriber, anyhow::Error>>>()
+    .await;
+
+    Ok(rows)
+}
+
+/// The issue a publish request refers to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum StoredIssue {
+    /// Created by this request.
+    New(Uuid),
+    /// Created by an earlier request with the same idempotency key and
+    /// content.
+    Existing(Uuid),
+    /// The idempotency key was already used for a different issue.
+    KeyReused,
+}
+
+/// Stores `issue`, or finds the one an earlier request with the same
+/// `idempotency_key` stored.
+#[tracing::instrument(name = "Store newsletter issue", skip(pool, issue))]
+pub async fn create_issue(
+    pool: &PgPool,
+    issue: &EmailBody,
+    idempotency_key: Option<&str>,
+) -> Result<StoredIssue, sqlx::Error> {
+    let inserted = sqlx::query_scalar!(
+        r#"
+        INSERT INTO newsletter_issues (id, title, message, list, idempotency_key)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (idempotency_key) DO NOTHING
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        issue.title,
+        issue.message,
+        issue.list,
+        idempotency_key
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(id) = inserted {
+        return Ok(StoredIssue::New(id));
+    }
+
+    let existing = sqlx::query!(
+        r#"SELECT id, title, message, list FROM newsletter_issues WHERE idempotency_key = $1"#,
+        idempotency_key
+    )
+    .fetch_one(pool)
+    .await?;
+    let existing_body = EmailBody {
+        title: existing.title,
+        message: existing.message,
+        list: existing.list,
+    };
+    if existing_body == *issue {
+        Ok(StoredIssue::Existing(existing.id))
+    } else {
+        Ok(StoredIssue::KeyReused)
+    }
+}
+
+#[tracing::instrument(name = "Load newsletter issue", skip(pool))]
+pub async fn load_issue(pool: &PgPool, issue_id: Uuid) -> Result<Option<EmailBody>, sqlx::Error> {
+    sqlx::query_as!(
+        EmailBody,
+        r#"SELECT title, message, list FROM newsletter_issues WHERE id = $1"#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// An issue checked against the current merge field schema, ready to send.
+#[derive(Debug)]
+pub struct PreparedIssue {
+    pub body: EmailBody,
+    template: IssueTemplate,
+    schema: Vec<String>,
+}
+
+impl PreparedIssue {
+    pub async fn prepare(
+        pool: &PgPool,
+        body: EmailBody,
+        links: &MergeLinks,
+    ) -> Result<Self, DeliveryError> {
+        let schema = custom_field_schema(pool)
+            .await
+            .context("Failed to get the custom field schema")?;
+        let template = IssueTemplate::compile(
+            &body.title,
+            &body.message,
+            &MergeFields::sample(&schema, links),
+        )
+        .map_err(DeliveryError::InvalidIssue)?;
+        Ok(Self {
+            body,
+            template,
+            schema,
+        })
+    }
+}
+
+/// What one delivery run did; `GET /issues/{issue_id}/report` has the full
+/// picture from the delivery records.
+#[derive(Debug, Serialize)]
+pub struct DeliverySummary {
+    pub issue_id: Uuid,
+    pub sent: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    /// The app started shutting down before every subscriber was reached;
+    /// resume the issue to deliver the rest.
+    pub interrupted: bool,
+}
+
+enum Outcome {
+    Sent,
+    Failed,
+    Skipped,
+}
+
+/// Per-send options, given as query parameters on `/publish` and
+/// `/issues/{issue_id}/resume`.
+#[derive(Debug, Default, Deserialize)]
+pub struct SendOptions {
+    /// Overrides `delivery.mode`.
+    pub mode: Option<DeliveryMode>,
+}
+
+/// A delivery that was claimed and rendered, ready to go to the backend.
+struct Claimed {
+    subscriber_id: Uuid,
+    key: String,
+    email: OutgoingEmail,
+}
+
+/// Sends `issue` to every subscriber confirmed before it was published who
+/// hasn't received it yet.
+///
+/// Deliveries are claimed and rendered up to `delivery.concurrency` at a time,
+/// then sent one per backend call or, in bulk mode, in batches of up to
+/// [`MAX_BATCH_SIZE`], paced by the email client's rate limiter. Each
+/// subscriber gets one attempt per run (not counting retries of throttled
+/// sends) and a failure, including a database error, is recorded against
+/// that subscriber without stopping the run. Running it again for the same
+/// issue resumes an interrupted send: `Sent` deliveries are skipped, `Failed`
+/// ones are retried, and so are `Pending` ones once they're
+/// `worker.stale_after_secs` old; younger ones are still being sent by
+/// another run.
+///
+/// Once the app starts shutting down no further deliveries are claimed; the
+/// ones already claimed are still sent and recorded, so nothing is left
+/// `Pending`.
+#[tracing::instrument(name = "Deliver newsletter issue", skip(state, issue))]
+pub async fn deliver_issue(
+    state: &AppState,
+    issue_id: Uuid,
+    issue: &PreparedIssue,
+    mode: DeliveryMode,
+) -> Result<DeliverySummary, DeliveryError> {
+    let recipients = get_confirmed_subscribers(&state.pool, issue_id)
+        .await
+        .context("Failed to get confirmed subscribers")?;
+    let concurrency = state.delivery.concurrency.max(1);
+    let batch_size = match mode {
+        DeliveryMode::Individual => 1,
+        DeliveryMode::Bulk => MAX_BATCH_SIZE,
+    };
+
+    let total = recipients.len();
+
+    let outcomes = futures::stream::iter(recipients)
+        .take_while(|_| futures::future::ready(!state.shutdown.is_cancelled()))
+        .map(|recipient| async move {
+            match recipient {
+                Ok(subscriber) => claim_and_render(state, issue_id, issue, &subscriber).await,
+                Err(e) => {
+                    tracing::warn!(
+                        "Skipping subscriber, their stored details are invalid: {:?}",
+                        e
+                    );
+                    Err(Outcome::Skipped)
+                }
+            }
+        })
+        .buffer_unordered(concurrency)
+        .chunks(batch_size)
+        .map(|chunk| send_chunk(state, mode, chunk))
+        .buffer_unordered(concurrency)
+        .collect::<Vec<Vec<Outcome>>>()
+        .await;
+
+    let mut summary = DeliverySummary {
+        issue_id,
+        sent: 0,
+        failed: 0,
+        skipped: 0,
+        interrupted: false,
+    };
+    for outcome in outcomes.into_iter().flatten() {
+        match outcome {
+            Outcome::Sent => summary.sent += 1,
+            Outcome::Failed => summary.failed += 1,
+            Outcome::Skipped => summary.skipped += 1,
+        }
+    }
+    let reached = summary.sent + summary.failed + summary.skipped;
+    if reached < total {
+        tracing::warn!(
+            "Shutting down: stopped after {} of {} subscribers, resume the issue to deliver the rest",
+            reached,
+            total
+        );
+        summary.interrupted = true;
+    }
+    Ok(summary)
+}
+
+/// Runs [`deliver_issue`] as a task of its own, so the send doesn't depend on
+/// the request that started it staying connected. The app waits for it on
+/// shutdown, before closing the pool; progress is on
+/// `GET /issues/{issue_id}/report`.
+pub fn spawn_delivery(state: &AppState, issue_id: Uuid, issue: PreparedIssue, mode: DeliveryMode) {
+    let state = state.clone();
+    let deliveries = state.deliveries.clone();
+    deliveries.spawn(
+        async move {
+            match deliver_issue(&state, issue_id, &issue, mode).await {
+                Ok(summary) => tracing::info!(
+                    "Delivered issue {}: {} sent, {} failed, {} skipped, interrupted: {}",
+                    issue_id,
+                    summary.sent,
+                    summary.failed,
+                    summary.skipped,
+                    summary.interrupted
+                ),
+                Err(e) => tracing::error!("Failed to deliver issue {}: {:?}", issue_id, e),
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+/// Claims the delivery to `subscriber` and renders their email, or says why
+/// there's nothing to send.
+#[tracing::instrument(
+    name = "Prepare delivery to subscriber",
+    skip(state, issue, subscriber),
+    fields(subscriber_id = %subscriber.id)
+)]
+async fn claim_and_render(
+    state: &AppState,
+    issue_id: Uuid,
+    issue: &PreparedIssue,
+    subscriber: &ConfirmedSubscriber,
+) -> Result<Claimed, Outcome> {
+    let key = generate_idempotency_key(issue_id, subscriber.id);
+    let lease = state.worker.stale_after();
+    match claim_delivery(&state.pool, subscriber.id, &key, issue_id, lease).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            tracing::info!("Skipping subscriber, email already sent or being sent");
+            return Err(Outcome::Skipped);
+        }
+        Err(e) => {
+            tracing::error!("Failed to claim the delivery: {:?}", e);
+            return Err(Outcome::Failed);
+        }
+    }
+
+    let email = render_newsletter(
+        &state.templates,
+        issue.body.list.as_deref(),
+        &issue.template,
+        &subscriber.merge_fields(&issue.schema, &state.merge_links()),
+        state
+            .tracker
+            .as_ref()
+            .filter(|_| !subscriber.tracking_opt_out)
+            .map(|tracker| tracker.for_recipient(issue_id, subscriber.id))
+            .as_ref(),
+    );
+    match email {
+        Ok(email) => Ok(Claimed {
+            subscriber_id: subscriber.id,
+            key,
+            email: OutgoingEmail {
+                recipient: subscriber.email.clone(),
+                subject: email.subject,
+                html: email.html,
+                text: email.text,
+            },
+        }),
+        Err(e) => Err(record_result(
+            state,
+            subscriber.id,
+            &key,
+            &subscriber.email,
+            Err(EmailClientError::from(e)),
+        )
+        .await),
+    }
+}
+
+/// Sends the claimed deliveries in `chunk`, one at a time or as one batch.
+async fn send_chunk(
+    state: &AppState,
+    mode: DeliveryMode,
+    chunk: Vec<Result<Claimed, Outcome>>,
+) -> Vec<Outcome> {
+    let mut outcomes = vec![];
+    let mut claimed = vec![];
+    for item in chunk {
+        match item {
+            Ok(delivery) => claimed.push(delivery),
+            Err(outcome) => outcomes.push(outcome),
+        }
+    }
+    if claimed.is_empty() {
+        return outcomes;
+    }
+
+    let results = match mode {
+        DeliveryMode::Individual => {
+            let mut results = vec![];
+            for delivery in &claimed {
+                results.push(state.email_client.send(&delivery.email).await);
+            }
+            results
+        }
+        DeliveryMode::Bulk => {
+            let emails: Vec<OutgoingEmail> = claimed.iter().map(|d| d.email.clone()).collect();
+            state.email_client.send_batch(&emails).await
+        }
+    };
+    for (delivery, result) in claimed.iter().zip(results) {
+        let result = result.map_err(EmailClientError::from);
+        outcomes.push(
+            record_result(
+                state,
+                delivery.subscriber_id,
+                &delivery.key,
+                &delivery.email.recipient,
+                result,
+            )
+            .await,
+        );
+    }
+    outcomes
+}
+
+/// Marks the delivery `Sent`, or records why it failed.
+async fn record_result(
+    state: &AppState,
+    subscriber_id: Uuid,
+    key: &str,
+    recipient: &SubscriberEmail,
+    result: Result<(), EmailClientError>,
+) -> Outcome {
+    match result {
+        Ok(()) => {
+            if let Err(e) =
+                update_job_status(&state.pool, subscriber_id, key, EmailStatus::Sent).await
+            {
+                // The email is out, so it must not look `Failed` and be
+                // retried; left `Pending`, it's only claimed again once the
+                // lease runs out.
+                tracing::error!(
+                    "Sent email to {} but failed to record it: {:?}",
+                    Pii(recipient),
+                    e
+                );
+            }
+            Outcome::Sent
+        }
+        Err(e) => {
+            let class = e.class();
+            let error = anyhow::Error::from(e);
+            tracing::error!("Failed to send email to {}: {:?}", Pii(recipient), error);
+            if let Err(update_err) = record_delivery_failure(
+                &state.pool,
+                subscriber_id,
+                key,
+                &class,
+                &format!("{:#}", error),
+            )
+            .await
+            {
+                tracing::error!("Failed to update job status: {:?}", update_err);
+            }
+            Outcome::Failed
+        }
+    }
+}
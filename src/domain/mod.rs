@@ -1,5 +1,3 @@
-use axum::response::{IntoResponse, Response};
-use hyper::StatusCode;
 use thiserror::Error;
 
 mod new_subscriber;
@@ -18,14 +16,21 @@ pub enum ParseError {
     Empty,
     #[error("Forbidden character")]
     ForbiddenChar,
-    #[error("Bad Name")]
+    #[error("Name must be 1 to 256 characters long and must not contain / ( ) \" < > \\ {{ }}")]
     BadName,
-    #[error("Bad Email")]
+    #[error("Not a valid email address")]
     BadEmail,
 }
 
-impl From<ParseError> for Response {
-    fn from(e: ParseError) -> Self {
-        (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+impl ParseError {
+    /// Name of the input field the error refers to.
+    pub fn field(&self) -> &'static str {
+        match self {
+            ParseError::BadEmail => "email",
+            ParseError::TooLong
+            | ParseError::Empty
+            | ParseError::ForbiddenChar
+            | ParseError::BadName => "name",
+        }
     }
 }
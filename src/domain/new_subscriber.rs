@@ -1,58 +1,69 @@
-use anyhow::Context;
+use std::collections::BTreeMap;
+
 use chrono::Utc;
-use sqlx::{PgPool, Postgres, Transaction};
+use sqlx::{types::Json, Postgres, Transaction};
 use tracing::Instrument;
 use uuid::Uuid;
 
-use crate::routes::subscriptions::SubscribeError;
-
 use super::{ParseError, SubscriberEmail, SubscriberName};
+use crate::redact::Pii;
 
-#[derive(Debug)]
-pub struct Subscriber<'a> {
-    name: SubscriberName,
-    pub email: SubscriberEmail<'a>,
+#[derive(Debug, Clone)]
+pub struct Subscriber {
+    pub name: SubscriberName,
+    pub email: SubscriberEmail,
+    pub custom_fields: BTreeMap<String, String>,
 }
 
-impl<'a> Subscriber<'a> {
-    pub fn new(name: &str, email: &'a str) -> Result<Subscriber<'a>, ParseError> {
+impl Subscriber {
+    pub fn new(name: &str, email: &str) -> Result<Subscriber, ParseError> {
         let name = SubscriberName::parse(name)?;
         let email = SubscriberEmail::parse(email)?;
 
-        Ok(Subscriber { name, email })
+        Ok(Subscriber {
+            name,
+            email,
+            custom_fields: BTreeMap::new(),
+        })
     }
 
+    /// Inserts the subscriber, returning `None` if the email is already subscribed.
     #[tracing::instrument(
     name = "Inserting a new subscriber",
-    skip(transaction),
+    skip(transaction, unsubscribe_token),
     fields(
-    subscriber_email = %self.email,
-    subscriber_name = %self.name
+    subscriber_email = %Pii(&self.email),
+    subscriber_name = %Pii(&self.name)
 ))]
     pub async fn try_insert(
         &self,
+        unsubscribe_token: &str,
         transaction: &mut Transaction<'_, Postgres>,
-    ) -> Result<Uuid, sqlx::Error> {
+    ) -> Result<Option<Uuid>, sqlx::Error> {
         let query_span = tracing::info_span!("Saving new subscriber details in the database");
         let uid = Uuid::new_v4();
-        let _query = sqlx::query!(
+        let inserted = sqlx::query!(
             r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-    VALUES ($1, $2, $3, $4, $5)
+    INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token, custom_fields)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+    ON CONFLICT (email) DO NOTHING
+    RETURNING id
     "#,
             uid,
             self.email.as_ref(),
             self.name.as_ref(),
             Utc::now(),
-            "Pending"
+            "Pending",
+            unsubscribe_token,
+            Json(&self.custom_fields) as _
         )
-        .execute(&mut **transaction)
+        .fetch_optional(&mut **transaction)
         .instrument(query_span)
         .await
         .map_err(|e| {
             tracing::error!("Failed to execute query: {:?}", e);
             e
         })?;
-        Ok(uid)
+        Ok(inserted.map(|r| r.id))
     }
 }
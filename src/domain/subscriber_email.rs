@@ -1,33 +1,99 @@
 use super::ParseError;
+use crate::redact::Pii;
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use sqlx::{
+    encode::IsNull,
+    error::BoxDynError,
+    postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef},
+    Decode, Encode, Postgres, Type,
+};
+use std::{fmt, str::FromStr};
 use validator::ValidateEmail;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SubscriberEmail<'a>(&'a str);
+/// `Debug` goes through [`Pii`], so subscribers logged with `{:?}` don't leak
+/// their address.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SubscriberEmail(String);
 
-impl<'a> AsRef<str> for SubscriberEmail<'a> {
+impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
         &self.0
     }
 }
 
-impl<'a> fmt::Display for SubscriberEmail<'a> {
+impl fmt::Debug for SubscriberEmail {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SubscriberEmail({})", Pii(&self.0))
+    }
+}
+
+impl fmt::Display for SubscriberEmail {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
-impl<'a> SubscriberEmail<'a> {
+impl SubscriberEmail {
     pub fn parse(name: &str) -> Result<SubscriberEmail, ParseError> {
         if name.validate_email() {
-            tracing::info!("Successful email validation  for string `{}`", name);
-            Ok(SubscriberEmail(name))
+            tracing::info!("Successful email validation for `{}`", Pii(name));
+            Ok(SubscriberEmail(name.to_string()))
         } else {
-            tracing::error!("Email validation failed for string `{}`", name);
-            return Err(ParseError::BadEmail);
+            tracing::error!("Email validation failed for `{}`", Pii(name));
+            Err(ParseError::BadEmail)
         }
     }
+
+    /// The part after the `@`, i.e. the domain mail is delivered to.
+    pub fn domain(&self) -> &str {
+        self.0.rsplit_once('@').map_or("", |(_, domain)| domain)
+    }
+}
+
+impl FromStr for SubscriberEmail {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<String> for SubscriberEmail {
+    type Error = ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl From<SubscriberEmail> for String {
+    fn from(email: SubscriberEmail) -> Self {
+        email.0
+    }
+}
+
+impl Type<Postgres> for SubscriberEmail {
+    fn type_info() -> PgTypeInfo {
+        <String as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for SubscriberEmail {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        let value = <&str as Decode<Postgres>>::decode(value)?;
+        Ok(Self::parse(value)?)
+    }
+}
+
+impl Encode<'_, Postgres> for SubscriberEmail {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        <&str as Encode<Postgres>>::encode(self.as_ref(), buf)
+    }
 }
 
 #[cfg(test)]
@@ -38,13 +104,13 @@ mod tests {
     #[test]
     fn valid_email_parsed_successfully() {
         let email = "luka_tim@gmail.com";
-        assert_ok!(SubscriberEmail::parse(&email));
+        assert_ok!(SubscriberEmail::parse(email));
     }
 
     #[test]
     fn invalid_email_rejected() {
         let email = "luka_timgmail.com";
-        assert_err!(SubscriberEmail::parse(&email));
+        assert_err!(SubscriberEmail::parse(email));
     }
 
     #[test]
@@ -58,4 +124,26 @@ mod tests {
         let email = " ";
         assert_err!(SubscriberEmail::parse(email));
     }
+
+    #[test]
+    fn deserializing_validates_the_email() {
+        assert_ok!(serde_json::from_str::<SubscriberEmail>(
+            r#""luka_tim@gmail.com""#
+        ));
+        assert_err!(serde_json::from_str::<SubscriberEmail>(
+            r#""luka_timgmail.com""#
+        ));
+    }
+
+    #[test]
+    fn domain_is_the_part_after_the_at_sign() {
+        let email: SubscriberEmail = "luka_tim@gmail.com".parse().unwrap();
+        assert_eq!(email.domain(), "gmail.com");
+    }
+
+    #[test]
+    fn debug_output_hides_the_address() {
+        let email: SubscriberEmail = "luka_tim@gmail.com".parse().unwrap();
+        assert!(!format!("{:?}", email).contains("luka_tim"));
+    }
 }
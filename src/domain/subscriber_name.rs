@@ -1,6 +1,20 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    encode::IsNull,
+    error::BoxDynError,
+    postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef},
+    Decode, Encode, Postgres, Type,
+};
 
 use super::ParseError;
+use crate::redact::Pii;
+
+/// `Debug` goes through [`Pii`], like [`super::SubscriberEmail`].
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SubscriberName(String);
 
 impl AsRef<str> for SubscriberName {
     fn as_ref(&self) -> &str {
@@ -8,15 +22,18 @@ impl AsRef<str> for SubscriberName {
     }
 }
 
+impl fmt::Debug for SubscriberName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SubscriberName({})", Pii(&self.0))
+    }
+}
+
 impl fmt::Display for SubscriberName {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct SubscriberName(String);
-
 impl SubscriberName {
     pub fn parse(name: &str) -> Result<SubscriberName, ParseError> {
         let is_too_long = name.len() > 256;
@@ -25,20 +42,61 @@ impl SubscriberName {
         let contais_forbidden_chars = name.chars().any(|c| forbidden_characters.contains(&c));
 
         let is_empty_or_whitespace = name.trim().is_empty();
-        if is_empty_or_whitespace
-            || is_too_long
-            || is_empty_or_whitespace
-            || contais_forbidden_chars
-        {
-            tracing::error!("Name validation failed for string `{}`", name);
+        if is_empty_or_whitespace || is_too_long || contais_forbidden_chars {
+            tracing::error!("Name validation failed for `{}`", Pii(name));
             return Err(ParseError::BadName);
         }
 
-        tracing::info!("Successfully parsed name: `{}`", name);
+        tracing::info!("Successfully parsed name: `{}`", Pii(name));
         Ok(SubscriberName(name.to_string()))
     }
 }
 
+impl FromStr for SubscriberName {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<String> for SubscriberName {
+    type Error = ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl From<SubscriberName> for String {
+    fn from(name: SubscriberName) -> Self {
+        name.0
+    }
+}
+
+impl Type<Postgres> for SubscriberName {
+    fn type_info() -> PgTypeInfo {
+        <String as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for SubscriberName {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        let value = <&str as Decode<Postgres>>::decode(value)?;
+        Ok(Self::parse(value)?)
+    }
+}
+
+impl Encode<'_, Postgres> for SubscriberName {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        <&str as Encode<Postgres>>::encode(self.as_ref(), buf)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::SubscriberName;
@@ -66,4 +124,10 @@ mod tests {
     fn valid_name_parsed_successfully() {
         assert_ok!(SubscriberName::parse("Luka Tim"));
     }
+
+    #[test]
+    fn deserializing_validates_the_name() {
+        assert_ok!(serde_json::from_str::<SubscriberName>(r#""Luka Tim""#));
+        assert_err!(serde_json::from_str::<SubscriberName>(r#""<Luka>""#));
+    }
 }
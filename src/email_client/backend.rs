@@ -0,0 +1,432 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use aws_sdk_ses::{
+    self as ses,
+    error::ProvideErrorMetadata,
+    types::{
+        Body, BulkEmailDestination, BulkEmailDestinationStatus, BulkEmailStatus, Content,
+        Destination, Message, Template,
+    },
+    Client,
+};
+use serde_json::json;
+use thiserror::Error;
+use tokio::time::Instant;
+
+use crate::{domain::SubscriberEmail, redact::Pii};
+
+/// A fully rendered email addressed to a single recipient.
+#[derive(Debug, Clone)]
+pub struct OutgoingEmail {
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// The account's sending limits, as reported by SES `GetSendQuota`.
+#[derive(Debug, Clone, Copy)]
+pub struct SendQuota {
+    /// Emails per second.
+    pub max_send_rate: f64,
+    pub max_24_hour_send: f64,
+    pub sent_last_24_hours: f64,
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum BackendError {
+    /// The provider rejected the send because we're over the send rate; it
+    /// can be retried once the rate drops.
+    #[error("sending rate exceeded: {0}")]
+    Throttled(String),
+    #[error("{code}: {message}")]
+    Service { code: String, message: String },
+    #[error("transport error: {0}")]
+    Transport(String),
+}
+
+impl BackendError {
+    /// A short, stable name for grouping failures.
+    pub fn class(&self) -> String {
+        match self {
+            BackendError::Throttled(_) => "Throttling".to_string(),
+            BackendError::Service { code, .. } => code.clone(),
+            BackendError::Transport(_) => "transport".to_string(),
+        }
+    }
+}
+
+impl From<ses::Error> for BackendError {
+    fn from(e: ses::Error) -> Self {
+        let message = e
+            .message()
+            .map(str::to_string)
+            .unwrap_or_else(|| e.to_string());
+        match e.code() {
+            // SES reports an exhausted daily quota as `Throttling` too, but
+            // slowing down won't help with that one.
+            Some("Throttling" | "ThrottlingException")
+                if !message.contains("Daily message quota") =>
+            {
+                BackendError::Throttled(message)
+            }
+            Some(code) => BackendError::Service {
+                code: code.to_string(),
+                message,
+            },
+            None => BackendError::Transport(message),
+        }
+    }
+}
+
+/// Most destinations SES accepts in one `SendBulkTemplatedEmail` call.
+pub const MAX_BATCH_SIZE: usize = 50;
+
+/// Result of each email in a batch, in the order they were given.
+pub type BatchResults = Vec<Result<(), BackendError>>;
+
+/// Where emails are actually handed off for delivery.
+#[async_trait]
+pub trait EmailBackend: Send + Sync + std::fmt::Debug {
+    /// Short name for logs and metrics, e.g. `"ses"`.
+    fn name(&self) -> &'static str;
+    async fn send(&self, email: &OutgoingEmail) -> Result<(), BackendError>;
+    async fn send_quota(&self) -> Result<SendQuota, BackendError>;
+
+    /// Cheaply checks that the backend is reachable and accepting our
+    /// credentials, without sending anything.
+    async fn check(&self) -> Result<(), BackendError> {
+        self.send_quota().await.map(|_| ())
+    }
+
+    /// Sends up to [`MAX_BATCH_SIZE`] emails at once. An `Err` means the whole
+    /// batch failed; otherwise each email has its own result.
+    ///
+    /// Backends without a bulk API send the emails one by one.
+    async fn send_batch(&self, emails: &[OutgoingEmail]) -> Result<BatchResults, BackendError> {
+        let mut results = Vec::with_capacity(emails.len());
+        for email in emails {
+            results.push(self.send(email).await);
+        }
+        Ok(results)
+    }
+}
+
+/// SES template the bulk sends go through. Each destination's replacement
+/// data carries its own fully rendered email, so merge fields, tracking links
+/// and list templates work exactly as for individual sends, and one template
+/// serves every issue.
+const BULK_TEMPLATE_NAME: &str = "newsletter-rendered";
+
+#[derive(Debug, Clone)]
+pub struct SesBackend {
+    client: Client,
+    sender: String,
+    template_registered: Arc<AtomicBool>,
+}
+
+impl SesBackend {
+    pub fn new(client: Client, sender: String) -> Self {
+        Self {
+            client,
+            sender,
+            template_registered: Default::default(),
+        }
+    }
+
+    /// Creates the bulk template the first time it's needed; it already
+    /// existing (from another instance or an earlier run) is fine.
+    async fn register_bulk_template(&self) -> Result<(), BackendError> {
+        if self.template_registered.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let template = Template::builder()
+            .template_name(BULK_TEMPLATE_NAME)
+            .subject_part("{{{subject}}}")
+            .html_part("{{{html}}}")
+            .text_part("{{{text}}}")
+            .build()
+            .expect("template name is always set");
+        match self
+            .client
+            .create_template()
+            .template(template)
+            .send()
+            .await
+            .map_err(ses::Error::from)
+        {
+            Ok(_) => {}
+            Err(e) if e.code() == Some("AlreadyExists") => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.template_registered.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+fn destination_result(status: &BulkEmailDestinationStatus) -> Result<(), BackendError> {
+    let message = status.error().unwrap_or_default().to_string();
+    match status.status() {
+        Some(BulkEmailStatus::Success) => Ok(()),
+        Some(BulkEmailStatus::AccountThrottled) => Err(BackendError::Throttled(message)),
+        Some(other) => Err(BackendError::Service {
+            code: other.as_str().to_string(),
+            message,
+        }),
+        None => Err(BackendError::Service {
+            code: "Unknown".to_string(),
+            message,
+        }),
+    }
+}
+
+fn content(data: &str) -> Content {
+    Content::builder()
+        .data(data)
+        .build()
+        .expect("content data is always set")
+}
+
+#[async_trait]
+impl EmailBackend for SesBackend {
+    fn name(&self) -> &'static str {
+        "ses"
+    }
+
+    async fn send(&self, email: &OutgoingEmail) -> Result<(), BackendError> {
+        let resp = self
+            .client
+            .send_email()
+            .source(&self.sender)
+            .destination(
+                Destination::builder()
+                    .to_addresses(email.recipient.as_ref())
+                    .build(),
+            )
+            .message(
+                Message::builder()
+                    .subject(content(&email.subject))
+                    .body(
+                        Body::builder()
+                            .html(content(&email.html))
+                            .text(content(&email.text))
+                            .build(),
+                    )
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(ses::Error::from)?;
+        tracing::info!(message_id = resp.message_id(), "Email accepted by SES");
+        Ok(())
+    }
+
+    async fn send_quota(&self) -> Result<SendQuota, BackendError> {
+        let quota = self
+            .client
+            .get_send_quota()
+            .send()
+            .await
+            .map_err(ses::Error::from)?;
+        Ok(SendQuota {
+            max_send_rate: quota.max_send_rate(),
+            max_24_hour_send: quota.max24_hour_send(),
+            sent_last_24_hours: quota.sent_last24_hours(),
+        })
+    }
+
+    async fn send_batch(&self, emails: &[OutgoingEmail]) -> Result<BatchResults, BackendError> {
+        self.register_bulk_template().await?;
+        let destinations = emails
+            .iter()
+            .map(|email| {
+                BulkEmailDestination::builder()
+                    .destination(
+                        Destination::builder()
+                            .to_addresses(email.recipient.as_ref())
+                            .build(),
+                    )
+                    .replacement_template_data(
+                        json!({
+                            "subject": email.subject,
+                            "html": email.html,
+                            "text": email.text,
+                        })
+                        .to_string(),
+                    )
+                    .build()
+            })
+            .collect();
+        let resp = self
+            .client
+            .send_bulk_templated_email()
+            .source(&self.sender)
+            .template(BULK_TEMPLATE_NAME)
+            .default_template_data(r#"{"subject":"","html":"","text":""}"#)
+            .set_destinations(Some(destinations))
+            .send()
+            .await
+            .map_err(ses::Error::from)?;
+
+        // Statuses come back in the order of the destinations.
+        let mut results: BatchResults = resp.status().iter().map(destination_result).collect();
+        results.resize(
+            emails.len(),
+            Err(BackendError::Service {
+                code: "Unknown".to_string(),
+                message: "SES returned no status for this destination".to_string(),
+            }),
+        );
+        Ok(results)
+    }
+}
+
+/// In-memory backend for local runs and tests. Emails are recorded instead of
+/// sent; it can be told to be slow, to reject recipients, or to throttle above
+/// a send rate like SES does.
+#[derive(Debug, Clone)]
+pub struct FakeBackend {
+    sent: Arc<Mutex<Vec<OutgoingEmail>>>,
+    failing: HashMap<String, String>,
+    latency: Duration,
+    max_send_rate: Option<f64>,
+    window: Arc<Mutex<VecDeque<Instant>>>,
+    throttled: Arc<AtomicUsize>,
+    batches: Arc<AtomicUsize>,
+    checks: Arc<AtomicUsize>,
+}
+
+impl Default for FakeBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeBackend {
+    /// Without [`FakeBackend::with_max_send_rate`] the quota it reports is
+    /// effectively unlimited.
+    pub const DEFAULT_SEND_RATE: f64 = 1000.0;
+
+    pub fn new() -> Self {
+        Self {
+            sent: Default::default(),
+            failing: HashMap::new(),
+            latency: Duration::ZERO,
+            max_send_rate: None,
+            window: Default::default(),
+            throttled: Default::default(),
+            batches: Default::default(),
+            checks: Default::default(),
+        }
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Rejects every email to `recipient` with the given error code.
+    pub fn with_failure(mut self, recipient: &str, code: &str) -> Self {
+        self.failing.insert(recipient.to_string(), code.to_string());
+        self
+    }
+
+    /// Throttles sends beyond `rate` per second, counted over a sliding
+    /// one-second window, and reports `rate` as the quota.
+    pub fn with_max_send_rate(mut self, rate: f64) -> Self {
+        self.max_send_rate = Some(rate);
+        self
+    }
+
+    pub fn sent(&self) -> Vec<OutgoingEmail> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Number of sends rejected for exceeding the send rate.
+    pub fn throttled(&self) -> usize {
+        self.throttled.load(Ordering::SeqCst)
+    }
+
+    /// Number of [`EmailBackend::send_batch`] calls served.
+    pub fn batches(&self) -> usize {
+        self.batches.load(Ordering::SeqCst)
+    }
+
+    /// Number of [`EmailBackend::check`] calls served.
+    pub fn checks(&self) -> usize {
+        self.checks.load(Ordering::SeqCst)
+    }
+
+    fn accept(&self, email: &OutgoingEmail) -> Result<(), BackendError> {
+        if let Some(code) = self.failing.get(email.recipient.as_ref()) {
+            return Err(BackendError::Service {
+                code: code.clone(),
+                message: format!("{} rejected by the fake backend", email.recipient),
+            });
+        }
+        if let Some(rate) = self.max_send_rate {
+            let now = Instant::now();
+            let mut window = self.window.lock().unwrap();
+            while window
+                .front()
+                .is_some_and(|sent| now.duration_since(*sent) >= Duration::from_secs(1))
+            {
+                window.pop_front();
+            }
+            if window.len() as f64 >= rate {
+                self.throttled.fetch_add(1, Ordering::SeqCst);
+                return Err(BackendError::Throttled(
+                    "Maximum sending rate exceeded.".to_string(),
+                ));
+            }
+            window.push_back(now);
+        }
+        tracing::info!("Fake backend accepted email to {}", Pii(&email.recipient));
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EmailBackend for FakeBackend {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    async fn send(&self, email: &OutgoingEmail) -> Result<(), BackendError> {
+        if !self.latency.is_zero() {
+            tokio::time::sleep(self.latency).await;
+        }
+        self.accept(email)
+    }
+
+    /// One call per batch, like the SES bulk API.
+    async fn send_batch(&self, emails: &[OutgoingEmail]) -> Result<BatchResults, BackendError> {
+        self.batches.fetch_add(1, Ordering::SeqCst);
+        if !self.latency.is_zero() {
+            tokio::time::sleep(self.latency).await;
+        }
+        Ok(emails.iter().map(|email| self.accept(email)).collect())
+    }
+
+    async fn check(&self) -> Result<(), BackendError> {
+        self.checks.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn send_quota(&self) -> Result<SendQuota, BackendError> {
+        Ok(SendQuota {
+            max_send_rate: self.max_send_rate.unwrap_or(Self::DEFAULT_SEND_RATE),
+            max_24_hour_send: -1.0,
+            sent_last_24_hours: self.sent.lock().unwrap().len() as f64,
+        })
+    }
+}
@@ -0,0 +1,396 @@
+use std::sync::Arc;
+
+use aws_config::BehaviorVersion;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::{
+    configuration::{DeliverySettings, EmailBackendKind},
+    domain::SubscriberEmail,
+    metrics::Metrics,
+    telemetry::PropagateTraceContext,
+    templates::{
+        merge::{IssueTemplate, MergeFields},
+        TemplateError, TemplateRegistry,
+    },
+    tracking::TrackedRecipient,
+};
+
+mod backend;
+mod rate_limit;
+
+pub use backend::{
+    BackendError, BatchResults, EmailBackend, FakeBackend, OutgoingEmail, SendQuota, SesBackend,
+    MAX_BATCH_SIZE,
+};
+pub use rate_limit::RateLimiter;
+
+/// Used when the send rate is neither configured nor available from the
+/// backend; it's the SES sandbox limit.
+const FALLBACK_SEND_RATE: f64 = 1.0;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct EmailBody {
+    /// The subject line; may use merge fields like the message.
+    pub title: String,
+    /// The issue body, in Markdown with merge fields (`{{first_name}}`).
+    pub message: String,
+    /// Render with the templates saved for this list, where there are any.
+    #[serde(default)]
+    pub list: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EmailClient {
+    backend: Arc<dyn EmailBackend>,
+    limiter: RateLimiter,
+    max_throttle_retries: u32,
+    metrics: Metrics,
+}
+
+#[derive(Error, Debug)]
+pub enum EmailClientError {
+    #[error("failed to render email")]
+    Render(#[from] TemplateError),
+    #[error("failed to send email")]
+    Send(#[from] BackendError),
+}
+
+impl EmailClientError {
+    /// A short, stable name for grouping failures: the provider's error code
+    /// where there is one.
+    pub fn class(&self) -> String {
+        match self {
+            EmailClientError::Render(_) => "render".to_string(),
+            EmailClientError::Send(e) => e.class(),
+        }
+    }
+}
+
+impl EmailClient {
+    pub fn new(backend: Arc<dyn EmailBackend>, limiter: RateLimiter) -> Self {
+        Self {
+            backend,
+            limiter,
+            max_throttle_retries: DeliverySettings::default().max_throttle_retries,
+            metrics: Metrics::new(),
+        }
+    }
+
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// See [`EmailBackend::check`].
+    pub async fn check_backend(&self) -> Result<(), BackendError> {
+        self.backend.check().await
+    }
+
+    /// Counts the outcome of one email in the metrics.
+    fn observe(&self, result: &Result<(), BackendError>) {
+        match result {
+            Ok(()) => self.metrics.email_sent(self.backend.name()),
+            Err(e) => self.metrics.email_failed(self.backend.name(), &e.class()),
+        }
+    }
+
+    pub fn with_max_throttle_retries(mut self, retries: u32) -> Self {
+        self.max_throttle_retries = retries;
+        self
+    }
+
+    /// Builds the configured backend, limited to `max_send_rate` or, if
+    /// that's unset, to the rate the backend reports (SES `GetSendQuota`).
+    pub async fn from_settings(settings: &DeliverySettings) -> Self {
+        let backend: Arc<dyn EmailBackend> = match settings.backend {
+            EmailBackendKind::Ses => {
+                let config = aws_config::load_defaults(BehaviorVersion::v2024_03_28()).await;
+                let config = aws_sdk_ses::config::Builder::from(&config)
+                    .interceptor(PropagateTraceContext)
+                    .build();
+                let sender = settings
+                    .sender_email
+                    .as_ref()
+                    .expect("delivery.sender_email is checked when the configuration is loaded");
+                Arc::new(SesBackend::new(
+                    aws_sdk_ses::Client::from_conf(config),
+                    sender.as_ref().to_string(),
+                ))
+            }
+            EmailBackendKind::Fake => Arc::new(FakeBackend::new()),
+        };
+        Self::for_backend(backend, settings).await
+    }
+
+    /// Sends through `backend`, with the rest of the delivery settings
+    /// applied as in [`EmailClient::from_settings`].
+    pub async fn for_backend(backend: Arc<dyn EmailBackend>, settings: &DeliverySettings) -> Self {
+        let rate = match settings.max_send_rate {
+            Some(rate) => rate,
+            None => match backend.send_quota().await {
+                Ok(quota) => quota.max_send_rate,
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to get the send quota, limiting to {}/s: {:?}",
+                        FALLBACK_SEND_RATE,
+                        e
+                    );
+                    FALLBACK_SEND_RATE
+                }
+            },
+        };
+        tracing::info!(
+            "Sending email through {} at up to {}/s",
+            backend.name(),
+            rate
+        );
+        Self::new(backend, RateLimiter::new(rate))
+            .with_max_throttle_retries(settings.max_throttle_retries)
+    }
+
+    pub fn backend_name(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    /// Sends once the rate limiter allows it, retrying sends the backend
+    /// throttled up to `max_throttle_retries` times.
+    pub async fn send(&self, email: &OutgoingEmail) -> Result<(), BackendError> {
+        let result = self.send_with_retries(email).await;
+        self.observe(&result);
+        result
+    }
+
+    async fn send_with_retries(&self, email: &OutgoingEmail) -> Result<(), BackendError> {
+        let mut retries = 0;
+        loop {
+            self.limiter.acquire().await;
+            match self.backend.send(email).await {
+                Ok(()) => {
+                    self.limiter.succeeded();
+                    return Ok(());
+                }
+                Err(e @ BackendError::Throttled(_)) => {
+                    self.limiter.throttled();
+                    if retries == self.max_throttle_retries {
+                        return Err(e);
+                    }
+                    retries += 1;
+                    self.metrics.email_retried(self.backend.name(), &e.class());
+                    tracing::warn!(
+                        "Sending to {} was throttled, retrying ({}/{})",
+                        email.recipient,
+                        retries,
+                        self.max_throttle_retries
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub async fn send_email_example(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html: &str,
+        text: &str,
+    ) -> Result<(), BackendError> {
+        self.send(&OutgoingEmail {
+            recipient: recipient.clone(),
+            subject: subject.to_string(),
+            html: html.to_string(),
+            text: text.to_string(),
+        })
+        .await
+    }
+
+    pub async fn send_rendered(
+        &self,
+        recipient: &SubscriberEmail,
+        email: &RenderedEmail,
+    ) -> Result<(), BackendError> {
+        self.send_email_example(recipient, &email.subject, &email.html, &email.text)
+            .await
+    }
+
+    /// Sends up to [`MAX_BATCH_SIZE`] emails with one backend call, returning
+    /// each email's result in order. Throttled emails are retried together,
+    /// like [`EmailClient::send`] does.
+    pub async fn send_batch(&self, emails: &[OutgoingEmail]) -> BatchResults {
+        let mut results: Vec<Option<Result<(), BackendError>>> = vec![None; emails.len()];
+        let mut pending: Vec<usize> = (0..emails.len()).collect();
+        let mut retries = 0;
+        while !pending.is_empty() {
+            self.limiter.acquire_many(pending.len()).await;
+            let batch: Vec<OutgoingEmail> = pending.iter().map(|&i| emails[i].clone()).collect();
+            let batch_results = match self.backend.send_batch(&batch).await {
+                Ok(batch_results) => batch_results,
+                Err(e) => vec![Err(e); batch.len()],
+            };
+
+            let mut throttled = vec![];
+            for (i, result) in pending.into_iter().zip(batch_results) {
+                match result {
+                    Ok(()) => {
+                        self.limiter.succeeded();
+                        results[i] = Some(Ok(()));
+                    }
+                    Err(BackendError::Throttled(message)) => throttled.push((i, message)),
+                    Err(e) => results[i] = Some(Err(e)),
+                }
+            }
+            if throttled.is_empty() {
+                break;
+            }
+            self.limiter.throttled();
+            if retries == self.max_throttle_retries {
+                for (i, message) in throttled {
+                    results[i] = Some(Err(BackendError::Throttled(message)));
+                }
+                break;
+            }
+            retries += 1;
+            for _ in &throttled {
+                self.metrics
+                    .email_retried(self.backend.name(), "Throttling");
+            }
+            tracing::warn!(
+                "{} emails of a batch were throttled, retrying ({}/{})",
+                throttled.len(),
+                retries,
+                self.max_throttle_retries
+            );
+            pending = throttled.into_iter().map(|(i, _)| i).collect();
+        }
+        let results: BatchResults = results
+            .into_iter()
+            .map(|result| result.expect("every email has a result"))
+            .collect();
+        results.iter().for_each(|result| self.observe(result));
+        results
+    }
+}
+
+/// A newsletter as a single recipient will receive it.
+#[derive(Debug, Serialize)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// Renders `issue` for one recipient and wraps it in the newsletter templates
+/// (the `list` overrides, if any). Links in the issue are tracked if `tracking`
+/// is given.
+pub fn render_newsletter(
+    templates: &TemplateRegistry,
+    list: Option<&str>,
+    issue: &IssueTemplate,
+    fields: &MergeFields,
+    tracking: Option<&TrackedRecipient>,
+) -> Result<RenderedEmail, TemplateError> {
+    let (subject, mut content) = issue.render(fields)?;
+    if let Some(tracking) = tracking {
+        content.content_html = tracking.apply(&content.content_html);
+    }
+    let mut data = json!(fields);
+    data["title"] = json!(subject);
+    data["subscriber_name"] = json!(fields.name);
+    data["content_html"] = json!(content.content_html);
+    data["content_text"] = json!(content.content_text);
+    data["privacy_policy"] = json!("#");
+
+    Ok(RenderedEmail {
+        html: templates.render("newsletter.html", list, &data)?,
+        text: templates.render("newsletter.txt", list, &data)?,
+        subject,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailClient, FakeBackend, OutgoingEmail, RateLimiter};
+    use crate::domain::SubscriberEmail;
+    use futures::StreamExt;
+    use std::{sync::Arc, time::Duration};
+
+    fn email(i: usize) -> OutgoingEmail {
+        OutgoingEmail {
+            recipient: SubscriberEmail::parse(&format!("reader{}@example.com", i)).unwrap(),
+            subject: "Issue".to_string(),
+            html: "<p>Hi</p>".to_string(),
+            text: "Hi".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_sends_back_off_to_the_backend_send_rate() {
+        let backend = FakeBackend::new()
+            .with_max_send_rate(10.0)
+            .with_latency(Duration::from_millis(20));
+        // Configured above what the backend accepts, so it gets throttled.
+        let limiter = RateLimiter::new(20.0);
+        let client = EmailClient::new(Arc::new(backend.clone()), limiter.clone())
+            .with_max_throttle_retries(20);
+
+        let results = futures::stream::iter(0..30)
+            .map(|i| {
+                let client = &client;
+                async move { client.send(&email(i)).await }
+            })
+            .buffer_unordered(8)
+            .collect::<Vec<_>>()
+            .await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(backend.sent().len(), 30);
+        assert!(backend.throttled() > 0);
+        assert!(limiter.rate() < 20.0);
+    }
+
+    #[tokio::test]
+    async fn other_backend_errors_are_not_retried() {
+        let backend = FakeBackend::new().with_failure("reader0@example.com", "MessageRejected");
+        let client = EmailClient::new(Arc::new(backend.clone()), RateLimiter::new(10.0));
+
+        let error = client.send(&email(0)).await.unwrap_err();
+
+        assert_eq!(error.class(), "MessageRejected");
+        assert!(backend.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn batches_map_results_back_to_each_email() {
+        let backend = FakeBackend::new().with_failure("reader1@example.com", "MessageRejected");
+        let client = EmailClient::new(Arc::new(backend.clone()), RateLimiter::new(100.0));
+        let emails: Vec<_> = (0..3).map(email).collect();
+
+        let results = client.send_batch(&emails).await;
+
+        assert!(results[0].is_ok());
+        assert_eq!(results[1].as_ref().unwrap_err().class(), "MessageRejected");
+        assert!(results[2].is_ok());
+        assert_eq!(backend.batches(), 1);
+        assert_eq!(backend.sent().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn only_the_throttled_part_of_a_batch_is_retried() {
+        let backend = FakeBackend::new().with_max_send_rate(5.0);
+        let client = EmailClient::new(Arc::new(backend.clone()), RateLimiter::new(10.0))
+            .with_max_throttle_retries(10);
+        let emails: Vec<_> = (0..8).map(email).collect();
+
+        let results = client.send_batch(&emails).await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(backend.sent().len(), 8);
+        assert!(backend.batches() > 1);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+/// The send rate never drops below this many emails per second, however
+/// often we're throttled.
+const MIN_RATE: f64 = 0.1;
+
+/// Throttles within this long of a back-off are answers to sends made before
+/// it, so they don't lower the rate again.
+const BACKOFF_COOLDOWN: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct Bucket {
+    max_rate: f64,
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled_at: Instant,
+    backed_off_at: Option<Instant>,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled_at = now;
+    }
+}
+
+/// Token bucket shared by every send, so concurrent deliveries together stay
+/// under the provider's send rate.
+///
+/// The rate adapts to the provider: it's halved whenever a send is throttled
+/// and creeps back up towards the configured maximum as sends succeed.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    /// Allows `rate` emails per second, with bursts of up to one second's worth.
+    pub fn new(rate: f64) -> Self {
+        let rate = rate.max(MIN_RATE);
+        let capacity = rate.max(1.0);
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                max_rate: rate,
+                rate,
+                capacity,
+                tokens: capacity,
+                refilled_at: Instant::now(),
+                backed_off_at: None,
+            })),
+        }
+    }
+
+    /// Waits for a token. Tokens are handed out in the order they're asked
+    /// for: a caller reserves one up front and sleeps until it's covered.
+    pub async fn acquire(&self) {
+        self.acquire_many(1).await
+    }
+
+    /// Waits for `n` tokens, e.g. one per destination of a bulk send.
+    pub async fn acquire_many(&self, n: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            bucket.refill();
+            bucket.tokens -= n as f64;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / bucket.rate)
+        };
+        tokio::time::sleep(wait).await;
+    }
+
+    /// The provider throttled a send: drop any burst and halve the rate, at
+    /// most once per [`BACKOFF_COOLDOWN`].
+    pub fn throttled(&self) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+        bucket.tokens = bucket.tokens.min(0.0);
+        let now = Instant::now();
+        if bucket
+            .backed_off_at
+            .is_some_and(|at| now.duration_since(at) < BACKOFF_COOLDOWN)
+        {
+            return;
+        }
+        bucket.backed_off_at = Some(now);
+        bucket.rate = (bucket.rate / 2.0).max(MIN_RATE);
+        tracing::warn!(
+            "Send throttled, lowering the send rate to {:.2}/s",
+            bucket.rate
+        );
+    }
+
+    /// A send went through: recover 1% of the maximum rate.
+    pub fn succeeded(&self) {
+        let mut bucket = self.bucket.lock().unwrap();
+        if bucket.rate < bucket.max_rate {
+            bucket.refill();
+            bucket.rate = (bucket.rate + bucket.max_rate / 100.0).min(bucket.max_rate);
+        }
+    }
+
+    /// The current send rate, in emails per second.
+    pub fn rate(&self) -> f64 {
+        self.bucket.lock().unwrap().rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[tokio::test]
+    async fn bursts_up_to_capacity_then_waits() {
+        let limiter = RateLimiter::new(10.0);
+        let start = Instant::now();
+
+        for _ in 0..10 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        for _ in 0..5 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+
+    #[tokio::test]
+    async fn backs_off_once_per_burst_of_throttles_and_recovers() {
+        let limiter = RateLimiter::new(8.0);
+
+        limiter.throttled();
+        limiter.throttled();
+        assert_eq!(limiter.rate(), 4.0);
+
+        for _ in 0..1000 {
+            limiter.succeeded();
+        }
+        assert_eq!(limiter.rate(), 8.0);
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::{Connection, PgPool};
+use tokio::time::Instant;
+
+use crate::{
+    configuration::{HealthSettings, MIGRATOR},
+    email_client::EmailClient,
+};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Up,
+    Down,
+    /// Nothing to check yet, e.g. no worker has ever reported; doesn't count
+    /// against readiness.
+    Skipped,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CheckResult {
+    pub status: CheckStatus,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadinessStatus {
+    Ready,
+    Degraded,
+}
+
+/// Outcome of every readiness check, keyed by check name.
+#[derive(Serialize, Debug)]
+pub struct Readiness {
+    pub status: ReadinessStatus,
+    pub checks: BTreeMap<&'static str, CheckResult>,
+}
+
+type Probe = (CheckStatus, Option<String>);
+
+/// Checks reported for information only: the web app serves requests just
+/// as well without a worker running, so they never make it unready.
+const INFORMATIONAL: &[&str] = &["workers"];
+
+/// The readiness checks, with the email backend's result cached for
+/// `health.email_backend_cache_secs` so frequent probes don't each call
+/// the provider. Clones share the cache.
+#[derive(Debug, Clone)]
+pub struct HealthChecks {
+    settings: HealthSettings,
+    email_backend: Arc<Mutex<Option<(Instant, CheckResult)>>>,
+}
+
+impl HealthChecks {
+    pub fn new(settings: HealthSettings) -> Self {
+        Self {
+            settings,
+            email_backend: Default::default(),
+        }
+    }
+
+    pub fn settings(&self) -> &HealthSettings {
+        &self.settings
+    }
+
+    /// Runs all readiness checks concurrently, each bounded by
+    /// `health.check_timeout_ms`.
+    pub async fn readiness(&self, pool: &PgPool, email_client: &EmailClient) -> Readiness {
+        let timeout = self.settings.check_timeout();
+        let (database, migrations, email_backend, workers) = tokio::join!(
+            timed(timeout, check_database(pool)),
+            timed(timeout, check_migrations(pool)),
+            self.cached_email_backend(timeout, email_client),
+            timed(
+                timeout,
+                check_workers(pool, self.settings.worker_heartbeat_max_age())
+            ),
+        );
+        let checks = BTreeMap::from([
+            ("database", database),
+            ("migrations", migrations),
+            ("email_backend", email_backend),
+            ("workers", workers),
+        ]);
+        let status = if checks
+            .iter()
+            .any(|(name, c)| c.status == CheckStatus::Down && !INFORMATIONAL.contains(name))
+        {
+            ReadinessStatus::Degraded
+        } else {
+            ReadinessStatus::Ready
+        };
+        Readiness { status, checks }
+    }
+
+    async fn cached_email_backend(
+        &self,
+        timeout: Duration,
+        email_client: &EmailClient,
+    ) -> CheckResult {
+        let ttl = self.settings.email_backend_cache_ttl();
+        if let Some((checked_at, result)) = &*self.email_backend.lock().unwrap() {
+            if checked_at.elapsed() < ttl {
+                return result.clone();
+            }
+        }
+        let result = timed(timeout, check_email_backend(email_client)).await;
+        *self.email_backend.lock().unwrap() = Some((Instant::now(), result.clone()));
+        result
+    }
+}
+
+async fn timed(timeout: Duration, check: impl Future<Output = Probe>) -> CheckResult {
+    let started = Instant::now();
+    let (status, detail) = tokio::time::timeout(timeout, check)
+        .await
+        .unwrap_or_else(|_| {
+            (
+                CheckStatus::Down,
+                Some(format!("timed out after {}ms", timeout.as_millis())),
+            )
+        });
+    CheckResult {
+        status,
+        latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+        detail,
+    }
+}
+
+/// The endpoint is unauthenticated, so the error itself is only logged.
+fn down(check: &str, error: impl std::fmt::Debug) -> Probe {
+    tracing::warn!("Readiness check `{}` failed: {:?}", check, error);
+    (CheckStatus::Down, Some("check failed".to_string()))
+}
+
+async fn check_database(pool: &PgPool) -> Probe {
+    match pool.acquire().await {
+        Ok(mut connection) => match connection.ping().await {
+            Ok(()) => (CheckStatus::Up, None),
+            Err(e) => down("database", e),
+        },
+        Err(e) => down("database", e),
+    }
+}
+
+/// Up when the database has every migration this build ships with.
+async fn check_migrations(pool: &PgPool) -> Probe {
+    let expected = MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0);
+    // `_sqlx_migrations` is sqlx's own table, so it isn't checked at compile time.
+    let applied = sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT MAX(version) FROM _sqlx_migrations WHERE success",
+    )
+    .fetch_one(pool)
+    .await;
+    match applied {
+        Ok(Some(applied)) if applied >= expected => {
+            (CheckStatus::Up, Some(format!("version {}", applied)))
+        }
+        Ok(applied) => (
+            CheckStatus::Down,
+            Some(format!(
+                "at version {}, expected {}",
+                applied.unwrap_or_default(),
+                expected
+            )),
+        ),
+        Err(e) => down("migrations", e),
+    }
+}
+
+async fn check_email_backend(email_client: &EmailClient) -> Probe {
+    match email_client.check_backend().await {
+        Ok(()) => (
+            CheckStatus::Up,
+            Some(email_client.backend_name().to_string()),
+        ),
+        Err(e) => down("email_backend", e),
+    }
+}
+
+async fn check_workers(pool: &PgPool, max_age: chrono::Duration) -> Probe {
+    let last_seen = sqlx::query_scalar!("SELECT MAX(last_seen_at) FROM worker_heartbeats")
+        .fetch_one(pool)
+        .await;
+    match last_seen {
+        Ok(Some(last_seen)) => {
+            let age = Utc::now() - last_seen;
+            let detail = format!("last heartbeat {}s ago", age.num_seconds());
+            if age > max_age {
+                (CheckStatus::Down, Some(detail))
+            } else {
+                (CheckStatus::Up, Some(detail))
+            }
+        }
+        Ok(None) => (
+            CheckStatus::Skipped,
+            Some("no worker has reported yet".to_string()),
+        ),
+        Err(e) => down("workers", e),
+    }
+}
+
+/// Records that `worker_id` is alive; workers call this periodically.
+/// Heartbeats of other workers older than `prune_after` are deleted, as
+/// those workers are gone.
+pub async fn record_heartbeat(
+    pool: &PgPool,
+    worker_id: &str,
+    prune_after: chrono::Duration,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO worker_heartbeats (worker_id, last_seen_at)
+        VALUES ($1, now())
+        ON CONFLICT (worker_id) DO UPDATE SET last_seen_at = EXCLUDED.last_seen_at
+        "#,
+        worker_id
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM worker_heartbeats WHERE worker_id <> $1 AND last_seen_at < $2",
+        worker_id,
+        Utc::now() - prune_after
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
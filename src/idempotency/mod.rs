@@ -1 +1,2 @@
 pub mod persistance;
+pub mod report;
@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use sha2::{Digest, Sha256};
 use sqlx::PgPool;
 use uuid::Uuid;
@@ -11,69 +11,62 @@ pub enum EmailStatus {
     Failed,
 }
 
-#[derive(Debug)]
-pub struct EmailJob {
-    pub user_id: Uuid,
-    pub idempotency_key: String,
-    pub status: EmailStatus,
-    pub attempts: i32,
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-}
-
-#[tracing::instrument(
-    name = "Generate idempotency key",
-    skip(subscriber_name, message_content)
-)]
-pub fn generate_idempotency_key(subscriber_name: &str, message_content: &str) -> String {
+/// The key of the delivery of issue `issue_id` to subscriber `subscriber_id`.
+#[tracing::instrument(name = "Generate idempotency key")]
+pub fn generate_idempotency_key(issue_id: Uuid, subscriber_id: Uuid) -> String {
     let mut hasher = Sha256::new();
-    hasher.update(subscriber_name.as_bytes());
-    hasher.update(message_content.as_bytes());
+    hasher.update(issue_id.as_bytes());
+    hasher.update(subscriber_id.as_bytes());
 
     // Convert the hash to a hexadecimal string
     format!("{:x}", hasher.finalize())
 }
 
-#[tracing::instrument(name = "Get existing job", skip(pool, idempotency_key))]
-pub async fn get_existing_job(
+#[tracing::instrument(name = "Update job status", skip(pool, idempotency_key))]
+pub async fn update_job_status(
     pool: &PgPool,
+    user_id: Uuid,
     idempotency_key: &str,
-) -> Result<Option<EmailJob>, sqlx::Error> {
-    let job = sqlx::query_as!(
-        EmailJob,
+    status: EmailStatus,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
         r#"
-        SELECT
-            user_id,
-            idempotency_key,
-            status as "status: EmailStatus",
-            attempts,
-            created_at,
-            updated_at
-        FROM idempotency
-        WHERE idempotency_key = $1
+        UPDATE idempotency
+        SET status = $1,
+            updated_at = $2,
+            sent_at = CASE WHEN $1 = 'Sent'::EmailStatus THEN $2 ELSE sent_at END
+        WHERE user_id = $3 AND idempotency_key = $4
         "#,
+        status as EmailStatus,
+        Utc::now(),
+        user_id,
         idempotency_key
     )
-    .fetch_optional(pool)
+    .execute(pool)
     .await?;
 
-    Ok(job)
+    Ok(())
 }
 
-#[tracing::instrument(name = "Update job status", skip(pool, idempotency_key))]
-pub async fn update_job_status(
+/// Marks a delivery as failed, keeping why for the issue report.
+#[tracing::instrument(name = "Record delivery failure", skip(pool, idempotency_key))]
+pub async fn record_delivery_failure(
     pool: &PgPool,
+    user_id: Uuid,
     idempotency_key: &str,
-    status: EmailStatus,
+    error_class: &str,
+    error: &str,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"
         UPDATE idempotency
-        SET status = $1, updated_at = $2
-        WHERE idempotency_key = $3
+        SET status = 'Failed', updated_at = $1, error_class = $2, last_error = $3
+        WHERE user_id = $4 AND idempotency_key = $5
         "#,
-        status as EmailStatus,
         Utc::now(),
+        error_class,
+        error,
+        user_id,
         idempotency_key
     )
     .execute(pool)
@@ -81,3 +74,41 @@ pub async fn update_job_status(
 
     Ok(())
 }
+
+/// Claims a delivery for this run: creates the record, or moves a `Failed`
+/// one, or a `Pending` one not updated for `lease`, back to `Pending` with one
+/// more attempt. Returns `None` if the email was already sent or another run
+/// is sending it, so each subscriber gets at most one attempt per run and
+/// never a second copy.
+#[tracing::instrument(name = "Claim delivery", skip(pool, idempotency_key))]
+pub async fn claim_delivery(
+    pool: &PgPool,
+    user_id: Uuid,
+    idempotency_key: &str,
+    issue_id: Uuid,
+    lease: chrono::Duration,
+) -> Result<Option<i32>, sqlx::Error> {
+    let now = Utc::now();
+    let claimed = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, status, attempts, created_at, updated_at, issue_id)
+        VALUES ($1, $2, 'Pending', 1, $3, $3, $4)
+        ON CONFLICT (user_id, idempotency_key) DO UPDATE
+        SET status = 'Pending',
+            attempts = idempotency.attempts + 1,
+            updated_at = EXCLUDED.updated_at
+        WHERE idempotency.status = 'Failed'
+            OR (idempotency.status = 'Pending' AND idempotency.updated_at < $5)
+        RETURNING attempts
+        "#,
+        user_id,
+        idempotency_key,
+        now,
+        issue_id,
+        now - lease
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(claimed.map(|r| r.attempts))
+}
@@ -0,0 +1,203 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize)]
+pub struct StatusCounts {
+    pub pending: i64,
+    pub sent: i64,
+    pub failed: i64,
+    pub total: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailureClass {
+    pub error_class: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Timing {
+    pub first_sent_at: Option<DateTime<Utc>>,
+    pub last_sent_at: Option<DateTime<Utc>>,
+    pub duration_seconds: Option<f64>,
+    /// Sent emails per minute between the first and last send.
+    pub throughput_per_minute: Option<f64>,
+}
+
+/// How an issue's delivery went, computed from its `idempotency` rows.
+#[derive(Debug, Serialize)]
+pub struct DeliveryReport {
+    pub issue_id: Uuid,
+    pub counts: StatusCounts,
+    pub failures: Vec<FailureClass>,
+    pub timing: Timing,
+}
+
+#[derive(Debug)]
+pub struct FailedRecipient {
+    pub email: String,
+    pub name: String,
+    pub error_class: Option<String>,
+    pub last_error: Option<String>,
+    pub attempts: i32,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Build delivery report", skip(pool))]
+pub async fn delivery_report(pool: &PgPool, issue_id: Uuid) -> Result<DeliveryReport, sqlx::Error> {
+    let totals = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE status = 'Pending') AS "pending!",
+            COUNT(*) FILTER (WHERE status = 'Sent') AS "sent!",
+            COUNT(*) FILTER (WHERE status = 'Failed') AS "failed!",
+            COUNT(*) AS "total!",
+            MIN(sent_at) AS first_sent_at,
+            MAX(sent_at) AS last_sent_at
+        FROM idempotency
+        WHERE issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let failures = sqlx::query_as!(
+        FailureClass,
+        r#"
+        SELECT COALESCE(error_class, 'unknown') AS "error_class!", COUNT(*) AS "count!"
+        FROM idempotency
+        WHERE issue_id = $1 AND status = 'Failed'
+        GROUP BY 1
+        ORDER BY 2 DESC, 1
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let duration_seconds = match (totals.first_sent_at, totals.last_sent_at) {
+        (Some(first), Some(last)) => Some((last - first).num_milliseconds() as f64 / 1000.0),
+        _ => None,
+    };
+    let throughput_per_minute = duration_seconds
+        .filter(|seconds| *seconds > 0.0)
+        .map(|seconds| totals.sent as f64 / seconds * 60.0);
+
+    Ok(DeliveryReport {
+        issue_id,
+        counts: StatusCounts {
+            pending: totals.pending,
+            sent: totals.sent,
+            failed: totals.failed,
+            total: totals.total,
+        },
+        failures,
+        timing: Timing {
+            first_sent_at: totals.first_sent_at,
+            last_sent_at: totals.last_sent_at,
+            duration_seconds,
+            throughput_per_minute,
+        },
+    })
+}
+
+#[tracing::instrument(name = "Get failed recipients", skip(pool))]
+pub async fn failed_recipients(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<FailedRecipient>, sqlx::Error> {
+    sqlx::query_as!(
+        FailedRecipient,
+        r#"
+        SELECT s.email, s.name, i.error_class, i.last_error, i.attempts, i.updated_at
+        FROM idempotency i
+        JOIN subscriptions s ON s.id = i.user_id
+        WHERE i.issue_id = $1 AND i.status = 'Failed'
+        ORDER BY s.email
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// RFC 4180 CSV with a header row. Cells that a spreadsheet would read as
+/// a formula are prefixed with `'`, since names and errors are untrusted.
+pub fn to_csv(recipients: &[FailedRecipient]) -> Result<String, anyhow::Error> {
+    let mut csv = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::CRLF)
+        .from_writer(vec![]);
+    csv.write_record([
+        "email",
+        "name",
+        "error_class",
+        "last_error",
+        "attempts",
+        "updated_at",
+    ])?;
+    for r in recipients {
+        csv.write_record([
+            escape_formula(&r.email),
+            escape_formula(&r.name),
+            escape_formula(r.error_class.as_deref().unwrap_or_default()),
+            escape_formula(r.last_error.as_deref().unwrap_or_default()),
+            r.attempts.to_string(),
+            r.updated_at.to_rfc3339(),
+        ])?;
+    }
+    let csv = csv.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8(csv)?)
+}
+
+fn escape_formula(value: &str) -> String {
+    if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_csv, FailedRecipient};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        let csv = to_csv(&[FailedRecipient {
+            email: "a@example.com".to_string(),
+            name: "Le Guin, Ursula".to_string(),
+            error_class: Some("MessageRejected".to_string()),
+            last_error: Some("said \"no\"".to_string()),
+            attempts: 1,
+            updated_at: Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap(),
+        }])
+        .unwrap();
+
+        assert_eq!(
+            csv.lines().nth(1).unwrap(),
+            "a@example.com,\"Le Guin, Ursula\",MessageRejected,\"said \"\"no\"\"\",1,2025-01-01T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn cells_that_look_like_formulas_are_escaped() {
+        let csv = to_csv(&[FailedRecipient {
+            email: "a@example.com".to_string(),
+            name: "=HYPERLINK(\"https://evil.example\")".to_string(),
+            error_class: Some("@SUM(A1)".to_string()),
+            last_error: Some("-1+1".to_string()),
+            attempts: 1,
+            updated_at: Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap(),
+        }])
+        .unwrap();
+
+        assert_eq!(
+            csv.lines().nth(1).unwrap(),
+            "a@example.com,\"'=HYPERLINK(\"\"https://evil.example\"\")\",'@SUM(A1),'-1+1,1,2025-01-01T00:00:00+00:00"
+        );
+    }
+}
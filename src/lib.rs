@@ -1,26 +1,61 @@
-use configuration::{configure_database, get_configuration};
+use std::{sync::Arc, time::Duration};
+
+use configuration::{configure_database, load_configuration, Environment, Settings};
+use deliverability::FakeResolver;
 use lazy_static::lazy_static;
 use sqlx::PgPool;
-use thiserror::Error;
-use tokio::net::TcpListener;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
-use startup::{get_subscriber, init_subscriber, run};
+use startup::{get_subscriber, init_subscriber, Application, ApplicationBuilder};
 use uuid::Uuid;
 
+pub mod authentication;
+pub mod cli;
 pub mod configuration;
+pub mod deliverability;
+pub mod delivery;
 pub mod domain;
 pub mod email_client;
+pub mod health;
 pub mod idempotency;
+pub mod metrics;
+pub mod pages;
+pub mod redact;
+pub mod request_id;
 pub mod routes;
 pub mod startup;
+pub mod telemetry;
+pub mod templates;
+pub mod tracking;
+pub mod worker;
 
 pub struct TestApp {
     pub address: String,
     pub client: reqwest::Client,
     pub pool: PgPool,
+    /// Cancel to shut the app down as SIGTERM would.
+    pub shutdown: CancellationToken,
+    pub deliveries: TaskTracker,
 }
 
 impl TestApp {
+    /// Waits for the sends `/publish` and `/issues/{issue_id}/resume` started
+    /// in the background.
+    pub async fn wait_for_deliveries(&self) {
+        self.deliveries.close();
+        self.deliveries.wait().await;
+        self.deliveries.reopen();
+    }
+
+    /// Publishes `body` and waits for it to be delivered.
+    pub async fn publish_and_wait(&self, body: &serde_json::Value) -> serde_json::Value {
+        let response = self.post_publish(body).await;
+        assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+        let accepted = response.json().await.expect("Failed to read the response");
+        self.wait_for_deliveries().await;
+        accepted
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.client
             .post(format!("{}/subscribe", self.address))
@@ -31,6 +66,51 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn post_subscriptions_json(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.client
+            .post(format!("{}/subscribe", self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_publish(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.client
+            .post(format!("{}/publish", self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_issue(&self, action: &str, body: &serde_json::Value) -> reqwest::Response {
+        self.client
+            .post(format!("{}/issues/{}", self.address, action))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_template(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.client
+            .post(format!("{}/templates", self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_confirm_subscription_json(&self, token: &str) -> reqwest::Response {
+        self.client
+            .post(format!("{}/subscribe/confirm", self.address))
+            .json(&serde_json::json!({ "subscription_token": token }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     pub async fn get_confirm_subscription(&self, token: &str) -> reqwest::Response {
         let params = [("subscription_token", &token)];
         self.client
@@ -47,33 +127,64 @@ lazy_static! {
         let default_filter = "TRACE";
         let default_subscriber_name = "test";
 
-        let subscriber = get_subscriber(default_filter, default_subscriber_name);
+        let subscriber = get_subscriber(
+            default_filter,
+            default_subscriber_name,
+            None,
+            std::io::stdout,
+        );
         init_subscriber(subscriber);
     };
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|builder| builder).await
+}
+
+/// Like [`spawn_app`], with `configure` applying overrides such as a fake
+/// email backend on top of the test defaults.
+pub async fn spawn_app_with(
+    configure: impl FnOnce(ApplicationBuilder) -> ApplicationBuilder,
+) -> TestApp {
+    spawn_app_with_settings(|_| {}, configure).await
+}
+
+/// Like [`spawn_app_with`], with `configure_settings` adjusting the test
+/// profile's settings first.
+pub async fn spawn_app_with_settings(
+    configure_settings: impl FnOnce(&mut Settings),
+    configure: impl FnOnce(ApplicationBuilder) -> ApplicationBuilder,
+) -> TestApp {
     lazy_static::initialize(&SUBSCRIBER);
-    let test_listener = TcpListener::bind("127.0.0.1:0")
-        .await
-        .expect("Failed to bind the listener");
 
-    let mut configuration = get_configuration().expect("Failed to get configuration");
+    let mut configuration =
+        load_configuration(Environment::Test).expect("Failed to get configuration");
+    configure_settings(&mut configuration);
     configuration.database.database_name = Uuid::new_v4().to_string();
+    configuration.application.host = "127.0.0.1".to_string();
+    configuration.application.port = 0;
     configure_database(&configuration.database).await;
-    let db = PgPool::connect_lazy_with(configuration.database.with_db());
-
-    let port = test_listener.local_addr().unwrap().port();
-    let server = run(test_listener, db.clone());
-    tokio::spawn(server);
-    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
-    tracing::info!(
-        "test addr: {}",
-        format!("http://127.0.0.1:{}", port.to_string()),
-    );
+
+    let resolver = FakeResolver::new()
+        .with_mx("gmail.com", Duration::from_secs(3600))
+        .with_address("example.com", Duration::from_secs(3600));
+    let application =
+        configure(Application::builder(configuration).with_resolver(Arc::new(resolver)))
+            .build()
+            .await
+            .expect("Failed to build the application");
+
+    let address = format!("http://127.0.0.1:{}", application.port());
+    let pool = application.pool().clone();
+    let shutdown = application.shutdown_handle();
+    let deliveries = application.deliveries();
+    tokio::spawn(application.run_until_stopped());
+    tracing::info!("test addr: {}", address);
     TestApp {
-        address: format!("http://127.0.0.1:{}", port.to_string()),
+        address,
         client: reqwest::Client::new(),
-        pool: db,
+        pool,
+        shutdown,
+        deliveries,
     }
 }
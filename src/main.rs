@@ -1,21 +1,39 @@
-use sqlx::PgPool;
+use anyhow::Context;
+use clap::Parser;
+use zero2prod::cli::Cli;
 use zero2prod::configuration::get_configuration;
-use zero2prod::startup::{get_subscriber, init_subscriber, run};
+use zero2prod::startup::{get_subscriber, init_subscriber};
+use zero2prod::telemetry;
 
 #[tokio::main]
-async fn main() {
-    let subscriber = get_subscriber("TRACE", "zero2prod");
-    init_subscriber(subscriber);
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let configuration = get_configuration()?;
 
-    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    let tracer_provider = telemetry::tracer_provider(&configuration.telemetry)
+        .context("Invalid telemetry settings")?;
+    if cli.writes_to_stdout() {
+        init_subscriber(get_subscriber(
+            "TRACE",
+            "zero2prod",
+            tracer_provider.as_ref(),
+            std::io::stderr,
+        ));
+    } else {
+        init_subscriber(get_subscriber(
+            "TRACE",
+            "zero2prod",
+            tracer_provider.as_ref(),
+            std::io::stdout,
+        ));
+    }
 
-    let main_listener = tokio::net::TcpListener::bind(configuration.application.get_address())
-        .await
-        .expect("Failed to bind port 8000");
-    println!("PORT: {}", configuration.database.port);
+    let result = cli.run(configuration).await;
 
-    configuration.database.database_name = "newsletter".to_string();
-    let db = PgPool::connect_lazy_with(configuration.database.with_db());
-
-    let _ = run(main_listener, db).await;
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Failed to flush traces: {:?}", e);
+        }
+    }
+    result
 }
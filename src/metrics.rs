@@ -0,0 +1,250 @@
+use std::{fmt, time::Duration};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use sqlx::PgPool;
+use tokio::time::Instant;
+
+/// Steps of the subscription funnel, counted as they happen.
+#[derive(Debug, Clone, Copy)]
+pub enum FunnelStage {
+    Subscribed,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl FunnelStage {
+    fn as_str(self) -> &'static str {
+        match self {
+            FunnelStage::Subscribed => "subscribed",
+            FunnelStage::Confirmed => "confirmed",
+            FunnelStage::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+/// Prometheus metrics for one running application, in its own registry.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    emails_sent: IntCounterVec,
+    emails_failed: IntCounterVec,
+    emails_retried: IntCounterVec,
+    funnel: IntCounterVec,
+    db_pool_connections: IntGauge,
+    db_pool_idle: IntGauge,
+    db_pool_acquire_seconds: Gauge,
+    email_queue_depth: IntGauge,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let http_labels = &["method", "route", "status"];
+        let metrics = Self {
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests by route and status"),
+                http_labels,
+            )
+            .unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "HTTP request latency by route and status",
+                ),
+                http_labels,
+            )
+            .unwrap(),
+            emails_sent: IntCounterVec::new(
+                Opts::new("emails_sent_total", "Emails accepted by the backend"),
+                &["backend"],
+            )
+            .unwrap(),
+            emails_failed: IntCounterVec::new(
+                Opts::new("emails_failed_total", "Emails that failed to send"),
+                &["backend", "error_class"],
+            )
+            .unwrap(),
+            emails_retried: IntCounterVec::new(
+                Opts::new(
+                    "emails_retried_total",
+                    "Sends retried after being throttled",
+                ),
+                &["backend", "error_class"],
+            )
+            .unwrap(),
+            funnel: IntCounterVec::new(
+                Opts::new("subscription_funnel_total", "Subscription funnel events"),
+                &["stage"],
+            )
+            .unwrap(),
+            db_pool_connections: IntGauge::new(
+                "db_pool_connections",
+                "Open connections in the Postgres pool",
+            )
+            .unwrap(),
+            db_pool_idle: IntGauge::new("db_pool_idle", "Idle connections in the Postgres pool")
+                .unwrap(),
+            db_pool_acquire_seconds: Gauge::new(
+                "db_pool_acquire_seconds",
+                "Time taken to acquire a connection at the last scrape",
+            )
+            .unwrap(),
+            email_queue_depth: IntGauge::new(
+                "email_queue_depth",
+                "Newsletter deliveries claimed but not yet sent or failed",
+            )
+            .unwrap(),
+            registry,
+        };
+        for collector in [
+            Box::new(metrics.http_requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(metrics.http_request_duration.clone()),
+            Box::new(metrics.emails_sent.clone()),
+            Box::new(metrics.emails_failed.clone()),
+            Box::new(metrics.emails_retried.clone()),
+            Box::new(metrics.funnel.clone()),
+            Box::new(metrics.db_pool_connections.clone()),
+            Box::new(metrics.db_pool_idle.clone()),
+            Box::new(metrics.db_pool_acquire_seconds.clone()),
+            Box::new(metrics.email_queue_depth.clone()),
+        ] {
+            metrics
+                .registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+        metrics
+    }
+
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_request_duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn email_sent(&self, backend: &str) {
+        self.emails_sent.with_label_values(&[backend]).inc();
+    }
+
+    pub fn email_failed(&self, backend: &str, error_class: &str) {
+        self.emails_failed
+            .with_label_values(&[backend, error_class])
+            .inc();
+    }
+
+    pub fn email_retried(&self, backend: &str, error_class: &str) {
+        self.emails_retried
+            .with_label_values(&[backend, error_class])
+            .inc();
+    }
+
+    pub fn funnel(&self, stage: FunnelStage) {
+        self.funnel.with_label_values(&[stage.as_str()]).inc();
+    }
+
+    /// Refreshes the pool and queue gauges and renders everything in the
+    /// Prometheus text format.
+    pub async fn render(&self, pool: &PgPool) -> String {
+        self.db_pool_connections.set(pool.size().into());
+        self.db_pool_idle.set(pool.num_idle() as i64);
+
+        let started = Instant::now();
+        match pool.acquire().await {
+            Ok(_) => self
+                .db_pool_acquire_seconds
+                .set(started.elapsed().as_secs_f64()),
+            Err(e) => tracing::warn!("Failed to acquire a connection for metrics: {:?}", e),
+        }
+
+        match sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!" FROM idempotency WHERE status = 'Pending'"#
+        )
+        .fetch_one(pool)
+        .await
+        {
+            Ok(row) => self.email_queue_depth.set(row.count),
+            Err(e) => tracing::warn!("Failed to count queued deliveries: {:?}", e),
+        }
+
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .expect("metrics are always encodable")
+    }
+}
+
+/// Counts and times every request by its matched route, e.g.
+/// `/issues/{issue_id}/report` rather than the concrete path.
+pub async fn track_metrics(
+    State(metrics): State<Metrics>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(request).await;
+
+    metrics.observe_request(
+        &method,
+        &route,
+        response.status().as_u16(),
+        started.elapsed(),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FunnelStage, Metrics};
+    use prometheus::TextEncoder;
+    use std::time::Duration;
+
+    #[test]
+    fn metrics_are_labelled_by_route_and_status() {
+        let metrics = Metrics::new();
+        metrics.observe_request("GET", "/issues/{issue_id}/report", 200, Duration::ZERO);
+        metrics.email_failed("ses", "MessageRejected");
+        metrics.funnel(FunnelStage::Confirmed);
+
+        let text = TextEncoder::new()
+            .encode_to_string(&metrics.registry.gather())
+            .unwrap();
+
+        assert!(text.contains(
+            r#"http_requests_total{method="GET",route="/issues/{issue_id}/report",status="200"} 1"#
+        ));
+        assert!(
+            text.contains(r#"emails_failed_total{backend="ses",error_class="MessageRejected"} 1"#)
+        );
+        assert!(text.contains(r#"subscription_funnel_total{stage="confirmed"} 1"#));
+    }
+}
@@ -0,0 +1,179 @@
+use std::{path::Path, sync::Arc};
+
+use axum::{
+    http::header::LOCATION,
+    response::{Html, IntoResponse, Response},
+};
+use handlebars::Handlebars;
+use hyper::StatusCode;
+use serde_json::json;
+use thiserror::Error;
+
+use crate::{configuration::PagesSettings, routes::error::ApiError};
+
+const TEMPLATES: &[(&str, &str)] = &[
+    ("layout", include_str!("./templates/pages/layout.html")),
+    (
+        "confirmed",
+        include_str!("./templates/pages/confirmed.html"),
+    ),
+    (
+        "already_confirmed",
+        include_str!("./templates/pages/already_confirmed.html"),
+    ),
+    (
+        "link_expired",
+        include_str!("./templates/pages/link_expired.html"),
+    ),
+    (
+        "confirm_unsubscribe",
+        include_str!("./templates/pages/confirm_unsubscribe.html"),
+    ),
+    (
+        "confirm_opt_out",
+        include_str!("./templates/pages/confirm_opt_out.html"),
+    ),
+    (
+        "unsubscribed",
+        include_str!("./templates/pages/unsubscribed.html"),
+    ),
+    ("error", include_str!("./templates/pages/error.html")),
+];
+
+/// What a browser-facing endpoint ended up doing, each mapped to its own page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageOutcome {
+    Confirmed,
+    AlreadyConfirmed,
+    LinkExpired,
+    /// Asks the subscriber to confirm, so link scanners can't unsubscribe them.
+    ConfirmUnsubscribe,
+    /// Same for opting out of open and click tracking.
+    ConfirmOptOut,
+    Unsubscribed,
+    Error,
+}
+
+impl PageOutcome {
+    fn template(self) -> &'static str {
+        match self {
+            PageOutcome::Confirmed => "confirmed",
+            PageOutcome::AlreadyConfirmed => "already_confirmed",
+            PageOutcome::LinkExpired => "link_expired",
+            PageOutcome::ConfirmUnsubscribe => "confirm_unsubscribe",
+            PageOutcome::ConfirmOptOut => "confirm_opt_out",
+            PageOutcome::Unsubscribed => "unsubscribed",
+            PageOutcome::Error => "error",
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum PagesError {
+    #[error("failed to read page template `{0}`")]
+    Io(String, #[source] std::io::Error),
+    #[error("invalid page template")]
+    Template(#[from] handlebars::TemplateError),
+}
+
+/// HTML landing pages, rendered from the built-in templates unless a file of
+/// the same name exists in `pages.template_dir`.
+#[derive(Debug, Clone)]
+pub struct Pages {
+    registry: Arc<Handlebars<'static>>,
+    settings: PagesSettings,
+}
+
+impl Pages {
+    pub fn new(settings: &PagesSettings) -> Result<Self, PagesError> {
+        let mut registry = Handlebars::new();
+        registry.set_strict_mode(true);
+
+        for (name, builtin) in TEMPLATES {
+            let source = match &settings.template_dir {
+                Some(dir) => {
+                    let path = Path::new(dir).join(format!("{}.html", name));
+                    if path.exists() {
+                        std::fs::read_to_string(&path)
+                            .map_err(|e| PagesError::Io(path.display().to_string(), e))?
+                    } else {
+                        builtin.to_string()
+                    }
+                }
+                None => builtin.to_string(),
+            };
+            registry.register_template_string(name, source)?;
+        }
+
+        Ok(Self {
+            registry: Arc::new(registry),
+            settings: settings.clone(),
+        })
+    }
+
+    /// Redirects to the configured URL for `outcome` if there is one,
+    /// otherwise renders its page with `status`.
+    pub fn respond(&self, outcome: PageOutcome, status: StatusCode, message: &str) -> Response {
+        if let Some(url) = self.settings.redirects.for_outcome(outcome) {
+            return (StatusCode::SEE_OTHER, [(LOCATION, url.to_string())]).into_response();
+        }
+
+        let data = json!({
+            "theme": &self.settings.theme,
+            "message": message,
+        });
+        match self.registry.render(outcome.template(), &data) {
+            Ok(html) => (status, Html(html)).into_response(),
+            Err(e) => {
+                tracing::error!("Failed to render `{}` page: {:?}", outcome.template(), e);
+                (StatusCode::INTERNAL_SERVER_ERROR, Html(message.to_string())).into_response()
+            }
+        }
+    }
+
+    /// The HTML counterpart of rendering `error` as problem+json.
+    pub fn respond_error(&self, outcome: PageOutcome, error: ApiError) -> Response {
+        let request_id = error.log();
+        let message = format!("{} (reference: {})", error.title(), request_id);
+        self.respond(outcome, error.status(), &message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PageOutcome, Pages};
+    use crate::configuration::PagesSettings;
+    use hyper::StatusCode;
+
+    #[test]
+    fn builtin_templates_render() {
+        let pages = Pages::new(&PagesSettings::default()).expect("templates should compile");
+        for outcome in [
+            PageOutcome::Confirmed,
+            PageOutcome::AlreadyConfirmed,
+            PageOutcome::LinkExpired,
+            PageOutcome::ConfirmUnsubscribe,
+            PageOutcome::ConfirmOptOut,
+            PageOutcome::Unsubscribed,
+            PageOutcome::Error,
+        ] {
+            let response = pages.respond(outcome, StatusCode::OK, "message");
+            assert_eq!(response.status(), StatusCode::OK, "{:?}", outcome);
+        }
+    }
+
+    #[test]
+    fn configured_redirect_replaces_the_page() {
+        let mut settings = PagesSettings::default();
+        settings.redirects.confirmed = Some("https://example.com/welcome".to_string());
+        let pages = Pages::new(&settings).unwrap();
+
+        let response = pages.respond(PageOutcome::Confirmed, StatusCode::OK, "");
+
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers()["location"],
+            "https://example.com/welcome"
+        );
+    }
+}
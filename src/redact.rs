@@ -0,0 +1,149 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::configuration::PiiMode;
+
+const REDACTED: &str = "[REDACTED]";
+
+static PII_MODE: AtomicU8 = AtomicU8::new(PiiMode::Mask as u8);
+
+/// Sets how [`Pii`] values are written to logs from now on.
+pub fn set_pii_mode(mode: PiiMode) {
+    PII_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+fn pii_mode() -> PiiMode {
+    match PII_MODE.load(Ordering::Relaxed) {
+        m if m == PiiMode::Hash as u8 => PiiMode::Hash,
+        m if m == PiiMode::Redact as u8 => PiiMode::Redact,
+        _ => PiiMode::Mask,
+    }
+}
+
+impl PiiMode {
+    fn apply(self, value: &str) -> String {
+        match self {
+            PiiMode::Hash => {
+                let digest = format!("{:x}", Sha256::digest(value.as_bytes()));
+                format!("sha256:{}", &digest[..12])
+            }
+            PiiMode::Mask => {
+                let (local, domain) = match value.rsplit_once('@') {
+                    Some((local, domain)) => (local, Some(domain)),
+                    None => (value, None),
+                };
+                let first = local.chars().next().map(String::from).unwrap_or_default();
+                match domain {
+                    Some(domain) => format!("{}***@{}", first, domain),
+                    None => format!("{}***", first),
+                }
+            }
+            PiiMode::Redact => REDACTED.to_string(),
+        }
+    }
+}
+
+/// Personal data about a subscriber, e.g. their email address or name, as it
+/// should appear in logs and spans: hashed, masked or redacted according to
+/// `logging.pii`.
+///
+/// `tracing::info!("Sending to {}", Pii(&email))`
+#[derive(Clone, Copy)]
+pub struct Pii<T>(pub T);
+
+impl<T: AsRef<str>> fmt::Display for Pii<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&pii_mode().apply(self.0.as_ref()))
+    }
+}
+
+impl<T: AsRef<str>> fmt::Debug for Pii<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// A credential that must never end up in logs: `Debug`, `Display` and
+/// `Serialize` all give `[REDACTED]`, and the value is only reachable through
+/// [`Secret::expose`].
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Secret;
+    use crate::configuration::PiiMode;
+
+    #[test]
+    fn masking_keeps_the_first_character_and_the_email_domain() {
+        assert_eq!(PiiMode::Mask.apply("luka_tim@gmail.com"), "l***@gmail.com");
+        assert_eq!(PiiMode::Mask.apply("Łukasz Tim"), "Ł***");
+        assert_eq!(PiiMode::Mask.apply(""), "***");
+    }
+
+    #[test]
+    fn hashing_is_stable_and_hides_the_value() {
+        let hashed = PiiMode::Hash.apply("luka_tim@gmail.com");
+        assert_eq!(hashed, PiiMode::Hash.apply("luka_tim@gmail.com"));
+        assert_ne!(hashed, PiiMode::Hash.apply("luka_tom@gmail.com"));
+        assert!(hashed.starts_with("sha256:"));
+        assert!(!hashed.contains("luka"));
+    }
+
+    #[test]
+    fn redacting_hides_everything() {
+        assert_eq!(PiiMode::Redact.apply("luka_tim@gmail.com"), "[REDACTED]");
+    }
+
+    #[test]
+    fn secrets_are_not_formatted() {
+        let secret = Secret::new("hunter2".to_string());
+        assert_eq!(format!("{:?} {}", secret, secret), "[REDACTED] [REDACTED]");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn secrets_are_not_serialized() {
+        let secret = Secret::new("hunter2".to_string());
+        assert_eq!(serde_json::to_string(&secret).unwrap(), r#""[REDACTED]""#);
+    }
+}
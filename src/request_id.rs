@@ -0,0 +1,95 @@
+use std::fmt;
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest incoming id we accept; longer ones are replaced.
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// Identifies one request in logs, traces, the `X-Request-Id` response header
+/// and error bodies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    /// Accepts ids of up to 128 letters, digits and `-_.:`, so whatever an
+    /// upstream proxy sends can't forge log lines or headers.
+    pub fn parse(value: &str) -> Option<Self> {
+        let valid = !value.is_empty()
+            && value.len() <= MAX_LENGTH
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+        valid.then(|| Self(value.to_string()))
+    }
+
+    /// The id of the request being handled, if called while handling one.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|id| id.clone()).ok()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Takes the request id from a valid incoming `X-Request-Id` or generates one,
+/// makes it available to the trace span (as a request extension) and to error
+/// responses ([`RequestId::current`]), and echoes it in the response.
+pub async fn propagate_request_id(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(RequestId::parse)
+        .unwrap_or_else(RequestId::generate);
+    request.extensions_mut().insert(id.clone());
+
+    let mut response = CURRENT.scope(id.clone(), next.run(request)).await;
+    response.headers_mut().insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_str(id.as_str()).expect("request ids are valid header values"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestId;
+    use claim::{assert_none, assert_some};
+
+    #[test]
+    fn well_formed_ids_are_accepted() {
+        assert_some!(RequestId::parse("3f2b9c1e-8a4d-4c1b-9e2f-6d7a8b9c0d1e"));
+        assert_some!(RequestId::parse("edge:req_42.1"));
+    }
+
+    #[test]
+    fn malformed_ids_are_rejected() {
+        assert_none!(RequestId::parse(""));
+        assert_none!(RequestId::parse("has space"));
+        assert_none!(RequestId::parse("line\nbreak"));
+        assert_none!(RequestId::parse(&"a".repeat(129)));
+    }
+}
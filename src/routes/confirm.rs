@@ -1,61 +1,182 @@
 use anyhow::Context;
 use axum::{
-    extract::{Query, State},
-    response::IntoResponse,
+    extract::{rejection::JsonRejection, Query, State},
+    response::{IntoResponse, Response},
+    Json,
 };
+use chrono::{DateTime, Utc};
 use hyper::StatusCode;
 use serde::Deserialize;
 use sqlx::{Postgres, Transaction};
+use thiserror::Error;
 use uuid::Uuid;
 
-use crate::startup::AppState;
+use crate::{
+    metrics::FunnelStage,
+    pages::{PageOutcome, Pages},
+    routes::{
+        error::{ApiError, FieldError},
+        negotiation::ResponseFormat,
+    },
+    startup::AppState,
+};
 
-use super::subscriptions::SubscribeError;
+use super::subscriptions::{SubscriptionStatus, SUBSCRIPTION_TOKEN_LENGTH};
 
 #[derive(Debug, Deserialize)]
-pub struct ConfirmQuery {
+pub struct ConfirmParams {
     subscription_token: String,
 }
 
+#[derive(Error, Debug)]
+pub enum ConfirmError {
+    #[error("subscription token is malformed")]
+    MalformedToken,
+    #[error("subscription token is unknown")]
+    UnknownToken,
+    #[error("subscription is already confirmed")]
+    AlreadyConfirmed,
+    #[error("subscription token has expired")]
+    ExpiredToken,
+    #[error(transparent)]
+    InvalidJson(#[from] JsonRejection),
+    #[error("unexpected error: `{0}`")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<ConfirmError> for ApiError {
+    fn from(e: ConfirmError) -> Self {
+        match e {
+            ConfirmError::MalformedToken => ApiError::validation(vec![FieldError {
+                field: "subscription_token".to_string(),
+                detail: format!(
+                    "Subscription token must be {} alphanumeric characters",
+                    SUBSCRIPTION_TOKEN_LENGTH
+                ),
+            }]),
+            ConfirmError::UnknownToken => ApiError::new(
+                StatusCode::NOT_FOUND,
+                "unknown-subscription-token",
+                "The confirmation link is not valid.",
+            ),
+            ConfirmError::AlreadyConfirmed => ApiError::new(
+                StatusCode::CONFLICT,
+                "subscription-already-confirmed",
+                "The subscription has already been confirmed.",
+            ),
+            ConfirmError::ExpiredToken => ApiError::new(
+                StatusCode::GONE,
+                "expired-subscription-token",
+                "The confirmation link has expired.",
+            )
+            .with_detail("Subscribe again to receive a new confirmation link."),
+            ConfirmError::InvalidJson(rejection) => rejection.into(),
+            ConfirmError::UnexpectedError(e) => ApiError::internal(e),
+        }
+    }
+}
+
+impl ConfirmError {
+    fn into_page(self, pages: &Pages) -> Response {
+        let outcome = match self {
+            ConfirmError::AlreadyConfirmed => PageOutcome::AlreadyConfirmed,
+            ConfirmError::ExpiredToken => PageOutcome::LinkExpired,
+            _ => PageOutcome::Error,
+        };
+        pages.respond_error(outcome, self.into())
+    }
+}
+
+impl IntoResponse for ConfirmError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+#[derive(Debug)]
+struct TokenRecord {
+    subscriber_id: Uuid,
+    created_at: DateTime<Utc>,
+    status: String,
+}
+
+/// `GET /subscribe/confirm?subscription_token=...`, the link sent by email.
 pub async fn confirm_subscriber(
     State(state): State<AppState>,
-    Query(params): Query<ConfirmQuery>,
-) -> Result<impl IntoResponse, SubscribeError> {
+    format: ResponseFormat,
+    Query(params): Query<ConfirmParams>,
+) -> Result<Response, ConfirmError> {
+    match confirm(&state, &params.subscription_token).await {
+        Ok(status) => Ok(status.respond(format, &state.pages)),
+        Err(e) if format == ResponseFormat::Html => Ok(e.into_page(&state.pages)),
+        Err(e) => Err(e),
+    }
+}
+
+/// `POST /subscribe/confirm` with a `{"subscription_token": ...}` body, for API clients.
+pub async fn confirm_subscriber_json(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    payload: Result<Json<ConfirmParams>, JsonRejection>,
+) -> Result<Response, ConfirmError> {
+    let Json(params) = payload?;
+    let status = confirm(&state, &params.subscription_token).await?;
+    Ok(status.respond(format, &state.pages))
+}
+
+#[tracing::instrument(name = "Confirming a pending subscriber", skip(state, token))]
+async fn confirm(state: &AppState, token: &str) -> Result<SubscriptionStatus, ConfirmError> {
+    if token.len() != SUBSCRIPTION_TOKEN_LENGTH || !token.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        return Err(ConfirmError::MalformedToken);
+    }
+
     let mut tx = state
         .pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let id = get_subscriber_id_from_token(&params.subscription_token, &mut tx)
+    let record = get_token_record(token, &mut tx)
         .await
-        .context("Failed to get subscriber id")?;
+        .context("Failed to get subscriber id")?
+        .ok_or(ConfirmError::UnknownToken)?;
 
-    let _ = update_to_confirmed(&mut tx, id)
+    if record.status == "confirmed" {
+        return Err(ConfirmError::AlreadyConfirmed);
+    }
+    if record.created_at + state.confirmation_token_ttl < Utc::now() {
+        return Err(ConfirmError::ExpiredToken);
+    }
+
+    update_to_confirmed(&mut tx, record.subscriber_id)
         .await
         .context("Failed to update subscriber status")?;
     tx.commit().await.context("Failed to commit transaction")?;
-    Ok(StatusCode::OK.into_response())
+    state.metrics.funnel(FunnelStage::Confirmed);
+    Ok(SubscriptionStatus::Confirmed)
 }
 
-async fn get_subscriber_id_from_token<'a>(
-    token: &'a str,
+async fn get_token_record(
+    token: &str,
     trans: &mut Transaction<'_, Postgres>,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscriptions_tokens WHERE subscription_tokens = $1"#,
-        &token
+) -> Result<Option<TokenRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        TokenRecord,
+        r#"
+        SELECT t.subscriber_id, t.created_at, s.status
+        FROM subscriptions_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.subscription_tokens = $1
+        "#,
+        token
     )
     .fetch_optional(&mut **trans)
     .await
     .map_err(|e| {
         tracing::error!("Failed trying to get subscriber id: {:?}", e);
         e
-    })?;
-    match subscriber_id {
-        Some(id) => Ok(id.subscriber_id),
-        None => Err(sqlx::Error::RowNotFound),
-    }
+    })
 }
 
 #[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, trans))]
@@ -64,7 +185,7 @@ pub async fn update_to_confirmed(
     subscriber_id: Uuid,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+        r#"UPDATE subscriptions SET status = 'confirmed', confirmed_at = now() WHERE id = $1"#,
         subscriber_id,
     )
     .execute(&mut **trans)
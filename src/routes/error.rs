@@ -0,0 +1,231 @@
+use axum::{
+    extract::rejection::{FormRejection, JsonRejection, PathRejection},
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::StatusCode;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::request_id::RequestId;
+
+/// Problem types are relative URIs so they stay stable across deployments.
+const PROBLEM_TYPE_PREFIX: &str = "/problems/";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub detail: String,
+}
+
+/// An `application/problem+json` (RFC 9457) error response.
+///
+/// Only `title`, `detail` and `errors` reach the client; `source` is logged
+/// together with the request id and never rendered.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    kind: &'static str,
+    title: &'static str,
+    detail: Option<String>,
+    errors: Vec<FieldError>,
+    extensions: Map<String, Value>,
+    source: Option<anyhow::Error>,
+}
+
+#[derive(Serialize)]
+struct ProblemDetails<'a> {
+    #[serde(rename = "type")]
+    kind: String,
+    title: &'a str,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    errors: &'a [FieldError],
+    request_id: &'a str,
+    #[serde(flatten)]
+    extensions: &'a Map<String, Value>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, kind: &'static str, title: &'static str) -> Self {
+        Self {
+            status,
+            kind,
+            title,
+            detail: None,
+            errors: vec![],
+            extensions: Map::new(),
+            source: None,
+        }
+    }
+
+    pub fn validation(errors: Vec<FieldError>) -> Self {
+        Self::new(
+            StatusCode::BAD_REQUEST,
+            "validation-error",
+            "Your request parameters didn't validate.",
+        )
+        .with_errors(errors)
+    }
+
+    /// A `500`, or a `503` when the cause is that no database connection
+    /// freed up in time (or the pool is closing during shutdown): the
+    /// request may well succeed if retried.
+    pub fn internal(source: anyhow::Error) -> Self {
+        let unavailable = source.chain().any(|e| {
+            matches!(
+                e.downcast_ref::<sqlx::Error>(),
+                Some(sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed)
+            )
+        });
+        if unavailable {
+            return Self::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "service-unavailable",
+                "The service is busy, please try again shortly.",
+            )
+            .with_source(source);
+        }
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal-error",
+            "Something went wrong on our side.",
+        )
+        .with_source(source)
+    }
+
+    pub fn invalid_body(status: StatusCode, detail: String) -> Self {
+        Self::new(
+            status,
+            "invalid-request-body",
+            "The request body could not be read.",
+        )
+        .with_detail(detail)
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_errors(mut self, errors: Vec<FieldError>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    pub fn with_extension(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.extensions.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn with_source(mut self, source: anyhow::Error) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn title(&self) -> &'static str {
+        self.title
+    }
+
+    pub fn type_uri(&self) -> String {
+        format!("{}{}", PROBLEM_TYPE_PREFIX, self.kind)
+    }
+}
+
+impl From<FormRejection> for ApiError {
+    fn from(rejection: FormRejection) -> Self {
+        ApiError::invalid_body(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::invalid_body(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        ApiError::new(
+            rejection.status(),
+            "invalid-path",
+            "The request path could not be read.",
+        )
+        .with_detail(rejection.body_text())
+    }
+}
+
+/// `404` for every path no route matches.
+pub async fn not_found() -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        "not-found",
+        "There is nothing at this path.",
+    )
+}
+
+/// `405` for a known path requested with a method it doesn't support.
+pub async fn method_not_allowed() -> ApiError {
+    ApiError::new(
+        StatusCode::METHOD_NOT_ALLOWED,
+        "method-not-allowed",
+        "This path does not support the request method.",
+    )
+}
+
+impl ApiError {
+    /// Logs the error with the request's id, including the full source chain,
+    /// and returns the id so it can be shown to the client.
+    pub fn log(&self) -> RequestId {
+        let request_id = RequestId::current().unwrap_or_else(RequestId::generate);
+
+        if self.status.is_server_error() {
+            tracing::error!(
+                request_id = %request_id,
+                problem_type = self.kind,
+                error.chain = ?self.source,
+                "Request failed: {}",
+                self.title
+            );
+        } else {
+            tracing::info!(
+                request_id = %request_id,
+                problem_type = self.kind,
+                error.chain = ?self.source,
+                "Request rejected: {}",
+                self.title
+            );
+        }
+        request_id
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let request_id = self.log();
+
+        let body = ProblemDetails {
+            kind: self.type_uri(),
+            title: self.title,
+            status: self.status.as_u16(),
+            detail: self.detail.as_deref(),
+            errors: &self.errors,
+            request_id: request_id.as_str(),
+            extensions: &self.extensions,
+        };
+
+        (
+            self.status,
+            [(CONTENT_TYPE, "application/problem+json")],
+            Json(body),
+        )
+            .into_response()
+    }
+}
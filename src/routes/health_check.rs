@@ -1,6 +1,30 @@
-use axum::response::IntoResponse;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde_json::json;
+
+use crate::{health::ReadinessStatus, startup::AppState};
 
 pub async fn health_check() -> impl IntoResponse {
     tracing::info!("Successful healthcheck");
     String::from("App is healthy!")
 }
+
+/// `GET /health/live`: the process is up and serving requests. Nothing else
+/// is checked, so a struggling dependency doesn't get the app restarted.
+pub async fn health_live() -> impl IntoResponse {
+    Json(json!({ "status": "live" }))
+}
+
+/// `GET /health/ready`: whether the app can do its job, with a breakdown per
+/// dependency. `503` when any check but the informational `workers` one is
+/// down.
+pub async fn health_ready(State(state): State<AppState>) -> impl IntoResponse {
+    let readiness = state
+        .health
+        .readiness(&state.pool, &state.email_client)
+        .await;
+    let status = match readiness.status {
+        ReadinessStatus::Ready => StatusCode::OK,
+        ReadinessStatus::Degraded => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(readiness))
+}
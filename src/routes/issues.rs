@@ -0,0 +1,239 @@
+use anyhow::Context;
+use axum::{
+    extract::{rejection::JsonRejection, Query, State},
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::StatusCode;
+use serde::Serialize;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    delivery::{load_issue, spawn_delivery, DeliveryError, PreparedIssue, SendOptions},
+    domain::SubscriberEmail,
+    email_client::{render_newsletter, EmailBody, RenderedEmail},
+    idempotency::report::{delivery_report, failed_recipients, to_csv},
+    redact::Pii,
+    routes::{
+        error::ApiError,
+        newsletters::{invalid_issue, send_accepted},
+        path::ApiPath,
+    },
+    startup::AppState,
+    templates::{
+        merge::{custom_field_schema, IssueTemplate, MergeFields},
+        TemplateError,
+    },
+    tracking,
+};
+
+#[derive(Error, Debug)]
+pub enum IssueError {
+    #[error("newsletter issue is unknown")]
+    UnknownIssue,
+    #[error("no seed addresses are configured")]
+    NoSeedAddresses,
+    #[error("the issue is invalid")]
+    InvalidIssue(#[source] TemplateError),
+    #[error(transparent)]
+    InvalidJson(#[from] JsonRejection),
+    #[error("unexpected error: `{0}`")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<IssueError> for ApiError {
+    fn from(e: IssueError) -> Self {
+        match e {
+            IssueError::UnknownIssue => ApiError::new(
+                StatusCode::NOT_FOUND,
+                "unknown-issue",
+                "There is no newsletter issue with this id.",
+            ),
+            IssueError::NoSeedAddresses => ApiError::new(
+                StatusCode::CONFLICT,
+                "no-seed-addresses",
+                "There are no seed addresses to send a test to.",
+            )
+            .with_detail("Configure `issues.seed_addresses` to enable test sends."),
+            IssueError::InvalidIssue(e) => invalid_issue(e),
+            IssueError::InvalidJson(rejection) => rejection.into(),
+            IssueError::UnexpectedError(e) => ApiError::internal(e),
+        }
+    }
+}
+
+impl From<DeliveryError> for IssueError {
+    fn from(e: DeliveryError) -> Self {
+        match e {
+            DeliveryError::InvalidIssue(e) => IssueError::InvalidIssue(e),
+            DeliveryError::UnexpectedError(e) => IssueError::UnexpectedError(e),
+        }
+    }
+}
+
+impl IntoResponse for IssueError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum TestSendStatus {
+    Sent,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+struct TestSendResult {
+    email: SubscriberEmail,
+    status: TestSendStatus,
+}
+
+/// Validates and renders `issue` exactly as `/publish` would, for the sample
+/// subscriber.
+async fn render_sample(state: &AppState, issue: &EmailBody) -> Result<RenderedEmail, IssueError> {
+    let schema = custom_field_schema(&state.pool)
+        .await
+        .context("Failed to get the custom field schema")?;
+    let sample = MergeFields::sample(&schema, &state.merge_links());
+    let template = IssueTemplate::compile(&issue.title, &issue.message, &sample)
+        .map_err(IssueError::InvalidIssue)?;
+    let email = render_newsletter(
+        &state.templates,
+        issue.list.as_deref(),
+        &template,
+        &sample,
+        None,
+    )
+    .context("Failed to render the issue")?;
+    Ok(email)
+}
+
+/// `POST /issues/preview`: the subject, HTML and text of an issue as the sample
+/// subscriber would receive it. Nothing is sent or stored.
+#[tracing::instrument(name = "Previewing an issue", skip(state, payload))]
+pub async fn preview_issue(
+    State(state): State<AppState>,
+    payload: Result<Json<EmailBody>, JsonRejection>,
+) -> Result<Response, IssueError> {
+    let Json(issue) = payload?;
+    let email = render_sample(&state, &issue).await?;
+    Ok(Json(email).into_response())
+}
+
+/// `POST /issues/test-send`: delivers an issue to the configured seed addresses
+/// only. Unlike `/publish`, no idempotency records or subscriber state are touched.
+#[tracing::instrument(name = "Test-sending an issue", skip(state, payload))]
+pub async fn test_send_issue(
+    State(state): State<AppState>,
+    payload: Result<Json<EmailBody>, JsonRejection>,
+) -> Result<Response, IssueError> {
+    let Json(issue) = payload?;
+    let email = render_sample(&state, &issue).await?;
+    if state.issues.seed_addresses.is_empty() {
+        return Err(IssueError::NoSeedAddresses);
+    }
+
+    let mut results = vec![];
+    for recipient in &state.issues.seed_addresses {
+        let status = match state.email_client.send_rendered(recipient, &email).await {
+            Ok(()) => TestSendStatus::Sent,
+            Err(e) => {
+                tracing::warn!("Failed to send a test email to {}: {:?}", Pii(recipient), e);
+                TestSendStatus::Failed
+            }
+        };
+        results.push(TestSendResult {
+            email: recipient.clone(),
+            status,
+        });
+    }
+
+    Ok(Json(results).into_response())
+}
+
+/// `GET /issues/{issue_id}/stats`: opens and clicks recorded for an issue.
+#[tracing::instrument(name = "Getting issue stats", skip(state))]
+pub async fn issue_stats(
+    State(state): State<AppState>,
+    ApiPath(issue_id): ApiPath<Uuid>,
+) -> Result<Response, IssueError> {
+    ensure_issue_exists(&state, issue_id).await?;
+    let stats = tracking::issue_stats(&state.pool, issue_id)
+        .await
+        .context("Failed to aggregate the issue stats")?;
+    Ok(Json(stats).into_response())
+}
+
+async fn ensure_issue_exists(state: &AppState, issue_id: Uuid) -> Result<(), IssueError> {
+    let exists = sqlx::query!("SELECT id FROM newsletter_issues WHERE id = $1", issue_id)
+        .fetch_optional(&state.pool)
+        .await
+        .context("Failed to look up the issue")?;
+    match exists {
+        Some(_) => Ok(()),
+        None => Err(IssueError::UnknownIssue),
+    }
+}
+
+/// `GET /issues/{issue_id}/report`: delivery counts, failure reasons and
+/// timing, from the persisted delivery records.
+#[tracing::instrument(name = "Getting issue delivery report", skip(state))]
+pub async fn issue_report(
+    State(state): State<AppState>,
+    ApiPath(issue_id): ApiPath<Uuid>,
+) -> Result<Response, IssueError> {
+    ensure_issue_exists(&state, issue_id).await?;
+    let report = delivery_report(&state.pool, issue_id)
+        .await
+        .context("Failed to build the delivery report")?;
+    Ok(Json(report).into_response())
+}
+
+/// `GET /issues/{issue_id}/report/failed`: the recipients whose delivery
+/// failed, as a CSV download. It lists subscriber emails and names, so it
+/// needs admin credentials.
+#[tracing::instrument(name = "Downloading failed recipients", skip(state))]
+pub async fn issue_failed_recipients(
+    State(state): State<AppState>,
+    ApiPath(issue_id): ApiPath<Uuid>,
+) -> Result<Response, IssueError> {
+    ensure_issue_exists(&state, issue_id).await?;
+    let recipients = failed_recipients(&state.pool, issue_id)
+        .await
+        .context("Failed to get the failed recipients")?;
+    Ok((
+        [
+            (CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"issue-{}-failed.csv\"", issue_id),
+            ),
+        ],
+        to_csv(&recipients).context("Failed to write the failed recipients as CSV")?,
+    )
+        .into_response())
+}
+
+/// `POST /issues/{issue_id}/resume`: continues an interrupted send in the
+/// background, retrying every delivery that isn't `Sent` yet, and answers
+/// `202 Accepted`. `?mode=bulk` or `?mode=individual` overrides
+/// `delivery.mode`.
+#[tracing::instrument(name = "Resuming an issue", skip(state))]
+pub async fn resume_issue(
+    State(state): State<AppState>,
+    ApiPath(issue_id): ApiPath<Uuid>,
+    Query(options): Query<SendOptions>,
+) -> Result<Response, IssueError> {
+    let body = load_issue(&state.pool, issue_id)
+        .await
+        .context("Failed to load the issue")?
+        .ok_or(IssueError::UnknownIssue)?;
+    let issue = PreparedIssue::prepare(&state.pool, body, &state.merge_links()).await?;
+    let mode = options.mode.unwrap_or(state.delivery.mode);
+    spawn_delivery(&state, issue_id, issue, mode);
+    Ok(send_accepted(issue_id))
+}
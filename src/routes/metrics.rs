@@ -0,0 +1,11 @@
+use axum::{extract::State, http::header::CONTENT_TYPE, response::IntoResponse};
+
+use crate::startup::AppState;
+
+/// `GET /metrics`, in the Prometheus text format.
+pub async fn render_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(&state.pool).await,
+    )
+}
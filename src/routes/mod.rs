@@ -1,4 +1,12 @@
 pub mod confirm;
+pub mod error;
 pub mod health_check;
+pub mod issues;
+pub mod metrics;
+pub mod negotiation;
 pub mod newsletters;
+pub mod path;
 pub mod subscriptions;
+pub mod templates;
+pub mod tracking;
+pub mod unsubscribe;
@@ -0,0 +1,125 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::FromRequestParts,
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        request::Parts,
+        HeaderMap,
+    },
+};
+
+/// Representation the client asked for, picked from `Accept`
+/// (falling back to the request's own `Content-Type`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Json,
+    Html,
+    Plain,
+}
+
+impl ResponseFormat {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let accept = headers
+            .get(ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+
+        match preferred(accept, &["application/json", "text/html", "text/plain"]) {
+            Some("application/json") => ResponseFormat::Json,
+            Some("text/html") => ResponseFormat::Html,
+            Some(_) => ResponseFormat::Plain,
+            None if is_json(headers) => ResponseFormat::Json,
+            None => ResponseFormat::Plain,
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for ResponseFormat
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ResponseFormat::from_headers(&parts.headers))
+    }
+}
+
+/// Whether the request body is JSON (`application/json` or any `+json` type).
+pub fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|mime| {
+            let mime = mime.trim();
+            mime.eq_ignore_ascii_case("application/json") || mime.ends_with("+json")
+        })
+        .unwrap_or(false)
+}
+
+/// Picks the supported media type with the highest `q` from an `Accept` header.
+/// Wildcards only match when nothing more specific was listed, so `*/*` alone
+/// returns `None` and lets the caller apply its default.
+fn preferred<'a>(accept: &str, supported: &[&'a str]) -> Option<&'a str> {
+    let mut best: Option<(&'a str, f32)> = None;
+
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let media = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if q <= 0.0 {
+            continue;
+        }
+
+        let matched = supported.iter().find(|s| match media.strip_suffix('*') {
+            Some(prefix) if media != "*/*" => s.starts_with(prefix),
+            _ => media == **s,
+        });
+        if let Some(matched) = matched {
+            if best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((matched, q));
+            }
+        }
+    }
+
+    best.map(|(media, _)| media)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::preferred;
+
+    const SUPPORTED: &[&str] = &["application/json", "text/html"];
+
+    #[test]
+    fn exact_match_is_preferred() {
+        assert_eq!(
+            preferred("application/json", SUPPORTED),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn highest_quality_wins() {
+        assert_eq!(
+            preferred("application/json;q=0.5, text/html", SUPPORTED),
+            Some("text/html")
+        );
+    }
+
+    #[test]
+    fn bare_wildcard_defers_to_the_caller() {
+        assert_eq!(preferred("*/*", SUPPORTED), None);
+        assert_eq!(preferred("", SUPPORTED), None);
+    }
+
+    #[test]
+    fn type_wildcard_matches_subtypes() {
+        assert_eq!(preferred("text/*", SUPPORTED), Some("text/html"));
+    }
+}
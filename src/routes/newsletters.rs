@@ -1,209 +1,149 @@
 use anyhow::Context;
 use axum::{
-    extract::{self, State},
+    extract::{self, Query, State},
     response::{IntoResponse, Response},
+    Json,
 };
-use hyper::StatusCode;
-use sqlx::PgPool;
+use hyper::{header::LOCATION, HeaderMap, StatusCode};
+use serde::Serialize;
 use thiserror::Error;
 use uuid::Uuid;
 
 use crate::{
-    domain::{SubscriberEmail, SubscriberName},
-    email_client::EmailBody,
-    idempotency::persistance::{
-        generate_idempotency_key, get_existing_job, update_job_status, EmailStatus,
+    delivery::{
+        create_issue, spawn_delivery, DeliveryError, PreparedIssue, SendOptions, StoredIssue,
     },
+    email_client::EmailBody,
+    routes::error::ApiError,
     startup::AppState,
+    templates::TemplateError,
 };
 
-#[derive(Debug)]
-struct ConfirmedSubscriber {
-    email: String,
-    name: String,
-    id: Uuid,
-}
+/// Header a client sets to make retrying `POST /publish` safe.
+const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
 
 #[derive(Debug, Error)]
 pub enum PublishError {
     #[error("test err")]
     TestErr,
+    #[error("the idempotency key is invalid")]
+    InvalidIdempotencyKey,
+    #[error("the idempotency key was already used for a different issue")]
+    IdempotencyKeyReused,
+    #[error("the issue is invalid")]
+    InvalidIssue(#[source] TemplateError),
     #[error("generic error")]
     UnexpectedError(#[from] anyhow::Error),
 }
 
-impl IntoResponse for PublishError {
-    fn into_response(self) -> Response {
-        let response = match self {
-            PublishError::TestErr => (StatusCode::BAD_REQUEST, "test err".to_string()),
-            PublishError::UnexpectedError(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
-        };
-        response.into_response()
+impl From<PublishError> for ApiError {
+    fn from(e: PublishError) -> Self {
+        match e {
+            PublishError::TestErr => ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid-newsletter",
+                "The newsletter could not be published.",
+            ),
+            PublishError::InvalidIdempotencyKey => ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid-idempotency-key",
+                "The Idempotency-Key header is not valid.",
+            )
+            .with_detail(format!(
+                "It must be between 1 and {} visible ASCII characters.",
+                MAX_IDEMPOTENCY_KEY_LENGTH
+            )),
+            PublishError::IdempotencyKeyReused => ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "idempotency-key-reused",
+                "The Idempotency-Key was already used for a different issue.",
+            ),
+            PublishError::InvalidIssue(e) => invalid_issue(e),
+            PublishError::UnexpectedError(e) => ApiError::internal(e),
+        }
     }
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let query = sqlx::query_as!(
-        ConfirmedSubscriber,
-        r#"
-        SELECT email, name, id
-        FROM subscriptions
-        WHERE status = 'confirmed'
-        "#,
+/// Problem details for an issue whose subject or message doesn't compile or
+/// references unknown merge fields.
+pub fn invalid_issue(e: TemplateError) -> ApiError {
+    ApiError::new(
+        StatusCode::BAD_REQUEST,
+        "invalid-issue",
+        "The issue does not compile against the merge field schema.",
     )
-    .fetch_all(pool)
-    .await?;
+    .with_detail(e.describe())
+}
 
-    let query = query
-        .iter()
-        // shit is cursed but hey, it is what it is
-        // cant convert ConfirmedSubscriber into SubscriberEmail
-        // since then 'a would be tied to lifetime of this fn
-        // which is too short
-        .map(|r| match SubscriberEmail::parse(&r.email) {
-            Ok(email) => Ok(ConfirmedSubscriber {
-                email: email.as_ref().to_string(),
-                name: SubscriberName::parse(&r.name)
-                    .unwrap_or_default()
-                    .as_ref()
-                    .to_string(),
-                id: r.id,
-            }),
-            Err(_) => Err(anyhow::anyhow!("Invalid email")),
-        })
-        .collect::<Vec<Result<ConfirmedSubscriber, anyhow::Error>>>();
+/// Body of the `202 Accepted` answering a send that now runs in the
+/// background.
+#[derive(Debug, Serialize)]
+pub struct SendAccepted {
+    pub issue_id: Uuid,
+}
 
-    Ok(query)
+/// `202 Accepted` for a send of `issue_id`, pointing at the report that
+/// shows its progress.
+pub fn send_accepted(issue_id: Uuid) -> Response {
+    (
+        StatusCode::ACCEPTED,
+        [(LOCATION, format!("/issues/{}/report", issue_id))],
+        Json(SendAccepted { issue_id }),
+    )
+        .into_response()
 }
 
+impl From<DeliveryError> for PublishError {
+    fn from(e: DeliveryError) -> Self {
+        match e {
+            DeliveryError::InvalidIssue(e) => PublishError::InvalidIssue(e),
+            DeliveryError::UnexpectedError(e) => PublishError::UnexpectedError(e),
+        }
+    }
+}
+
+impl IntoResponse for PublishError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+/// The `Idempotency-Key` header, if the client sent one.
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<&str>, PublishError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    let key = value
+        .to_str()
+        .map_err(|_| PublishError::InvalidIdempotencyKey)?;
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(PublishError::InvalidIdempotencyKey);
+    }
+    Ok(Some(key))
+}
+
+/// `POST /publish`: stores the issue and answers `202 Accepted` with its id
+/// while it's delivered in the background. Sent again with the same
+/// `Idempotency-Key` and payload, it finishes the same issue instead of
+/// publishing a second one.
 pub async fn publish_newsletter(
     State(state): State<AppState>,
+    Query(options): Query<SendOptions>,
+    headers: HeaderMap,
     extract::Json(payload): extract::Json<EmailBody>,
 ) -> Result<Response, PublishError> {
-    let recipients = get_confirmed_subscribers(&state.pool)
+    let key = idempotency_key(&headers)?;
+    let issue = PreparedIssue::prepare(&state.pool, payload, &state.merge_links()).await?;
+    let issue_id = match create_issue(&state.pool, &issue.body, key)
         .await
-        .context("Failed to get confirmed subscribers")?;
-
-    let mut count = 0;
-    let mut successes = vec![];
-    let mut errors = vec![];
-
-    for recipient in &recipients {
-        match &recipient {
-            Ok(subscriber) => {
-                let key = generate_idempotency_key(&subscriber.name, &payload.message);
-                let job = get_existing_job(&state.pool, &key)
-                    .await
-                    .context("Failed to get existing job")?;
-
-                match job {
-                    Some(job) if matches!(job.status, EmailStatus::Sent) => {
-                        tracing::info!("Skipping subscriber, email already sent: {:?}", subscriber);
-                        continue;
-                    }
-                    Some(mut job) if matches!(job.status, EmailStatus::Pending) => {
-                        job.attempts += 1;
-                        job.updated_at = chrono::Utc::now();
-                        sqlx::query!(
-                            r#"
-                            UPDATE idempotency
-                            SET attempts = $1, updated_at = $2
-                            WHERE idempotency_key = $3
-                            "#,
-                            job.attempts,
-                            job.updated_at,
-                            key
-                        )
-                        .execute(&state.pool)
-                        .await
-                        .context("Failed to update job")?;
-
-                        return Ok(StatusCode::OK.into_response());
-                    }
-                    Some(_) if matches!(job.unwrap().status, EmailStatus::Failed) => {
-                        sqlx::query!(
-                            r#"
-                            UPDATE idempotency
-                            SET status = $1
-                            WHERE idempotency_key = $2
-                            "#,
-                            EmailStatus::Pending as EmailStatus,
-                            key
-                        )
-                        .execute(&state.pool)
-                        .await
-                        .context("Failed to update job")?;
-                    }
-                    None => {
-                        let _ = sqlx::query!(
-                            r#"
-                            INSERT INTO idempotency (user_id, idempotency_key, status, attempts, created_at, updated_at)
-                            VALUES ($1, $2, $3, $4, $5, $6)
-                            "#,
-                            subscriber.id,
-                            key,
-                            EmailStatus::Pending as EmailStatus,
-                            0,
-                            chrono::Utc::now(),
-                            chrono::Utc::now()
-                        )
-                        .execute(&state.pool)
-                        .await;
-                    }
-                    _ => {}
-                }
-
-                match state
-                    .email_client
-                    .send_newsletter(
-                        &subscriber.email,
-                        &payload.title,
-                        &subscriber.name,
-                        "newsletter",
-                        &key,
-                        &state,
-                    )
-                    .await
-                    .with_context(|| format!("Failed to send email to recipient: {:?}", recipient))
-                {
-                    Ok(success) => {
-                        // Update job status to Sent
-                        if let Err(e) =
-                            update_job_status(&state.pool, &key, EmailStatus::Sent).await
-                        {
-                            tracing::error!("Failed to update job status: {:?}", e);
-                        }
-                        successes.push(success);
-                    }
-                    Err(e) => {
-                        // Update job status to Failed
-                        if let Err(update_err) =
-                            update_job_status(&state.pool, &key, EmailStatus::Failed).await
-                        {
-                            tracing::error!("Failed to update job status: {:?}", update_err);
-                        }
-                        errors.push(e);
-                    }
-                }
-            }
-            Err(e) => {
-                tracing::warn!(
-                    "Skipping subscriber, their stored details are invalid: {:?}",
-                    e
-                );
-            }
-        }
-        count += 1;
-    }
-
-    let response_message = format!(
-        "Sent updates to {} subscribers, errored with: {}",
-        count,
-        errors.len()
-    );
+        .context("Failed to store the newsletter issue")?
+    {
+        StoredIssue::New(id) | StoredIssue::Existing(id) => id,
+        StoredIssue::KeyReused => return Err(PublishError::IdempotencyKeyReused),
+    };
 
-    Ok((StatusCode::OK, response_message).into_response())
+    let mode = options.mode.unwrap_or(state.delivery.mode);
+    spawn_delivery(&state, issue_id, issue, mode);
+    Ok(send_accepted(issue_id))
 }
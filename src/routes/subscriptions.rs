@@ -1,43 +1,98 @@
+use std::collections::BTreeMap;
+
 use anyhow::Context;
 use axum::{
-    extract::State,
+    extract::{
+        rejection::{FormRejection, JsonRejection},
+        FromRequest, Request, State,
+    },
     response::{IntoResponse, Response},
-    Form,
+    Form, Json,
 };
 use hyper::StatusCode;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use serde::Deserialize;
-use sqlx::{PgPool, Postgres, Transaction};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
 use thiserror::Error;
 use uuid::Uuid;
 
 use crate::{
-    domain::{ParseError, Subscriber},
+    configuration::CustomFieldSettings,
+    deliverability::DomainStatus,
+    domain::{ParseError, Subscriber, SubscriberEmail},
     email_client::EmailClient,
+    metrics::FunnelStage,
+    pages::{PageOutcome, Pages},
+    redact::Pii,
+    routes::{
+        error::{ApiError, FieldError},
+        negotiation::{is_json, ResponseFormat},
+        unsubscribe::generate_unsubscribe_token,
+    },
     startup::AppState,
+    templates::TemplateRegistry,
 };
 
 #[derive(Error, Debug)]
 pub enum SubscribeError {
     #[error("validation failed for string `{0}`")]
     ValidationError(#[from] ParseError),
+    #[error("email domain `{domain}` cannot receive email")]
+    UndeliverableDomain {
+        domain: String,
+        suggestion: Option<String>,
+    },
+    #[error("invalid custom fields")]
+    InvalidCustomFields(Vec<FieldError>),
+    #[error(transparent)]
+    InvalidForm(#[from] FormRejection),
+    #[error(transparent)]
+    InvalidJson(#[from] JsonRejection),
     #[error("unexpected error: `{0}`")]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl From<SubscribeError> for ApiError {
+    fn from(e: SubscribeError) -> Self {
+        match e {
+            SubscribeError::ValidationError(e) => ApiError::validation(vec![FieldError {
+                field: e.field().to_string(),
+                detail: e.to_string(),
+            }]),
+            SubscribeError::UndeliverableDomain { domain, suggestion } => {
+                let detail = match &suggestion {
+                    Some(suggestion) => format!(
+                        "Email domain `{}` cannot receive email. Did you mean {}?",
+                        domain, suggestion
+                    ),
+                    None => format!("Email domain `{}` cannot receive email.", domain),
+                };
+                let error = ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "undeliverable-email-domain",
+                    "The email address cannot receive email.",
+                )
+                .with_errors(vec![FieldError {
+                    field: "email".to_string(),
+                    detail,
+                }]);
+                match suggestion {
+                    Some(suggestion) => error.with_extension("suggestion", suggestion),
+                    None => error,
+                }
+            }
+            SubscribeError::InvalidCustomFields(errors) => ApiError::validation(errors),
+            SubscribeError::InvalidForm(rejection) => rejection.into(),
+            SubscribeError::InvalidJson(rejection) => rejection.into(),
+            SubscribeError::UnexpectedError(e) => ApiError::internal(e),
+        }
+    }
+}
+
 impl IntoResponse for SubscribeError {
     fn into_response(self) -> Response {
-        let response = match self {
-            SubscribeError::ValidationError(e) => (StatusCode::BAD_REQUEST, e.to_string()),
-            SubscribeError::UnexpectedError(e) => match e.downcast_ref::<sqlx::Error>() {
-                Some(sqlx::Error::PoolTimedOut) => (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Database error".to_string(),
-                ),
-                _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
-            },
-        };
-        response.into_response()
+        ApiError::from(self).into_response()
     }
 }
 
@@ -45,31 +100,144 @@ impl IntoResponse for SubscribeError {
 pub struct SubscribeForm {
     pub email: String,
     pub name: String,
+    /// Extra attributes available to issues as `{{custom.<name>}}` merge
+    /// fields, limited to `custom_fields.names`.
+    #[serde(default)]
+    pub custom_fields: BTreeMap<String, String>,
+}
+
+impl TryFrom<SubscribeForm> for Subscriber {
+    type Error = ParseError;
+
+    fn try_from(form: SubscribeForm) -> Result<Self, Self::Error> {
+        let mut subscriber = Subscriber::new(&form.name, &form.email)?;
+        subscriber.custom_fields = form.custom_fields;
+        Ok(subscriber)
+    }
+}
+
+/// Extracts and validates a subscriber from a form or JSON body,
+/// so handlers only see valid data.
+impl<S> FromRequest<S> for Subscriber
+where
+    S: Send + Sync,
+{
+    type Rejection = SubscribeError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let form = if is_json(req.headers()) {
+            let Json(form) = Json::<SubscribeForm>::from_request(req, state).await?;
+            form
+        } else {
+            let Form(form) = Form::<SubscribeForm>::from_request(req, state).await?;
+            form
+        };
+        Ok(Subscriber::try_from(form)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+}
+
+#[derive(Debug, Serialize)]
+struct StatusBody {
+    status: SubscriptionStatus,
+}
+
+impl SubscriptionStatus {
+    fn page(self) -> Option<PageOutcome> {
+        match self {
+            SubscriptionStatus::PendingConfirmation => None,
+            SubscriptionStatus::Confirmed => Some(PageOutcome::Confirmed),
+            SubscriptionStatus::Unsubscribed => Some(PageOutcome::Unsubscribed),
+        }
+    }
+
+    /// `200 OK`, with a `{"status": ...}` body for JSON clients, the matching
+    /// landing page for browsers and an empty body otherwise.
+    pub fn respond(self, format: ResponseFormat, pages: &Pages) -> Response {
+        match (format, self.page()) {
+            (ResponseFormat::Json, _) => Json(StatusBody { status: self }).into_response(),
+            (ResponseFormat::Html, Some(outcome)) => pages.respond(outcome, StatusCode::OK, ""),
+            _ => StatusCode::OK.into_response(),
+        }
+    }
+}
+
+pub async fn subscribe(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    subscriber: Result<Subscriber, SubscribeError>,
+) -> Result<Response, SubscribeError> {
+    match subscriber {
+        // Always reported as pending, even for confirmed addresses, so the
+        // response doesn't reveal who is already on the list.
+        Ok(subscriber) => match add_subscriber(&state, subscriber).await {
+            Ok(()) => Ok(SubscriptionStatus::PendingConfirmation.respond(format, &state.pages)),
+            Err(e) if format == ResponseFormat::Html => {
+                Ok(state.pages.respond_error(PageOutcome::Error, e.into()))
+            }
+            Err(e) => Err(e),
+        },
+        Err(e) if format == ResponseFormat::Html => {
+            Ok(state.pages.respond_error(PageOutcome::Error, e.into()))
+        }
+        Err(e) => Err(e),
+    }
 }
 
 #[tracing::instrument(
     name = "Adding new subscriber",
-    skip(sub_form, state),
+    skip(subscriber, state),
     fields(
-    subscriber_email = %sub_form.email,
-    subscriber_name = %sub_form.name,
+    subscriber_email = %Pii(&subscriber.email),
+    subscriber_name = %Pii(&subscriber.name),
 ))]
-pub async fn subscribe(
-    State(state): State<AppState>,
-    Form(sub_form): Form<SubscribeForm>,
-) -> Result<impl IntoResponse, SubscribeError> {
+async fn add_subscriber(state: &AppState, subscriber: Subscriber) -> Result<(), SubscribeError> {
+    check_custom_fields(&state.custom_fields, &subscriber.custom_fields)?;
+
+    // Resolved before opening the transaction so a slow DNS lookup doesn't
+    // hold a pooled connection.
+    let domain = subscriber.email.domain();
+    if let DomainStatus::Undeliverable { suggestion } = state.domain_checker.check(domain).await {
+        return Err(SubscribeError::UndeliverableDomain {
+            domain: domain.to_string(),
+            suggestion,
+        });
+    }
+
     let mut tx = state
         .pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let subscriber = Subscriber::new(&sub_form.name, &sub_form.email)?;
-
-    let uuid = subscriber
-        .try_insert(&mut tx)
+    // Subscribing twice looks exactly like subscribing once to the caller,
+    // so the endpoint can't be used to probe who is on the list.
+    let unsubscribe_token = generate_unsubscribe_token();
+    let inserted = subscriber
+        .try_insert(&unsubscribe_token, &mut tx)
         .await
         .context("Failed to insert new subscriber in the database.")?;
+    let (uuid, unsubscribe_token) = match inserted {
+        Some(uuid) => (uuid, unsubscribe_token),
+        None => {
+            let existing = get_existing_subscriber(&subscriber.email, &mut tx)
+                .await
+                .context("Failed to fetch the existing subscriber.")?;
+            if existing.status == "confirmed" {
+                tracing::info!("Subscriber is already confirmed, nothing to do");
+                return Ok(());
+            }
+            tracing::info!("Subscriber is still pending, re-sending the confirmation email");
+            (existing.id, existing.unsubscribe_token)
+        }
+    };
 
     let subsciption_token = generate_subscription_token();
 
@@ -77,15 +245,87 @@ pub async fn subscribe(
         .await
         .context("Failed to store the confirmation token for a new subscriber.")?;
 
-    send_confirmation(&state.email_client, &subscriber, &subsciption_token)
-        .await
-        .context("Failed to send a confirmation email.")?;
+    send_confirmation(
+        &state.email_client,
+        &state.templates,
+        &state.base_url,
+        &subscriber,
+        &subsciption_token,
+        &unsubscribe_token,
+    )
+    .await
+    .context("Failed to send a confirmation email.")?;
 
     tx.commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
+    if inserted.is_some() {
+        state.metrics.funnel(FunnelStage::Subscribed);
+    }
 
-    Ok(StatusCode::OK.into_response())
+    Ok(())
+}
+
+/// Rejects custom fields outside the configured schema, too many of them and
+/// overlong values.
+fn check_custom_fields(
+    settings: &CustomFieldSettings,
+    fields: &BTreeMap<String, String>,
+) -> Result<(), SubscribeError> {
+    if fields.len() > settings.names.len() {
+        return Err(SubscribeError::InvalidCustomFields(vec![FieldError {
+            field: "custom_fields".to_string(),
+            detail: format!(
+                "At most {} custom fields are accepted.",
+                settings.names.len()
+            ),
+        }]));
+    }
+
+    let errors: Vec<FieldError> = fields
+        .iter()
+        .filter_map(|(name, value)| {
+            let detail = if !settings.names.contains(name) {
+                "Unknown custom field.".to_string()
+            } else if value.chars().count() > settings.max_value_length {
+                format!(
+                    "Must be at most {} characters long.",
+                    settings.max_value_length
+                )
+            } else {
+                return None;
+            };
+            Some(FieldError {
+                field: format!("custom_fields.{}", name),
+                detail,
+            })
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(SubscribeError::InvalidCustomFields(errors))
+    }
+}
+
+#[derive(Debug)]
+struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
+    unsubscribe_token: String,
+}
+
+async fn get_existing_subscriber(
+    email: &SubscriberEmail,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<ExistingSubscriber, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"SELECT id, status, unsubscribe_token FROM subscriptions WHERE email = $1"#,
+        email.as_ref()
+    )
+    .fetch_one(&mut **transaction)
+    .await
 }
 
 async fn store_token(
@@ -108,37 +348,49 @@ async fn store_token(
     Ok(())
 }
 
-async fn send_confirmation<'a>(
+async fn send_confirmation(
     client: &EmailClient,
-    subscriber: &Subscriber<'a>,
+    templates: &TemplateRegistry,
+    base_url: &str,
+    subscriber: &Subscriber,
     token: &str,
+    unsubscribe_token: &str,
 ) -> Result<(), SubscribeError> {
     let confirmation_link = format!(
-        "http://localhost:8000/subscribe/confirm?subscription_token={}",
-        &token
-    );
-    println!("Confirmation link: {}", confirmation_link);
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
+        "{}/subscribe/confirm?subscription_token={}",
+        base_url, &token
     );
-    let html_body = format!(
-        "Welcome to our newsletter!<br />\
-Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
+    let unsubscribe_link = format!(
+        "{}/unsubscribe?unsubscribe_token={}",
+        base_url, unsubscribe_token
     );
     let subject = "Welcome!";
+    let data = json!({
+        "title": subject,
+        "subscriber_name": &subscriber.name,
+        "confirmation_link": confirmation_link,
+        "unsubscribe_link": unsubscribe_link,
+        "privacy_policy": "#",
+    });
+    let html_body = templates
+        .render("confirmation.html", None, &data)
+        .context("Failed to render the confirmation email")?;
+    let plain_body = templates
+        .render("confirmation.txt", None, &data)
+        .context("Failed to render the confirmation email")?;
 
     let _ = client
-        .send_email_example(&subscriber.email, &subject, &html_body, &plain_body)
+        .send_email_example(&subscriber.email, subject, &html_body, &plain_body)
         .await;
     Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub const SUBSCRIPTION_TOKEN_LENGTH: usize = 25;
+
+pub(crate) fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
-        .take(25)
+        .take(SUBSCRIPTION_TOKEN_LENGTH)
         .collect()
 }
@@ -0,0 +1,68 @@
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::StatusCode;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{routes::error::ApiError, startup::AppState, templates::TemplateError};
+
+#[derive(Debug, Deserialize)]
+pub struct TemplateBody {
+    name: String,
+    list: Option<String>,
+    body: String,
+}
+
+#[derive(Error, Debug)]
+pub enum SaveTemplateError {
+    #[error(transparent)]
+    InvalidTemplate(#[from] TemplateError),
+    #[error(transparent)]
+    InvalidJson(#[from] JsonRejection),
+}
+
+impl From<SaveTemplateError> for ApiError {
+    fn from(e: SaveTemplateError) -> Self {
+        match e {
+            SaveTemplateError::InvalidTemplate(
+                e @ (TemplateError::Compile(..) | TemplateError::Render(..)),
+            ) => ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid-template",
+                "The template does not compile or render.",
+            )
+            .with_detail(e.describe()),
+            SaveTemplateError::InvalidTemplate(e) => ApiError::internal(e.into()),
+            SaveTemplateError::InvalidJson(rejection) => rejection.into(),
+        }
+    }
+}
+
+impl IntoResponse for SaveTemplateError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+/// `POST /templates`: validates the template and stores it as a new version,
+/// so a broken template is rejected here rather than in the middle of a send.
+#[tracing::instrument(name = "Saving an email template", skip(state, payload))]
+pub async fn save_template(
+    State(state): State<AppState>,
+    payload: Result<Json<TemplateBody>, JsonRejection>,
+) -> Result<Response, SaveTemplateError> {
+    let Json(template) = payload?;
+    let saved = state
+        .templates
+        .save(
+            &state.pool,
+            &template.name,
+            template.list.as_deref(),
+            &template.body,
+        )
+        .await?;
+    Ok((StatusCode::CREATED, Json(saved)).into_response())
+}
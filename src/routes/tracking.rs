@@ -0,0 +1,200 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    pages::PageOutcome,
+    routes::{error::ApiError, negotiation::ResponseFormat, path::ApiPath},
+    startup::AppState,
+    tracking::{record_event, EmailEventKind, Tracker},
+};
+
+/// A transparent 1x1 GIF.
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(Error, Debug)]
+pub enum TrackingError {
+    #[error("tracking is disabled")]
+    Disabled,
+    #[error("tracking signature is invalid")]
+    InvalidSignature,
+    #[error("unsubscribe token is unknown")]
+    UnknownToken,
+    #[error("unexpected error: `{0}`")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<TrackingError> for ApiError {
+    fn from(e: TrackingError) -> Self {
+        match e {
+            TrackingError::Disabled => ApiError::new(
+                StatusCode::NOT_FOUND,
+                "tracking-disabled",
+                "Tracking is disabled.",
+            ),
+            TrackingError::InvalidSignature => ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid-tracking-signature",
+                "The tracking link is not valid.",
+            ),
+            TrackingError::UnknownToken => ApiError::new(
+                StatusCode::NOT_FOUND,
+                "unknown-unsubscribe-token",
+                "The link is not valid.",
+            ),
+            TrackingError::UnexpectedError(e) => ApiError::internal(e),
+        }
+    }
+}
+
+impl IntoResponse for TrackingError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenParams {
+    sig: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClickParams {
+    url: String,
+    sig: String,
+}
+
+fn tracker(state: &AppState) -> Result<&Tracker, TrackingError> {
+    state.tracker.as_ref().ok_or(TrackingError::Disabled)
+}
+
+/// `GET /track/open/{issue_id}/{subscriber_id}`, the open pixel.
+#[tracing::instrument(name = "Tracking an open", skip(state, params))]
+pub async fn track_open(
+    State(state): State<AppState>,
+    ApiPath((issue_id, subscriber_id)): ApiPath<(Uuid, Uuid)>,
+    Query(params): Query<OpenParams>,
+) -> Result<Response, TrackingError> {
+    let parts = ["open", &issue_id.to_string(), &subscriber_id.to_string()];
+    if !tracker(&state)?.verify(&parts, &params.sig) {
+        return Err(TrackingError::InvalidSignature);
+    }
+    record_event(
+        &state.pool,
+        issue_id,
+        subscriber_id,
+        EmailEventKind::Open,
+        None,
+    )
+    .await
+    .context("Failed to record an open")?;
+    Ok((
+        [(CONTENT_TYPE, "image/gif"), (CACHE_CONTROL, "no-store")],
+        PIXEL,
+    )
+        .into_response())
+}
+
+/// `GET /track/click/{issue_id}/{subscriber_id}?url=...`, the redirect every
+/// link in a tracked issue goes through.
+#[tracing::instrument(name = "Tracking a click", skip(state, params))]
+pub async fn track_click(
+    State(state): State<AppState>,
+    ApiPath((issue_id, subscriber_id)): ApiPath<(Uuid, Uuid)>,
+    Query(params): Query<ClickParams>,
+) -> Result<Response, TrackingError> {
+    let parts = [
+        "click",
+        &issue_id.to_string(),
+        &subscriber_id.to_string(),
+        &params.url,
+    ];
+    if !tracker(&state)?.verify(&parts, &params.sig) {
+        return Err(TrackingError::InvalidSignature);
+    }
+    record_event(
+        &state.pool,
+        issue_id,
+        subscriber_id,
+        EmailEventKind::Click,
+        Some(&params.url),
+    )
+    .await
+    .context("Failed to record a click")?;
+    Ok(Redirect::to(&params.url).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OptOutParams {
+    unsubscribe_token: String,
+}
+
+#[derive(Debug, Serialize)]
+struct OptOutBody {
+    tracking_opt_out: bool,
+}
+
+/// `GET /tracking/opt-out?unsubscribe_token=...`: asks the subscriber to
+/// confirm, so link scanners following the link change nothing.
+#[tracing::instrument(name = "Confirming a tracking opt-out", skip(state, params))]
+pub async fn opt_out_form(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Query(params): Query<OptOutParams>,
+) -> Result<Response, TrackingError> {
+    let known = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM subscriptions WHERE unsubscribe_token = $1) AS "known!""#,
+        params.unsubscribe_token
+    )
+    .fetch_one(&state.pool)
+    .await
+    .context("Failed to look up the unsubscribe token")?;
+    match (known, format) {
+        (true, _) => Ok(state
+            .pages
+            .respond(PageOutcome::ConfirmOptOut, StatusCode::OK, "")),
+        (false, ResponseFormat::Html) => Ok(state
+            .pages
+            .respond_error(PageOutcome::Error, TrackingError::UnknownToken.into())),
+        (false, _) => Err(TrackingError::UnknownToken),
+    }
+}
+
+/// `POST /tracking/opt-out?unsubscribe_token=...`: stops tracking a
+/// subscriber without unsubscribing them.
+#[tracing::instrument(name = "Opting out of tracking", skip(state, params))]
+pub async fn opt_out(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Query(params): Query<OptOutParams>,
+) -> Result<Response, TrackingError> {
+    let updated = sqlx::query!(
+        r#"UPDATE subscriptions SET tracking_opt_out = true WHERE unsubscribe_token = $1"#,
+        params.unsubscribe_token
+    )
+    .execute(&state.pool)
+    .await
+    .context("Failed to opt the subscriber out of tracking")?;
+    if updated.rows_affected() == 0 {
+        return Err(TrackingError::UnknownToken);
+    }
+    Ok(match format {
+        ResponseFormat::Json => Json(OptOutBody {
+            tracking_opt_out: true,
+        })
+        .into_response(),
+        _ => (StatusCode::OK, "You will no longer be tracked.").into_response(),
+    })
+}
@@ -0,0 +1,131 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+};
+use hyper::StatusCode;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::Deserialize;
+use sqlx::PgPool;
+use thiserror::Error;
+
+use crate::{
+    metrics::FunnelStage,
+    pages::PageOutcome,
+    routes::{error::ApiError, negotiation::ResponseFormat, subscriptions::SubscriptionStatus},
+    startup::AppState,
+};
+
+pub const UNSUBSCRIBE_TOKEN_LENGTH: usize = 32;
+
+/// The token in a subscriber's unsubscribe and tracking opt-out links. It
+/// lives as long as the subscription, unlike a confirmation token.
+pub(crate) fn generate_unsubscribe_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(UNSUBSCRIBE_TOKEN_LENGTH)
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnsubscribeParams {
+    unsubscribe_token: String,
+}
+
+#[derive(Error, Debug)]
+pub enum UnsubscribeError {
+    #[error("unsubscribe token is unknown")]
+    UnknownToken,
+    #[error("unexpected error: `{0}`")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<UnsubscribeError> for ApiError {
+    fn from(e: UnsubscribeError) -> Self {
+        match e {
+            UnsubscribeError::UnknownToken => ApiError::new(
+                StatusCode::NOT_FOUND,
+                "unknown-unsubscribe-token",
+                "The unsubscribe link is not valid.",
+            ),
+            UnsubscribeError::UnexpectedError(e) => ApiError::internal(e),
+        }
+    }
+}
+
+impl IntoResponse for UnsubscribeError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+/// `GET /unsubscribe?unsubscribe_token=...` from the link in every newsletter.
+/// Only asks for confirmation: mail scanners prefetch links, so the
+/// subscription is left alone until the form is posted.
+#[tracing::instrument(name = "Confirming an unsubscribe", skip(state, params))]
+pub async fn unsubscribe_form(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Query(params): Query<UnsubscribeParams>,
+) -> Result<Response, UnsubscribeError> {
+    match ensure_known_token(&state.pool, &params.unsubscribe_token).await {
+        Ok(()) => Ok(state
+            .pages
+            .respond(PageOutcome::ConfirmUnsubscribe, StatusCode::OK, "")),
+        Err(e) if format == ResponseFormat::Html => {
+            Ok(state.pages.respond_error(PageOutcome::Error, e.into()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// `POST /unsubscribe?unsubscribe_token=...`, from the confirmation form or
+/// an RFC 8058 one-click unsubscribe.
+#[tracing::instrument(name = "Unsubscribing a subscriber", skip(state, params))]
+pub async fn unsubscribe(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Query(params): Query<UnsubscribeParams>,
+) -> Result<Response, UnsubscribeError> {
+    match mark_unsubscribed(&state.pool, &params.unsubscribe_token).await {
+        Ok(()) => {
+            state.metrics.funnel(FunnelStage::Unsubscribed);
+            Ok(SubscriptionStatus::Unsubscribed.respond(format, &state.pages))
+        }
+        Err(e) if format == ResponseFormat::Html => {
+            Ok(state.pages.respond_error(PageOutcome::Error, e.into()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn ensure_known_token(pool: &PgPool, token: &str) -> Result<(), UnsubscribeError> {
+    let known = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM subscriptions WHERE unsubscribe_token = $1) AS "known!""#,
+        token
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look up the unsubscribe token")?;
+
+    if !known {
+        return Err(UnsubscribeError::UnknownToken);
+    }
+    Ok(())
+}
+
+async fn mark_unsubscribed(pool: &PgPool, token: &str) -> Result<(), UnsubscribeError> {
+    let updated = sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE unsubscribe_token = $1"#,
+        token
+    )
+    .execute(pool)
+    .await
+    .context("Failed to mark subscriber as unsubscribed")?;
+
+    if updated.rows_affected() == 0 {
+        return Err(UnsubscribeError::UnknownToken);
+    }
+    Ok(())
+}
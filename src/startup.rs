@@ -1,96 +1,431 @@
+use std::{future::IntoFuture, sync::Arc, time::Duration};
+
 use crate::{
-    configuration::get_configuration,
-    email_client::EmailClient,
+    authentication::require_admin,
+    configuration::{
+        get_connection_pool, CustomFieldSettings, DeliverySettings, IssueSettings, Settings,
+        WorkerSettings,
+    },
+    deliverability::{DomainChecker, DomainResolver, HickoryResolver},
+    email_client::{EmailBackend, EmailClient},
+    health::HealthChecks,
+    metrics::{track_metrics, Metrics},
+    pages::{Pages, PagesError},
+    redact::set_pii_mode,
+    request_id::{propagate_request_id, RequestId},
     routes::{
-        confirm::confirm_subscriber, health_check::health_check, newsletters::publish_newsletter,
+        confirm::{confirm_subscriber, confirm_subscriber_json},
+        error::{method_not_allowed, not_found},
+        health_check::{health_check, health_live, health_ready},
+        issues::{
+            issue_failed_recipients, issue_report, issue_stats, preview_issue, resume_issue,
+            test_send_issue,
+        },
+        metrics::render_metrics,
+        newsletters::publish_newsletter,
         subscriptions::subscribe,
+        templates::save_template,
+        tracking::{opt_out, opt_out_form, track_click, track_open},
+        unsubscribe::{unsubscribe, unsubscribe_form},
     },
+    telemetry,
+    templates::{merge::MergeLinks, TemplateError, TemplateRegistry},
+    tracking::{Tracker, TrackerError},
 };
-use aws_config::BehaviorVersion;
-use aws_sdk_ses::Client;
 use axum::{
     body::Body,
     extract::Request,
+    middleware,
     routing::{get, post},
     Router,
 };
+use opentelemetry_sdk::trace::SdkTracerProvider;
 use sqlx::PgPool;
+use thiserror::Error;
 use tokio::net::TcpListener;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tower_http::trace::{DefaultOnRequest, DefaultOnResponse, TraceLayer};
 use tracing::{subscriber::set_global_default, Level, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
-use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
-use uuid::Uuid;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
 
 #[derive(Clone)]
 pub struct AppState {
     pub pool: PgPool,
     pub email_client: EmailClient,
+    pub domain_checker: DomainChecker,
+    pub confirmation_token_ttl: chrono::Duration,
+    pub base_url: String,
+    pub pages: Pages,
+    pub templates: TemplateRegistry,
+    pub issues: IssueSettings,
+    pub custom_fields: CustomFieldSettings,
+    pub delivery: DeliverySettings,
+    pub health: HealthChecks,
+    /// `stale_after` is also how long a `Pending` delivery is left to the
+    /// run that claimed it.
+    pub worker: WorkerSettings,
+    /// The email client's metrics, shared with the HTTP and funnel metrics.
+    pub metrics: Metrics,
+    /// `None` when open and click tracking is disabled.
+    pub tracker: Option<Tracker>,
+    /// Cancelled when the app starts shutting down.
+    pub shutdown: CancellationToken,
+    /// Sends started by `/publish` and `/issues/{issue_id}/resume`, which
+    /// outlive the request; shutdown waits for them.
+    pub deliveries: TaskTracker,
 }
 impl AppState {
-    pub fn new(pool: PgPool, client: EmailClient) -> Self {
+    pub fn new(
+        pool: PgPool,
+        client: EmailClient,
+        domain_checker: DomainChecker,
+        settings: &Settings,
+        pages: Pages,
+        templates: TemplateRegistry,
+        tracker: Option<Tracker>,
+    ) -> Self {
+        let metrics = client.metrics().clone();
         Self {
             pool,
             email_client: client,
+            domain_checker,
+            confirmation_token_ttl: settings.application.confirmation_token_ttl(),
+            base_url: settings.application.base_url(),
+            pages,
+            templates,
+            issues: settings.issues.clone(),
+            custom_fields: settings.custom_fields.clone(),
+            delivery: settings.delivery.clone(),
+            health: HealthChecks::new(settings.health.clone()),
+            worker: settings.worker.clone(),
+            metrics,
+            tracker,
+            shutdown: CancellationToken::new(),
+            deliveries: TaskTracker::new(),
         }
     }
+
+    pub fn merge_links(&self) -> MergeLinks {
+        MergeLinks {
+            base_url: self.base_url.clone(),
+            preferences_url: self.issues.preferences_url.clone(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum StartupError {
+    #[error("failed to bind {0}")]
+    Bind(String, #[source] std::io::Error),
+    #[error("failed to load page templates")]
+    Pages(#[from] PagesError),
+    #[error("failed to load email templates")]
+    Templates(#[from] TemplateError),
+    #[error("invalid tracking settings")]
+    Tracking(#[from] TrackerError),
 }
 
-pub async fn run(listener: TcpListener, pool: PgPool) -> Result<String, std::io::Error> {
-    let configuration = get_configuration().expect("Failed to read configuration.");
-    tracing::info!(
-        "Connected to database with string: {:?}",
-        configuration.database.with_db()
-    );
+/// Collects overrides for the dependencies [`Application::build`] would
+/// otherwise construct from the settings.
+pub struct ApplicationBuilder {
+    settings: Settings,
+    email_backend: Option<Arc<dyn EmailBackend>>,
+    resolver: Option<Arc<dyn DomainResolver>>,
+}
+
+impl ApplicationBuilder {
+    /// Sends through `backend` instead of the one `delivery.backend` names.
+    pub fn with_email_backend(mut self, backend: Arc<dyn EmailBackend>) -> Self {
+        self.email_backend = Some(backend);
+        self
+    }
+
+    /// Looks up email domains with `resolver` instead of the system's DNS.
+    pub fn with_resolver(mut self, resolver: Arc<dyn DomainResolver>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Everything the handlers, the worker and the CLI share, without
+    /// binding any ports.
+    pub async fn build_state(self) -> Result<AppState, StartupError> {
+        let settings = self.settings;
+        set_pii_mode(settings.logging.pii);
 
-    let config = aws_config::load_defaults(BehaviorVersion::v2024_03_28()).await;
-    let client = EmailClient::new(Client::new(&config));
+        let pool = get_connection_pool(&settings.database);
+        let client = match self.email_backend {
+            Some(backend) => EmailClient::for_backend(backend, &settings.delivery).await,
+            None => EmailClient::from_settings(&settings.delivery).await,
+        }
+        .with_metrics(Metrics::new());
+        let resolver = self
+            .resolver
+            .unwrap_or_else(|| Arc::new(HickoryResolver::from_system_conf()));
+        let domain_checker = DomainChecker::new(resolver, &settings.deliverability);
+        let pages = Pages::new(&settings.pages)?;
+        let templates = TemplateRegistry::load(&settings.email_templates, &pool).await?;
+        let tracker = Tracker::from_settings(&settings.tracking, &settings.application.base_url())?;
+
+        Ok(AppState::new(
+            pool,
+            client,
+            domain_checker,
+            &settings,
+            pages,
+            templates,
+            tracker,
+        ))
+    }
 
-    let app_state = AppState::new(pool, client);
+    pub async fn build(self) -> Result<Application, StartupError> {
+        let settings = self.settings.clone();
+        let app_state = self.build_state().await?;
+        let pool = app_state.pool.clone();
+        let metrics = app_state.metrics.clone();
+        let shutdown = app_state.shutdown.clone();
+        let deliveries = app_state.deliveries.clone();
 
-    let app = Router::new()
+        let metrics_router = Router::new()
+            .route("/metrics", get(render_metrics))
+            .with_state(app_state.clone());
+        let (admin, public_metrics) = match settings.metrics.admin_port {
+            Some(port) => {
+                let listener = bind(&settings.application.host, port).await?;
+                (Some((listener, metrics_router)), None)
+            }
+            None => (None, Some(metrics_router)),
+        };
+        let router = router(app_state)
+            .merge(public_metrics.unwrap_or_default())
+            .route_layer(middleware::from_fn_with_state(metrics, track_metrics))
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(|request: &Request<Body>| {
+                        let request_id = request
+                            .extensions()
+                            .get::<RequestId>()
+                            .cloned()
+                            .unwrap_or_else(RequestId::generate);
+                        let span = tracing::span!(
+                            Level::INFO,
+                            "request: ",
+                            method = tracing::field::display(request.method()),
+                            uri = tracing::field::display(request.uri()),
+                            version = tracing::field::debug(request.version()),
+                            request_id = tracing::field::display(request_id)
+                        );
+                        // Fails only without an OpenTelemetry layer, when there's
+                        // nothing to link up anyway.
+                        let _ = span.set_parent(telemetry::parent_context(request.headers()));
+                        span
+                    })
+                    .on_request(DefaultOnRequest::new().level(Level::INFO))
+                    .on_response(DefaultOnResponse::new().level(Level::INFO)),
+            )
+            // Outermost, so the id is known before the trace span is created.
+            .layer(middleware::from_fn(propagate_request_id));
+
+        let listener = bind(&settings.application.host, settings.application.port).await?;
+        Ok(Application {
+            listener,
+            router,
+            admin,
+            pool,
+            shutdown,
+            deliveries,
+            drain_timeout: settings.application.drain_timeout(),
+        })
+    }
+}
+
+async fn bind(host: &str, port: u16) -> Result<TcpListener, StartupError> {
+    let address = format!("{}:{}", host, port);
+    TcpListener::bind(&address)
+        .await
+        .map_err(|e| StartupError::Bind(address, e))
+}
+
+fn router(app_state: AppState) -> Router {
+    Router::new()
         .route("/health_check", get(health_check))
+        .route("/health/live", get(health_live))
+        .route("/health/ready", get(health_ready))
         .route("/subscribe", post(subscribe))
-        .route("/subscribe/confirm", get(confirm_subscriber))
+        .route(
+            "/subscribe/confirm",
+            get(confirm_subscriber).post(confirm_subscriber_json),
+        )
+        .route("/unsubscribe", get(unsubscribe_form).post(unsubscribe))
         .route("/publish", post(publish_newsletter))
+        .route("/templates", post(save_template))
+        .route("/issues/preview", post(preview_issue))
+        .route("/issues/test-send", post(test_send_issue))
+        .route("/issues/{issue_id}/stats", get(issue_stats))
+        .route("/issues/{issue_id}/report", get(issue_report))
+        .route("/issues/{issue_id}/resume", post(resume_issue))
+        .route(
+            "/issues/{issue_id}/report/failed",
+            get(issue_failed_recipients).route_layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                require_admin,
+            )),
+        )
+        .route("/track/open/{issue_id}/{subscriber_id}", get(track_open))
+        .route("/track/click/{issue_id}/{subscriber_id}", get(track_click))
+        .route("/tracking/opt-out", get(opt_out_form).post(opt_out))
+        .fallback(not_found)
+        .method_not_allowed_fallback(method_not_allowed)
         .with_state(app_state)
-        .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(|request: &Request<Body>| {
-                    let request_id = Uuid::new_v4();
-                    tracing::span!(
-                        Level::INFO,
-                        "request: ",
-                        method = tracing::field::display(request.method()),
-                        uri = tracing::field::display(request.uri()),
-                        version = tracing::field::debug(request.version()),
-                        request_id = tracing::field::display(request_id)
-                    )
-                })
-                .on_request(DefaultOnRequest::new().level(Level::INFO))
-                .on_response(DefaultOnResponse::new().level(Level::INFO)),
-        );
-
-    tracing::info!("Successfully created router");
-
-    let address = configuration.application.get_address();
-    tracing::info!("About to start listening on: {}", &address);
-
-    axum::serve(listener, app).await?;
-
-    Ok(address)
 }
 
-pub fn get_subscriber(name: &str, filter: &str) -> impl Subscriber + Send + Sync {
-    let formatting = BunyanFormattingLayer::new(name.into(), std::io::stdout);
+/// The app with every dependency built and its ports bound, ready to serve.
+pub struct Application {
+    listener: TcpListener,
+    router: Router,
+    /// The metrics listener and router when they're on `metrics.admin_port`.
+    admin: Option<(TcpListener, Router)>,
+    pool: PgPool,
+    shutdown: CancellationToken,
+    deliveries: TaskTracker,
+    drain_timeout: Duration,
+}
+
+impl Application {
+    /// Builds everything from `settings`; see [`Application::builder`] to
+    /// swap in test doubles.
+    pub async fn build(settings: Settings) -> Result<Self, StartupError> {
+        Self::builder(settings).build().await
+    }
+
+    pub fn builder(settings: Settings) -> ApplicationBuilder {
+        ApplicationBuilder {
+            settings,
+            email_backend: None,
+            resolver: None,
+        }
+    }
+
+    /// The port actually bound, which differs from the configured one when
+    /// that is `0`.
+    pub fn port(&self) -> u16 {
+        self.listener.local_addr().unwrap().port()
+    }
+
+    /// The port `/metrics` is served on, if it has one of its own.
+    pub fn metrics_port(&self) -> Option<u16> {
+        self.admin
+            .as_ref()
+            .map(|(listener, _)| listener.local_addr().unwrap().port())
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Cancel it to shut the app down, as SIGTERM does in `main`.
+    pub fn shutdown_handle(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// The sends running in the background.
+    pub fn deliveries(&self) -> TaskTracker {
+        self.deliveries.clone()
+    }
+
+    /// Serves until shut down, then drains in-flight requests and background
+    /// sends for up to `application.drain_timeout_secs` and closes the pool.
+    /// Sends stop claiming deliveries once shutdown starts, so they finish
+    /// with what they already claimed.
+    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+        let shutdown = self.shutdown;
+        if let Some((listener, router)) = self.admin {
+            tracing::info!("Serving metrics on: {}", listener.local_addr()?);
+            tokio::spawn(
+                axum::serve(listener, router)
+                    .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+                    .into_future(),
+            );
+        }
+
+        tracing::info!("Listening on: {}", self.listener.local_addr()?);
+        let server = axum::serve(self.listener, self.router)
+            .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+            .into_future();
+        tokio::pin!(server);
+        let deadline = tokio::select! {
+            result = &mut server => {
+                result?;
+                tokio::time::Instant::now() + self.drain_timeout
+            }
+            _ = shutdown.cancelled() => {
+                tracing::info!("Shutting down, draining requests for up to {:?}", self.drain_timeout);
+                let deadline = tokio::time::Instant::now() + self.drain_timeout;
+                match tokio::time::timeout_at(deadline, &mut server).await {
+                    Ok(result) => result?,
+                    Err(_) => tracing::warn!("Drain timeout elapsed, dropping in-flight requests"),
+                }
+                deadline
+            }
+        };
+        self.deliveries.close();
+        if tokio::time::timeout_at(deadline, self.deliveries.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                "Drain timeout elapsed, dropping {} background sends",
+                self.deliveries.len()
+            );
+        }
+        self.pool.close().await;
+        tracing::info!("Shut down cleanly");
+        Ok(())
+    }
+}
+
+/// Resolves on SIGTERM (what deploys send) or SIGINT (Ctrl-C).
+pub async fn shutdown_signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for SIGINT");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+}
+
+/// Bunyan JSON logs to `sink` and, given a tracer provider, OTLP span export.
+pub fn get_subscriber<Sink>(
+    name: &str,
+    filter: &str,
+    tracer_provider: Option<&SdkTracerProvider>,
+    sink: Sink,
+) -> impl Subscriber + Send + Sync
+where
+    Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let formatting = BunyanFormattingLayer::new(name.into(), sink);
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| filter.into());
-    println!("{:?}", env_filter);
 
-    let subsciber = Registry::default()
+    Registry::default()
         .with(env_filter)
         .with(JsonStorageLayer)
-        .with(formatting);
-    subsciber
+        .with(formatting)
+        .with(tracer_provider.map(telemetry::layer))
 }
 
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use aws_sdk_ses::{
+    config::{
+        interceptors::BeforeTransmitInterceptorContextMut, ConfigBag, Intercept, RuntimeComponents,
+    },
+    error::BoxError,
+};
+use axum::http::HeaderMap;
+use opentelemetry::{propagation::TextMapPropagator, trace::TracerProvider, Context};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracer, SdkTracerProvider},
+    Resource,
+};
+use thiserror::Error;
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::configuration::TelemetrySettings;
+
+#[derive(Error, Debug)]
+pub enum TelemetryError {
+    #[error("failed to build the OTLP exporter")]
+    Exporter(#[from] opentelemetry_otlp::ExporterBuildError),
+}
+
+/// Exports spans over OTLP/HTTP to `telemetry.otlp_endpoint`, or `None` when
+/// no endpoint is configured.
+///
+/// Traces continued from an incoming `traceparent` keep the caller's sampling
+/// decision; new ones are sampled at `telemetry.sampling_ratio`.
+pub fn tracer_provider(
+    settings: &TelemetrySettings,
+) -> Result<Option<SdkTracerProvider>, TelemetryError> {
+    let Some(endpoint) = &settings.otlp_endpoint else {
+        return Ok(None);
+    };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            settings.sampling_ratio,
+        ))))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(settings.service_name.clone())
+                .build(),
+        )
+        .build();
+    Ok(Some(provider))
+}
+
+/// The `tracing` layer that hands spans to `provider`.
+pub fn layer<S>(provider: &SdkTracerProvider) -> OpenTelemetryLayer<S, SdkTracer>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer("zero2prod"))
+}
+
+/// The trace context a caller sent in its W3C `traceparent`/`tracestate`
+/// headers; empty if there are none.
+pub fn parent_context(headers: &HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+/// W3C trace context headers that continue `span`'s trace in another service.
+pub fn trace_headers(span: &Span) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    TraceContextPropagator::new().inject_context(&span.context(), &mut headers);
+    headers
+}
+
+/// Adds the current span's trace context to every AWS SDK request, so SES
+/// calls show up in the trace of the request that made them.
+#[derive(Debug)]
+pub struct PropagateTraceContext;
+
+impl Intercept for PropagateTraceContext {
+    fn name(&self) -> &'static str {
+        "PropagateTraceContext"
+    }
+
+    fn modify_before_signing(
+        &self,
+        context: &mut BeforeTransmitInterceptorContextMut<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let headers = context.request_mut().headers_mut();
+        for (name, value) in trace_headers(&Span::current()) {
+            headers.insert(name, value);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{layer, parent_context, trace_headers};
+    use axum::http::{HeaderMap, HeaderValue};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    #[test]
+    fn incoming_trace_context_is_continued_in_outgoing_headers() {
+        let provider = SdkTracerProvider::builder().build();
+        let subscriber = Registry::default().with(layer(&provider));
+        let mut incoming = HeaderMap::new();
+        incoming.insert(
+            "traceparent",
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+
+        let outgoing = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request");
+            span.set_parent(parent_context(&incoming)).unwrap();
+            trace_headers(&span)
+        });
+
+        let traceparent = &outgoing["traceparent"];
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+    }
+}
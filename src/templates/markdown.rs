@@ -0,0 +1,162 @@
+use pulldown_cmark::{html, Event, Options, Parser, Tag, TagEnd};
+use serde::Serialize;
+
+/// An issue body authored in Markdown, rendered once per issue and injected
+/// into the newsletter templates as `content_html` / `content_text`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RenderedContent {
+    pub content_html: String,
+    pub content_text: String,
+}
+
+impl RenderedContent {
+    pub fn from_markdown(markdown: &str) -> Self {
+        Self {
+            content_html: to_html(markdown),
+            content_text: to_text(markdown),
+        }
+    }
+}
+
+fn options() -> Options {
+    Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES
+}
+
+/// Markdown to HTML, sanitised so raw HTML in the source can't inject
+/// scripts, styles or event handlers into the email.
+pub fn to_html(markdown: &str) -> String {
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options()));
+    ammonia::clean(&unsafe_html)
+}
+
+/// Markdown to a plain-text alternative. Formatting is dropped, list items
+/// keep their markers and links become footnotes (`text [1]` ... `[1] url`).
+pub fn to_text(markdown: &str) -> String {
+    let mut out = String::new();
+    let mut links: Vec<String> = vec![];
+    let mut pending_links: Vec<String> = vec![];
+    let mut lists: Vec<Option<u64>> = vec![];
+
+    for event in Parser::new_ext(markdown, options()) {
+        match event {
+            Event::Start(Tag::List(start)) => {
+                end_line(&mut out);
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    end_block(&mut out);
+                }
+            }
+            Event::Start(Tag::Item) => {
+                end_line(&mut out);
+                out.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(n)) => {
+                        out.push_str(&format!("{}. ", n));
+                        *n += 1;
+                    }
+                    _ => out.push_str("- "),
+                }
+            }
+            Event::End(TagEnd::Item) => end_line(&mut out),
+            Event::Start(Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. }) => {
+                pending_links.push(dest_url.to_string());
+            }
+            Event::End(TagEnd::Link | TagEnd::Image) => {
+                if let Some(url) = pending_links.pop() {
+                    links.push(url);
+                    out.push_str(&format!(" [{}]", links.len()));
+                }
+            }
+            Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::CodeBlock
+                | TagEnd::BlockQuote(_)
+                | TagEnd::Table,
+            ) if lists.is_empty() => end_block(&mut out),
+            Event::End(TagEnd::Paragraph | TagEnd::TableRow | TagEnd::TableHead) => {
+                end_line(&mut out)
+            }
+            Event::End(TagEnd::TableCell) => out.push('\t'),
+            Event::Text(text) | Event::Code(text) => out.push_str(&text),
+            Event::SoftBreak | Event::HardBreak => out.push('\n'),
+            Event::Rule => {
+                out.push_str("----");
+                end_block(&mut out);
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = out.trim_end().to_string();
+    if !links.is_empty() {
+        out.push_str("\n\n");
+        for (i, url) in links.iter().enumerate() {
+            out.push_str(&format!("[{}] {}\n", i + 1, url));
+        }
+        out.truncate(out.trim_end().len());
+    }
+    out
+}
+
+fn end_line(out: &mut String) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+fn end_block(out: &mut String) {
+    end_line(out);
+    if !out.is_empty() && !out.ends_with("\n\n") {
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_html, to_text};
+
+    #[test]
+    fn links_become_footnotes_in_plain_text() {
+        let text = to_text(
+            "Read [the post](https://example.com/post) or [the FAQ](https://example.com/faq).",
+        );
+
+        assert_eq!(
+            text,
+            "Read the post [1] or the FAQ [2].\n\n[1] https://example.com/post\n[2] https://example.com/faq"
+        );
+    }
+
+    #[test]
+    fn blocks_and_lists_are_separated_in_plain_text() {
+        let text =
+            to_text("# News\n\nSome *bold* text.\n\n- one\n- two\n\n1. first\n2. second\n\nBye");
+
+        assert_eq!(
+            text,
+            "News\n\nSome bold text.\n\n- one\n- two\n\n1. first\n2. second\n\nBye"
+        );
+    }
+
+    #[test]
+    fn raw_html_is_sanitised() {
+        let html = to_html("Hi <script>alert(1)</script><a href=\"#\" onclick=\"x()\">there</a>");
+
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("onclick"));
+        assert!(html.contains("there"));
+    }
+
+    #[test]
+    fn markdown_is_rendered_to_html() {
+        let html = to_html("## Title\n\nA [link](https://example.com)");
+
+        assert!(html.contains("<h2>Title</h2>"));
+        assert!(html.contains("href=\"https://example.com\""));
+    }
+}
@@ -0,0 +1,289 @@
+use chrono::{DateTime, Utc};
+use handlebars::{handlebars_helper, no_escape, Handlebars};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+
+use super::{markdown::RenderedContent, TemplateError, SAMPLE_SUBSCRIBER_NAME};
+
+/// Per-recipient values an issue's subject and body can reference, e.g.
+/// `{{first_name}}` or `{{custom.company}}`.
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeFields {
+    pub name: String,
+    pub first_name: String,
+    pub email: String,
+    pub signup_date: String,
+    pub custom: Map<String, Value>,
+    pub unsubscribe_link: String,
+    pub preferences_link: String,
+    /// Deprecated: templates saved before issues were rendered from Markdown
+    /// link to `{{link}}`, which now points at the site itself.
+    pub link: String,
+}
+
+/// Where the links in [`MergeFields`] point to.
+#[derive(Debug, Clone)]
+pub struct MergeLinks {
+    pub base_url: String,
+    /// Falls back to the unsubscribe link until there's a preferences page.
+    pub preferences_url: Option<String>,
+}
+
+impl MergeFields {
+    /// Every custom field in `schema` is present, `null` if the subscriber
+    /// doesn't have it, so strict rendering only fails on unknown names.
+    pub fn new(
+        name: &str,
+        email: &str,
+        subscribed_at: DateTime<Utc>,
+        custom: &Value,
+        unsubscribe_token: &str,
+        schema: &[String],
+        links: &MergeLinks,
+    ) -> Self {
+        let custom = schema
+            .iter()
+            .map(|key| (key.clone(), custom.get(key).cloned().unwrap_or(Value::Null)))
+            .collect();
+        let unsubscribe_link = format!(
+            "{}/unsubscribe?unsubscribe_token={}",
+            links.base_url, unsubscribe_token
+        );
+        Self {
+            name: name.to_string(),
+            first_name: name.split_whitespace().next().unwrap_or(name).to_string(),
+            email: email.to_string(),
+            signup_date: subscribed_at.format("%Y-%m-%d").to_string(),
+            custom,
+            preferences_link: links
+                .preferences_url
+                .clone()
+                .unwrap_or_else(|| unsubscribe_link.clone()),
+            unsubscribe_link,
+            link: links.base_url.clone(),
+        }
+    }
+
+    pub fn sample(schema: &[String], links: &MergeLinks) -> Self {
+        let custom = Value::Object(
+            schema
+                .iter()
+                .map(|key| (key.clone(), Value::String(format!("sample {}", key))))
+                .collect(),
+        );
+        Self::new(
+            SAMPLE_SUBSCRIBER_NAME,
+            "ursula@example.com",
+            Utc::now(),
+            &custom,
+            "sample",
+            schema,
+            links,
+        )
+    }
+}
+
+/// Names of the custom fields stored for any subscriber.
+pub async fn custom_field_schema(pool: &PgPool) -> Result<Vec<String>, sqlx::Error> {
+    let keys = sqlx::query!(
+        r#"
+        SELECT DISTINCT jsonb_object_keys(custom_fields) AS "key!"
+        FROM subscriptions
+        ORDER BY 1
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(keys.into_iter().map(|r| r.key).collect())
+}
+
+handlebars_helper!(default: |value: Json, fallback: Json| match value {
+    Value::Null => fallback.clone(),
+    Value::String(s) if s.is_empty() => fallback.clone(),
+    value => value.clone(),
+});
+
+/// Backslash-escapes Markdown punctuation so merged values are always
+/// rendered as literal text.
+fn escape_markdown(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\`*_{}[]()<>#+-.!|~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Rewrites Liquid-style fallbacks, `{{first_name | default: "there"}}`, into
+/// the `default` helper call, `{{default first_name "there"}}`.
+fn rewrite_filters(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("}}").map(|end| start + end) else {
+            break;
+        };
+        let expression = &rest[start + 2..end];
+        let filtered = expression.split_once('|').and_then(|(field, filter)| {
+            let fallback = filter.trim().strip_prefix("default:")?.trim();
+            Some(format!("{{{{default {} {}}}}}", field.trim(), fallback))
+        });
+        match filtered {
+            Some(helper) if !expression.starts_with(['#', '/', '{', '!', '>']) => {
+                out.push_str(&helper)
+            }
+            _ => out.push_str(&rest[start..end + 2]),
+        }
+        rest = &rest[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn new_handlebars(escape: fn(&str) -> String) -> Handlebars<'static> {
+    let mut hb = Handlebars::new();
+    hb.set_strict_mode(true);
+    hb.register_escape_fn(escape);
+    hb.register_helper("default", Box::new(default));
+    hb
+}
+
+/// An issue's subject and Markdown body with their merge fields compiled.
+#[derive(Debug)]
+pub struct IssueTemplate {
+    subject: Handlebars<'static>,
+    body: Handlebars<'static>,
+}
+
+impl IssueTemplate {
+    /// Compiles and test-renders the issue against `sample`, so unknown merge
+    /// fields are reported before anything is sent.
+    pub fn compile(
+        subject: &str,
+        markdown: &str,
+        sample: &MergeFields,
+    ) -> Result<Self, TemplateError> {
+        let mut template = Self {
+            subject: new_handlebars(no_escape),
+            body: new_handlebars(escape_markdown),
+        };
+        template
+            .subject
+            .register_template_string("subject", rewrite_filters(subject))
+            .map_err(|e| TemplateError::Compile("subject".to_string(), Box::new(e)))?;
+        template
+            .body
+            .register_template_string("message", rewrite_filters(markdown))
+            .map_err(|e| TemplateError::Compile("message".to_string(), Box::new(e)))?;
+        template.render(sample)?;
+        Ok(template)
+    }
+
+    /// The subject and content for one recipient.
+    pub fn render(&self, fields: &MergeFields) -> Result<(String, RenderedContent), TemplateError> {
+        let subject = self
+            .subject
+            .render("subject", fields)
+            .map_err(|e| TemplateError::Render("subject".to_string(), Box::new(e)))?;
+        let markdown = self
+            .body
+            .render("message", fields)
+            .map_err(|e| TemplateError::Render("message".to_string(), Box::new(e)))?;
+        Ok((subject, RenderedContent::from_markdown(&markdown)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rewrite_filters, IssueTemplate, MergeFields, MergeLinks};
+    use chrono::Utc;
+    use claim::assert_err;
+    use serde_json::json;
+
+    fn links() -> MergeLinks {
+        MergeLinks {
+            base_url: "https://example.com".to_string(),
+            preferences_url: None,
+        }
+    }
+
+    fn fields(name: &str, custom: serde_json::Value) -> MergeFields {
+        let schema = vec!["company".to_string()];
+        MergeFields::new(
+            name,
+            "a@example.com",
+            Utc::now(),
+            &custom,
+            "token",
+            &schema,
+            &links(),
+        )
+    }
+
+    fn compile(subject: &str, markdown: &str) -> Result<IssueTemplate, super::TemplateError> {
+        IssueTemplate::compile(
+            subject,
+            markdown,
+            &MergeFields::sample(&["company".to_string()], &links()),
+        )
+    }
+
+    #[test]
+    fn filter_syntax_is_rewritten_to_the_default_helper() {
+        assert_eq!(
+            rewrite_filters(r#"Hi {{first_name | default: "there"}}!"#),
+            r#"Hi {{default first_name "there"}}!"#
+        );
+        assert_eq!(
+            rewrite_filters("{{#if a}}{{b}}{{/if}}"),
+            "{{#if a}}{{b}}{{/if}}"
+        );
+    }
+
+    #[test]
+    fn merge_fields_are_rendered_in_subject_and_body() {
+        let template = compile(
+            "News for {{first_name}}",
+            "Hello {{name}} from {{custom.company}}",
+        )
+        .unwrap();
+
+        let (subject, content) = template
+            .render(&fields("Ursula Le Guin", json!({ "company": "Earthsea" })))
+            .unwrap();
+
+        assert_eq!(subject, "News for Ursula");
+        assert_eq!(content.content_text, "Hello Ursula Le Guin from Earthsea");
+    }
+
+    #[test]
+    fn missing_values_use_the_fallback() {
+        let template = compile("Hi", r#"Hi {{custom.company | default: "friend"}}"#).unwrap();
+
+        let (_, content) = template.render(&fields("Ursula", json!({}))).unwrap();
+
+        assert_eq!(content.content_text, "Hi friend");
+    }
+
+    #[test]
+    fn unknown_merge_fields_fail_validation() {
+        assert_err!(compile("Hi {{frist_name}}", "Body"));
+        assert_err!(compile("Hi", "Body {{custom.compnay}}"));
+    }
+
+    #[test]
+    fn merged_values_are_not_interpreted_as_markdown() {
+        let template = compile("Hi", "Hello {{name}}").unwrap();
+
+        let (_, content) = template
+            .render(&fields("*Bold* [x](y)", json!({})))
+            .unwrap();
+
+        assert_eq!(content.content_text, "Hello *Bold* [x](y)");
+        assert!(!content.content_html.contains("<em>"));
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use handlebars::{no_escape, Handlebars};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    configuration::EmailTemplateSettings,
+    templates::merge::{MergeFields, MergeLinks},
+};
+
+pub mod markdown;
+pub mod merge;
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("layout", include_str!("./emails/layout.html")),
+    ("newsletter.html", include_str!("./emails/newsletter.html")),
+    ("newsletter.txt", include_str!("./emails/newsletter.txt")),
+    (
+        "confirmation.html",
+        include_str!("./emails/confirmation.html"),
+    ),
+    (
+        "confirmation.txt",
+        include_str!("./emails/confirmation.txt"),
+    ),
+];
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("failed to read template file `{0}`")]
+    Io(String, #[source] std::io::Error),
+    #[error("template `{0}` does not compile")]
+    Compile(String, #[source] Box<handlebars::TemplateError>),
+    #[error("template `{0}` fails to render")]
+    Render(String, #[source] Box<handlebars::RenderError>),
+    #[error("failed to inline the CSS of template `{0}`")]
+    InlineCss(String, #[source] css_inline::InlineError),
+    #[error("unknown template `{0}`")]
+    Unknown(String),
+    #[error("failed to load templates from the database")]
+    Database(#[from] sqlx::Error),
+}
+
+impl TemplateError {
+    /// The error including handlebars' line/column details, safe to show to
+    /// whoever is editing the template.
+    pub fn describe(&self) -> String {
+        match self {
+            TemplateError::Compile(name, e) => format!("template `{}`: {}", name, e),
+            TemplateError::Render(name, e) => format!("template `{}`: {}", name, e),
+            e => e.to_string(),
+        }
+    }
+}
+
+/// Email templates, keyed by name (`newsletter.html`, `confirmation.txt`, ...).
+///
+/// Templates are layered: built-ins first, then files from
+/// `email_templates.directory`, then the latest version of each template saved
+/// in the database. A template saved for a list is stored as `<list>/<name>`
+/// and takes precedence over `<name>` when rendering for that list. Names
+/// without an `.html`/`.txt` extension (like `layout`) are partials.
+///
+/// `.txt` templates live in a separate registry without HTML escaping.
+#[derive(Debug, Clone)]
+pub struct TemplateRegistry {
+    html: Arc<RwLock<Handlebars<'static>>>,
+    text: Arc<RwLock<Handlebars<'static>>>,
+    /// Held for the whole of a save, so each one is validated against every
+    /// template saved before it.
+    saving: Arc<tokio::sync::Mutex<()>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SavedTemplate {
+    pub name: String,
+    pub list: Option<String>,
+    pub version: i32,
+}
+
+fn key(name: &str, list: Option<&str>) -> String {
+    match list {
+        Some(list) => format!("{}/{}", list, name),
+        None => name.to_string(),
+    }
+}
+
+fn is_text(key: &str) -> bool {
+    key.ends_with(".txt")
+}
+
+fn is_renderable(key: &str) -> bool {
+    key.ends_with(".html") || key.ends_with(".txt")
+}
+
+pub const SAMPLE_SUBSCRIBER_NAME: &str = "Ursula Le Guin";
+
+/// Data every renderable template must accept: the sample subscriber's merge
+/// fields plus what the templates are rendered with. Strict mode makes
+/// references to anything else fail validation instead of rendering as an
+/// empty string.
+pub fn sample_data() -> Value {
+    let links = MergeLinks {
+        base_url: "https://example.com".to_string(),
+        preferences_url: None,
+    };
+    let mut data = json!(MergeFields::sample(&[], &links));
+    data["title"] = json!("Sample issue");
+    data["subscriber_name"] = json!(SAMPLE_SUBSCRIBER_NAME);
+    data["content_html"] =
+        json!("<p>This is a <a href=\"https://example.com/article\">sample issue</a>.</p>");
+    data["content_text"] = json!("This is a sample issue [1].\n\n[1] https://example.com/article");
+    data["confirmation_link"] =
+        json!("https://example.com/subscribe/confirm?subscription_token=sample");
+    data["privacy_policy"] = json!("https://example.com/privacy");
+    data
+}
+
+impl TemplateRegistry {
+    /// Only the templates compiled into the binary.
+    pub fn builtin() -> Result<Self, TemplateError> {
+        let registry = Self {
+            html: Arc::new(RwLock::new(new_handlebars(false))),
+            text: Arc::new(RwLock::new(new_handlebars(true))),
+            saving: Arc::new(tokio::sync::Mutex::new(())),
+        };
+        for (name, source) in BUILTIN {
+            registry.register(name, source)?;
+        }
+        registry.validate()?;
+        Ok(registry)
+    }
+
+    /// Built-ins overridden by the configured directory and the database,
+    /// validated as a whole so a broken template stops startup.
+    pub async fn load(
+        settings: &EmailTemplateSettings,
+        pool: &PgPool,
+    ) -> Result<Self, TemplateError> {
+        let registry = Self::builtin()?;
+
+        if let Some(dir) = &settings.directory {
+            registry.load_directory(Path::new(dir), Path::new(dir))?;
+        }
+
+        if settings.load_from_database {
+            let saved = sqlx::query!(
+                r#"
+                SELECT DISTINCT ON (name, COALESCE(list, '')) name, list, body
+                FROM email_templates
+                ORDER BY name, COALESCE(list, ''), version DESC
+                "#
+            )
+            .fetch_all(pool)
+            .await?;
+            for template in saved {
+                registry.register(
+                    &key(&template.name, template.list.as_deref()),
+                    &template.body,
+                )?;
+            }
+        }
+
+        registry.validate()?;
+        Ok(registry)
+    }
+
+    fn load_directory(&self, root: &Path, dir: &Path) -> Result<(), TemplateError> {
+        let entries =
+            std::fs::read_dir(dir).map_err(|e| TemplateError::Io(dir.display().to_string(), e))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|e| TemplateError::Io(dir.display().to_string(), e))?
+                .path();
+            if path.is_dir() {
+                self.load_directory(root, &path)?;
+                continue;
+            }
+            let source = std::fs::read_to_string(&path)
+                .map_err(|e| TemplateError::Io(path.display().to_string(), e))?;
+            let name = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            // `layout.hbs` registers as the `layout` partial
+            let name = name.strip_suffix(".hbs").unwrap_or(&name);
+            self.register(name, &source)?;
+        }
+        Ok(())
+    }
+
+    fn register(&self, key: &str, source: &str) -> Result<(), TemplateError> {
+        let registry = if is_text(key) { &self.text } else { &self.html };
+        registry
+            .write()
+            .unwrap()
+            .register_template_string(key, source)
+            .map_err(|e| TemplateError::Compile(key.to_string(), Box::new(e)))
+    }
+
+    /// Renders every email template against `sample_data`.
+    pub fn validate(&self) -> Result<(), TemplateError> {
+        for registry in [&self.html, &self.text] {
+            validate_all(&registry.read().unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// Renders `name`, preferring the override saved for `list`. The CSS of
+    /// HTML templates is inlined, since many email clients ignore `<style>`.
+    pub fn render<T: Serialize>(
+        &self,
+        name: &str,
+        list: Option<&str>,
+        data: &T,
+    ) -> Result<String, TemplateError> {
+        let registry = if is_text(name) {
+            &self.text
+        } else {
+            &self.html
+        };
+        let registry = registry.read().unwrap();
+
+        let list_key = list.map(|list| key(name, Some(list)));
+        let key = match list_key {
+            Some(list_key) if registry.has_template(&list_key) => list_key,
+            _ if registry.has_template(name) => name.to_string(),
+            _ => return Err(TemplateError::Unknown(name.to_string())),
+        };
+        let rendered = registry
+            .render(&key, data)
+            .map_err(|e| TemplateError::Render(key.clone(), Box::new(e)))?;
+        if is_text(&key) {
+            return Ok(rendered);
+        }
+        css_inline::inline(&rendered).map_err(|e| TemplateError::InlineCss(key, e))
+    }
+
+    /// Validates `body` and stores it as the next version of `name` (for
+    /// `list`, if given). Every template is rendered again with the new one in
+    /// place, so a partial like `layout` can't break the templates using it.
+    /// Nothing is saved if any of them fails to compile or render. Saves run
+    /// one at a time.
+    pub async fn save(
+        &self,
+        pool: &PgPool,
+        name: &str,
+        list: Option<&str>,
+        body: &str,
+    ) -> Result<SavedTemplate, TemplateError> {
+        let _saving = self.saving.lock().await;
+        let key = key(name, list);
+        let mut candidate = if is_text(&key) {
+            self.text.read().unwrap().clone()
+        } else {
+            self.html.read().unwrap().clone()
+        };
+        candidate
+            .register_template_string(&key, body)
+            .map_err(|e| TemplateError::Compile(key.clone(), Box::new(e)))?;
+        validate_all(&candidate)?;
+
+        let version = sqlx::query!(
+            r#"
+            INSERT INTO email_templates (id, name, list, version, body)
+            SELECT $1, $2, $3, COALESCE(MAX(version), 0) + 1, $4
+            FROM email_templates
+            WHERE name = $2 AND list IS NOT DISTINCT FROM $3
+            RETURNING version
+            "#,
+            Uuid::new_v4(),
+            name,
+            list,
+            body
+        )
+        .fetch_one(pool)
+        .await?
+        .version;
+
+        self.register(&key, body)?;
+        Ok(SavedTemplate {
+            name: name.to_string(),
+            list: list.map(str::to_string),
+            version,
+        })
+    }
+}
+
+fn validate_all(registry: &Handlebars<'static>) -> Result<(), TemplateError> {
+    for name in registry.get_templates().keys().filter(|k| is_renderable(k)) {
+        registry
+            .render(name, &sample_data())
+            .map_err(|e| TemplateError::Render(name.clone(), Box::new(e)))?;
+    }
+    Ok(())
+}
+
+fn new_handlebars(text: bool) -> Handlebars<'static> {
+    let mut hb = Handlebars::new();
+    hb.set_strict_mode(true);
+    if text {
+        hb.register_escape_fn(no_escape);
+    }
+    hb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sample_data, TemplateRegistry};
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn builtin_templates_are_valid() {
+        assert_ok!(TemplateRegistry::builtin());
+    }
+
+    #[test]
+    fn text_templates_are_not_html_escaped() {
+        let registry = TemplateRegistry::builtin().unwrap();
+        let mut data = sample_data();
+        data["subscriber_name"] = "Tom & Jerry".into();
+
+        let text = registry.render("newsletter.txt", None, &data).unwrap();
+        let html = registry.render("newsletter.html", None, &data).unwrap();
+
+        assert!(text.contains("Tom & Jerry"));
+        assert!(html.contains("Tom &amp; Jerry"));
+    }
+
+    #[test]
+    fn list_override_takes_precedence() {
+        let registry = TemplateRegistry::builtin().unwrap();
+        registry
+            .register("weekly/newsletter.txt", "Weekly for {{subscriber_name}}")
+            .unwrap();
+
+        let weekly = registry
+            .render("newsletter.txt", Some("weekly"), &sample_data())
+            .unwrap();
+        let other = registry
+            .render("newsletter.txt", Some("monthly"), &sample_data())
+            .unwrap();
+
+        assert_eq!(weekly, "Weekly for Ursula Le Guin");
+        assert!(other.starts_with("Hello Ursula Le Guin"));
+    }
+
+    #[test]
+    fn html_templates_have_their_css_inlined() {
+        let registry = TemplateRegistry::builtin().unwrap();
+
+        let html = registry
+            .render("newsletter.html", None, &sample_data())
+            .unwrap();
+
+        assert!(!html.contains("<style"));
+        assert!(html.contains("style=\""));
+    }
+
+    #[test]
+    fn the_deprecated_link_variable_still_validates() {
+        let registry = TemplateRegistry::builtin().unwrap();
+        registry
+            .register("weekly/newsletter.txt", "Read it at {{link}}")
+            .unwrap();
+
+        assert_ok!(registry.validate());
+    }
+
+    #[test]
+    fn unknown_variables_fail_validation() {
+        let registry = TemplateRegistry::builtin().unwrap();
+        registry
+            .register("broken.html", "Hello {{subscriber_nmae}}")
+            .unwrap();
+
+        assert_err!(registry.validate());
+    }
+}
@@ -0,0 +1,314 @@
+use std::{borrow::Cow, fmt, sync::Arc};
+
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::PgPool;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::configuration::TrackingSettings;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Error, Debug)]
+pub enum TrackerError {
+    #[error("tracking is enabled but `tracking.secret` is empty")]
+    MissingSecret,
+    #[error("`{0}` is not a valid base URL for tracking links: {1}")]
+    InvalidBaseUrl(String, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize)]
+#[sqlx(type_name = "emaileventkind")]
+pub enum EmailEventKind {
+    Open,
+    Click,
+}
+
+/// Builds and verifies the signed open-pixel and click-redirect URLs.
+///
+/// Signing stops the redirect from being used as an open redirect and keeps
+/// anyone from inflating another subscriber's stats.
+#[derive(Clone)]
+pub struct Tracker {
+    secret: Arc<[u8]>,
+    base_url: Url,
+}
+
+impl fmt::Debug for Tracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracker")
+            .field("base_url", &self.base_url.as_str())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Tracker {
+    /// Fails if `base_url` can't have tracking paths appended to it, so
+    /// building the links while sending can't.
+    pub fn new(secret: &[u8], base_url: &str) -> Result<Self, TrackerError> {
+        let invalid = |reason: String| TrackerError::InvalidBaseUrl(base_url.to_string(), reason);
+        let base_url = Url::parse(base_url).map_err(|e| invalid(e.to_string()))?;
+        if base_url.cannot_be_a_base() {
+            return Err(invalid("it cannot have a path".to_string()));
+        }
+        Ok(Self {
+            secret: secret.into(),
+            base_url,
+        })
+    }
+
+    /// `None` when tracking is switched off for the deployment.
+    pub fn from_settings(
+        settings: &TrackingSettings,
+        base_url: &str,
+    ) -> Result<Option<Self>, TrackerError> {
+        if !settings.enabled {
+            return Ok(None);
+        }
+        if settings.secret.expose().is_empty() {
+            return Err(TrackerError::MissingSecret);
+        }
+        Self::new(settings.secret.expose().as_bytes(), base_url).map(Some)
+    }
+
+    /// `path` under the base URL, keeping any path the base URL has.
+    fn endpoint(&self, path: &str) -> Url {
+        let mut url = self.base_url.clone();
+        url.set_path(&format!(
+            "{}/{}",
+            self.base_url.path().trim_end_matches('/'),
+            path
+        ));
+        url
+    }
+
+    fn mac(&self, parts: &[&str]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts any key length");
+        for part in parts {
+            mac.update(part.as_bytes());
+            mac.update(b"\n");
+        }
+        mac
+    }
+
+    pub fn sign(&self, parts: &[&str]) -> String {
+        format!("{:x}", self.mac(parts).finalize().into_bytes())
+    }
+
+    /// Constant-time check of a hex signature produced by [`Tracker::sign`].
+    pub fn verify(&self, parts: &[&str], signature: &str) -> bool {
+        match decode_hex(signature) {
+            Some(signature) => self.mac(parts).verify_slice(&signature).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn open_url(&self, issue_id: Uuid, subscriber_id: Uuid) -> String {
+        let (issue, subscriber) = (issue_id.to_string(), subscriber_id.to_string());
+        let mut url = self.endpoint(&format!("track/open/{}/{}", issue, subscriber));
+        url.query_pairs_mut()
+            .append_pair("sig", &self.sign(&["open", &issue, &subscriber]));
+        url.to_string()
+    }
+
+    pub fn click_url(&self, issue_id: Uuid, subscriber_id: Uuid, target: &str) -> String {
+        let (issue, subscriber) = (issue_id.to_string(), subscriber_id.to_string());
+        let mut url = self.endpoint(&format!("track/click/{}/{}", issue, subscriber));
+        url.query_pairs_mut()
+            .append_pair("url", target)
+            .append_pair("sig", &self.sign(&["click", &issue, &subscriber, target]));
+        url.to_string()
+    }
+
+    pub fn for_recipient(&self, issue_id: Uuid, subscriber_id: Uuid) -> TrackedRecipient {
+        TrackedRecipient {
+            tracker: self.clone(),
+            issue_id,
+            subscriber_id,
+        }
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Tracking for one newsletter sent to one subscriber.
+#[derive(Debug, Clone)]
+pub struct TrackedRecipient {
+    tracker: Tracker,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+}
+
+impl TrackedRecipient {
+    /// Routes every `http(s)` link in the (already sanitised) issue HTML
+    /// through the click redirect and appends the open pixel.
+    pub fn apply(&self, html: &str) -> String {
+        let recipient = self.clone();
+        let rewritten = ammonia::Builder::default()
+            .attribute_filter(move |element, attribute, value| {
+                let is_web_link = value.starts_with("http://") || value.starts_with("https://");
+                if element == "a" && attribute == "href" && is_web_link {
+                    return Some(Cow::Owned(recipient.tracker.click_url(
+                        recipient.issue_id,
+                        recipient.subscriber_id,
+                        value,
+                    )));
+                }
+                Some(Cow::Borrowed(value))
+            })
+            .clean(html)
+            .to_string();
+        format!(
+            r#"{}<img src="{}" width="1" height="1" alt="">"#,
+            rewritten,
+            self.tracker.open_url(self.issue_id, self.subscriber_id)
+        )
+    }
+}
+
+/// Records an open or click, unless the subscriber has opted out since.
+#[tracing::instrument(name = "Record email event", skip(pool))]
+pub async fn record_event(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    kind: EmailEventKind,
+    url: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_events (id, issue_id, subscriber_id, kind, url)
+        SELECT $1, $2, id, $4, $5
+        FROM subscriptions
+        WHERE id = $3 AND NOT tracking_opt_out
+        "#,
+        Uuid::new_v4(),
+        issue_id,
+        subscriber_id,
+        kind as EmailEventKind,
+        url
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkStats {
+    pub url: String,
+    pub clicks: i64,
+    pub unique_clicks: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IssueStats {
+    pub opens: i64,
+    pub unique_opens: i64,
+    pub clicks: i64,
+    pub unique_clicks: i64,
+    pub links: Vec<LinkStats>,
+}
+
+#[tracing::instrument(name = "Aggregate issue stats", skip(pool))]
+pub async fn issue_stats(pool: &PgPool, issue_id: Uuid) -> Result<IssueStats, sqlx::Error> {
+    let totals = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE kind = 'Open') AS "opens!",
+            COUNT(DISTINCT subscriber_id) FILTER (WHERE kind = 'Open') AS "unique_opens!",
+            COUNT(*) FILTER (WHERE kind = 'Click') AS "clicks!",
+            COUNT(DISTINCT subscriber_id) FILTER (WHERE kind = 'Click') AS "unique_clicks!"
+        FROM email_events
+        WHERE issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let links = sqlx::query_as!(
+        LinkStats,
+        r#"
+        SELECT url AS "url!", COUNT(*) AS "clicks!", COUNT(DISTINCT subscriber_id) AS "unique_clicks!"
+        FROM email_events
+        WHERE issue_id = $1 AND kind = 'Click' AND url IS NOT NULL
+        GROUP BY url
+        ORDER BY 2 DESC, 1
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(IssueStats {
+        opens: totals.opens,
+        unique_opens: totals.unique_opens,
+        clicks: totals.clicks,
+        unique_clicks: totals.unique_clicks,
+        links,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tracker;
+    use uuid::Uuid;
+
+    fn tracker() -> Tracker {
+        Tracker::new(b"secret", "https://news.example.com/").unwrap()
+    }
+
+    #[test]
+    fn signatures_round_trip() {
+        let tracker = tracker();
+        let signature = tracker.sign(&["open", "a", "b"]);
+
+        assert!(tracker.verify(&["open", "a", "b"], &signature));
+        assert!(!tracker.verify(&["open", "a", "c"], &signature));
+        assert!(!Tracker::new(b"other", "https://news.example.com")
+            .unwrap()
+            .verify(&["open", "a", "b"], &signature));
+        assert!(!tracker.verify(&["open", "a", "b"], "not hex"));
+    }
+
+    #[test]
+    fn web_links_are_rewritten_and_a_pixel_is_added() {
+        let recipient = tracker().for_recipient(Uuid::new_v4(), Uuid::new_v4());
+
+        let html = recipient.apply(
+            r#"<p><a href="https://example.com/post?a=1&amp;b=2">post</a> <a href="mailto:a@example.com">mail</a></p>"#,
+        );
+
+        assert!(html.contains("https://news.example.com/track/click/"));
+        assert!(html.contains("url=https%3A%2F%2Fexample.com%2Fpost%3Fa%3D1%26b%3D2"));
+        assert!(html.contains(r#"href="mailto:a@example.com""#));
+        assert!(html.contains("https://news.example.com/track/open/"));
+    }
+
+    #[test]
+    fn the_base_url_is_checked_up_front() {
+        assert!(Tracker::new(b"secret", "not a url").is_err());
+        assert!(Tracker::new(b"secret", "mailto:news@example.com").is_err());
+
+        let tracker = Tracker::new(b"secret", "https://example.com/news/").unwrap();
+        let url = tracker.click_url(Uuid::nil(), Uuid::nil(), "https://example.com/post");
+
+        assert!(url.starts_with(&format!(
+            "https://example.com/news/track/click/{}/{}?url=",
+            Uuid::nil(),
+            Uuid::nil()
+        )));
+    }
+}
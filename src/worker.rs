@@ -0,0 +1,89 @@
+use anyhow::Context;
+use uuid::Uuid;
+
+use crate::{
+    configuration::WorkerSettings,
+    delivery::{deliver_issue, load_issue, DeliverySummary, PreparedIssue},
+    health::record_heartbeat,
+    startup::AppState,
+};
+
+/// Background process that picks up newsletter sends nobody is finishing:
+/// deliveries left `Pending` by an instance that died mid-send (a crash, or a
+/// drain timeout running out) are resumed once they're `worker.stale_after_secs`
+/// old.
+///
+/// It reports a heartbeat every poll, which `/health/ready` reports on.
+pub struct Worker {
+    state: AppState,
+    id: String,
+    settings: WorkerSettings,
+}
+
+impl Worker {
+    pub fn new(state: AppState, settings: WorkerSettings) -> Self {
+        Self {
+            state,
+            id: format!("worker-{}", Uuid::new_v4()),
+            settings,
+        }
+    }
+
+    /// Polls until the app's shutdown token is cancelled. A send in progress
+    /// stops claiming deliveries at that point, like an HTTP-triggered one.
+    pub async fn run_until_stopped(self) -> Result<(), anyhow::Error> {
+        tracing::info!("Worker {} started", self.id);
+        loop {
+            if let Err(e) = self.run_once().await {
+                tracing::error!("Worker poll failed: {:?}", e);
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(self.settings.poll_interval()) => {}
+                _ = self.state.shutdown.cancelled() => break,
+            }
+        }
+        self.state.pool.close().await;
+        tracing::info!("Worker {} stopped", self.id);
+        Ok(())
+    }
+
+    /// One poll: reports the heartbeat, then resumes every issue with stale
+    /// `Pending` deliveries.
+    pub async fn run_once(&self) -> Result<Vec<DeliverySummary>, anyhow::Error> {
+        let prune_after = self.state.health.settings().worker_heartbeat_max_age();
+        record_heartbeat(&self.state.pool, &self.id, prune_after)
+            .await
+            .context("Failed to record the heartbeat")?;
+
+        let cutoff = chrono::Utc::now() - self.settings.stale_after();
+        let issue_ids = sqlx::query_scalar!(
+            r#"
+            SELECT DISTINCT issue_id AS "issue_id!"
+            FROM idempotency
+            WHERE status = 'Pending' AND issue_id IS NOT NULL AND updated_at < $1
+            "#,
+            cutoff
+        )
+        .fetch_all(&self.state.pool)
+        .await
+        .context("Failed to look for stale deliveries")?;
+
+        let mut summaries = Vec::with_capacity(issue_ids.len());
+        for issue_id in issue_ids {
+            if self.state.shutdown.is_cancelled() {
+                break;
+            }
+            tracing::info!("Resuming issue {} with stale deliveries", issue_id);
+            let body = load_issue(&self.state.pool, issue_id)
+                .await
+                .context("Failed to load the issue")?
+                .context("Stale deliveries reference a missing issue")?;
+            let issue =
+                PreparedIssue::prepare(&self.state.pool, body, &self.state.merge_links()).await?;
+            summaries.push(
+                deliver_issue(&self.state, issue_id, &issue, self.state.delivery.mode).await?,
+            );
+        }
+        Ok(summaries)
+    }
+}
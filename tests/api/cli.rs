@@ -0,0 +1,337 @@
+use std::sync::Arc;
+
+use serde_json::json;
+use zero2prod::{
+    cli::{admin, migrate, subscribers},
+    configuration::{load_configuration, Environment},
+    email_client::FakeBackend,
+    spawn_app,
+    startup::Application,
+    worker::Worker,
+    TestApp,
+};
+
+const SUBSCRIBERS: &str = "\
+email,name,city
+ursula@example.com,Ursula Le Guin,Portland
+not-an-email,Nobody,
+octavia@example.com,Octavia Butler,
+ursula@example.com,Ursula Again,Berkeley
+";
+
+/// A worker sharing the test app's database, sending through `backend`.
+async fn worker_for(app: &TestApp, backend: FakeBackend) -> Worker {
+    let mut settings = load_configuration(Environment::Test).expect("Failed to get configuration");
+    settings.database.database_name = app
+        .pool
+        .connect_options()
+        .get_database()
+        .expect("The test pool names its database")
+        .to_string();
+    let worker_settings = settings.worker.clone();
+    let state = Application::builder(settings)
+        .with_email_backend(Arc::new(backend))
+        .build_state()
+        .await
+        .expect("Failed to build the worker's state");
+    Worker::new(state, worker_settings)
+}
+
+#[tokio::test]
+async fn import_skips_invalid_rows_and_existing_subscribers() {
+    let app = spawn_app().await;
+
+    let summary = subscribers::import(&app.pool, SUBSCRIBERS.as_bytes())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        summary,
+        subscribers::ImportSummary {
+            imported: 2,
+            already_subscribed: 1,
+            invalid: 1,
+        }
+    );
+    let saved =
+        sqlx::query!("SELECT email, name, status, custom_fields FROM subscriptions ORDER BY email")
+            .fetch_all(&app.pool)
+            .await
+            .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[0].email, "octavia@example.com");
+    assert_eq!(saved[0].custom_fields, json!({}));
+    assert_eq!(saved[1].name, "Ursula Le Guin");
+    assert_eq!(saved[1].status, "confirmed");
+    assert_eq!(saved[1].custom_fields, json!({ "city": "Portland" }));
+}
+
+#[tokio::test]
+async fn import_requires_email_and_name_columns() {
+    let app = spawn_app().await;
+
+    let result = subscribers::import(&app.pool, "email\nursula@example.com\n".as_bytes()).await;
+
+    assert!(result.is_err());
+}
+
+const SUBSCRIBERS_WITH_STATUS: &str = "\
+email,name,status,subscribed_at,city
+ursula@example.com,Ursula Le Guin,confirmed,2024-01-02T03:04:05+00:00,Portland
+octavia@example.com,Octavia Butler,Pending,,
+ted@example.com,Ted Chiang,unsubscribed,,Seattle
+";
+
+#[tokio::test]
+async fn import_honours_the_status_and_subscribed_at_columns() {
+    let app = spawn_app().await;
+
+    let summary = subscribers::import(&app.pool, SUBSCRIBERS_WITH_STATUS.as_bytes())
+        .await
+        .unwrap();
+
+    assert_eq!(summary.imported, 3);
+    let saved = sqlx::query!(
+        "SELECT email, status, subscribed_at, custom_fields FROM subscriptions ORDER BY email"
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+    let statuses: Vec<_> = saved.iter().map(|s| s.status.as_str()).collect();
+    assert_eq!(statuses, ["Pending", "unsubscribed", "confirmed"]);
+    assert_eq!(
+        saved[2].subscribed_at.to_rfc3339(),
+        "2024-01-02T03:04:05+00:00"
+    );
+    assert_eq!(saved[2].custom_fields, json!({ "city": "Portland" }));
+}
+
+#[tokio::test]
+async fn import_skips_rows_with_an_unknown_status_or_date() {
+    let app = spawn_app().await;
+    let csv = "\
+email,name,status,subscribed_at
+ursula@example.com,Ursula Le Guin,active,
+octavia@example.com,Octavia Butler,confirmed,yesterday
+";
+
+    let summary = subscribers::import(&app.pool, csv.as_bytes())
+        .await
+        .unwrap();
+
+    assert_eq!(summary.imported, 0);
+    assert_eq!(summary.invalid, 2);
+}
+
+#[tokio::test]
+async fn export_can_be_imported_again() {
+    let app = spawn_app().await;
+    subscribers::import(&app.pool, SUBSCRIBERS_WITH_STATUS.as_bytes())
+        .await
+        .unwrap();
+
+    let mut exported = vec![];
+    subscribers::export(&app.pool, &mut exported, false)
+        .await
+        .unwrap();
+
+    let exported = String::from_utf8(exported).unwrap();
+    let mut lines = exported.lines();
+    assert_eq!(lines.next(), Some("email,name,status,subscribed_at,city"));
+    let rows: Vec<&str> = lines.collect();
+    assert_eq!(rows.len(), 2, "Unsubscribed rows are left out");
+    assert!(rows.iter().any(
+        |row| row.starts_with("ursula@example.com,Ursula Le Guin,confirmed,")
+            && row.ends_with(",Portland")
+    ));
+
+    let other = spawn_app().await;
+    let summary = subscribers::import(&other.pool, exported.as_bytes())
+        .await
+        .unwrap();
+    assert_eq!(summary.imported, 2);
+    assert_eq!(summary.invalid, 0);
+    let original = sqlx::query!(
+        "SELECT email, name, status, subscribed_at, custom_fields FROM subscriptions
+        WHERE status <> 'unsubscribed' ORDER BY email"
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+    let copied = sqlx::query!(
+        "SELECT email, name, status, subscribed_at, custom_fields FROM subscriptions ORDER BY email"
+    )
+    .fetch_all(&other.pool)
+    .await
+    .unwrap();
+    assert_eq!(copied.len(), original.len());
+    for (copied, original) in copied.iter().zip(&original) {
+        assert_eq!(copied.email, original.email);
+        assert_eq!(copied.name, original.name);
+        assert_eq!(copied.status, original.status);
+        assert_eq!(
+            copied.subscribed_at.timestamp(),
+            original.subscribed_at.timestamp()
+        );
+        assert_eq!(copied.custom_fields, original.custom_fields);
+    }
+}
+
+#[tokio::test]
+async fn export_includes_unsubscribed_subscribers_when_asked() {
+    let app = spawn_app().await;
+    subscribers::import(&app.pool, SUBSCRIBERS_WITH_STATUS.as_bytes())
+        .await
+        .unwrap();
+
+    let mut exported = vec![];
+    subscribers::export(&app.pool, &mut exported, true)
+        .await
+        .unwrap();
+
+    let exported = String::from_utf8(exported).unwrap();
+    assert_eq!(exported.lines().count(), 4);
+    assert!(exported.contains("ted@example.com,Ted Chiang,unsubscribed,"));
+}
+
+#[tokio::test]
+async fn create_admin_stores_a_hash_and_rejects_duplicates() {
+    let app = spawn_app().await;
+    let password = admin::generate_password();
+
+    admin::create_admin(&app.pool, "root", &password)
+        .await
+        .unwrap();
+
+    let hash = sqlx::query_scalar!("SELECT password_hash FROM users WHERE username = 'root'")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert!(hash.starts_with("$argon2id$"));
+    assert!(!hash.contains(&password));
+    assert!(admin::create_admin(&app.pool, "root", &password)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn create_admin_rejects_a_short_password() {
+    let app = spawn_app().await;
+
+    assert!(admin::create_admin(&app.pool, "root", "hunter2")
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn migrate_status_lists_every_migration_as_applied() {
+    let app = spawn_app().await;
+
+    let status = migrate::status(&app.pool).await.unwrap();
+
+    assert!(!status.is_empty());
+    assert!(status.iter().all(|migration| migration.applied));
+    assert!(status
+        .iter()
+        .any(|migration| migration.description == "create users table"));
+}
+
+#[tokio::test]
+async fn migrate_revert_undoes_the_latest_migration() {
+    let app = spawn_app().await;
+    let latest = migrate::status(&app.pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap();
+
+    migrate::revert(&app.pool).await.unwrap();
+
+    let pending: Vec<_> = migrate::status(&app.pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|migration| !migration.applied)
+        .map(|migration| migration.version)
+        .collect();
+    assert_eq!(pending, [latest]);
+    migrate::apply(&app.pool).await.unwrap();
+    assert!(migrate::status(&app.pool)
+        .await
+        .unwrap()
+        .iter()
+        .all(|migration| migration.applied));
+}
+
+#[tokio::test]
+async fn migrate_revert_stops_at_migrations_without_a_down_script() {
+    let app = spawn_app().await;
+
+    while migrate::revert(&app.pool).await.is_ok() {}
+
+    let status = migrate::status(&app.pool).await.unwrap();
+    let latest_applied = status.iter().rfind(|m| m.applied).unwrap();
+    assert_eq!(latest_applied.description, "create idempotency table");
+    assert!(status
+        .iter()
+        .skip_while(|m| m.version <= latest_applied.version)
+        .all(|m| !m.applied));
+}
+
+#[tokio::test]
+async fn worker_resumes_stale_pending_deliveries() {
+    let app = spawn_app().await;
+    subscribers::import(&app.pool, SUBSCRIBERS.as_bytes())
+        .await
+        .unwrap();
+    app.publish_and_wait(&json!({ "title": "Issue #1", "message": "Hello" }))
+        .await;
+    // As if the instance sending to Ursula died before recording the result.
+    sqlx::query!(
+        r#"
+        UPDATE idempotency SET status = 'Pending', updated_at = now() - interval '1 hour'
+        WHERE user_id = (SELECT id FROM subscriptions WHERE email = 'ursula@example.com')
+        "#
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    let backend = FakeBackend::new();
+    let worker = worker_for(&app, backend.clone()).await;
+
+    let summaries = worker.run_once().await.unwrap();
+
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(summaries[0].sent, 1);
+    assert_eq!(summaries[0].skipped, 1);
+    let sent = backend.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].recipient.as_ref(), "ursula@example.com");
+    let heartbeats = sqlx::query_scalar!("SELECT COUNT(*) FROM worker_heartbeats")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(heartbeats, Some(1));
+    assert!(worker.run_once().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn worker_leaves_recent_pending_deliveries_alone() {
+    let app = spawn_app().await;
+    subscribers::import(&app.pool, SUBSCRIBERS.as_bytes())
+        .await
+        .unwrap();
+    app.publish_and_wait(&json!({ "title": "Issue #1", "message": "Hello" }))
+        .await;
+    sqlx::query!("UPDATE idempotency SET status = 'Pending'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let backend = FakeBackend::new();
+    let worker = worker_for(&app, backend.clone()).await;
+
+    assert!(worker.run_once().await.unwrap().is_empty());
+    assert!(backend.sent().is_empty());
+}
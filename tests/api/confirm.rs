@@ -1,5 +1,5 @@
 use hyper::StatusCode;
-use zero2prod::spawn_app;
+use zero2prod::{spawn_app, TestApp};
 
 #[tokio::test]
 async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
@@ -62,7 +62,7 @@ async fn confirming_wrong_link_does_not_change_status() {
     assert_eq!(subscriber_data.status, "Pending");
     assert_eq!(subscriber_data.name, "lukar tim");
     assert_eq!(subscriber_data.email, "lukar_tim@gmail.com");
-    let reversed: String = token.chars().into_iter().rev().collect();
+    let reversed: String = token.chars().rev().collect();
     app.get_confirm_subscription(&reversed).await;
 
     let subscriber_data = sqlx::query!(r#"SELECT * FROM subscriptions WHERE email = $1"#, &email,)
@@ -76,14 +76,154 @@ async fn confirming_wrong_link_does_not_change_status() {
 }
 
 #[tokio::test]
-async fn bad_token_link_returns_500() {
+async fn malformed_token_returns_400() {
     let app = spawn_app().await;
     let body = "name=lukar%20tim&email=lukar_tim%40gmail.com";
     let _ = app.post_subscriptions(body.into()).await;
 
     let token = "surely invalid token";
 
+    let response = app.get_confirm_subscription(token).await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+}
+
+#[tokio::test]
+async fn unknown_token_returns_404() {
+    let app = spawn_app().await;
+    let body = "name=lukar%20tim&email=lukar_tim%40gmail.com";
+    let _ = app.post_subscriptions(body.into()).await;
+
+    let token = "a".repeat(25);
+
     let response = app.get_confirm_subscription(&token).await;
 
-    assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+}
+
+#[tokio::test]
+async fn confirming_twice_returns_409() {
+    let app = spawn_app().await;
+    let body = "name=lukar%20tim&email=lukar_tim%40gmail.com";
+    let _ = app.post_subscriptions(body.into()).await;
+    let token = get_token(&app, "lukar_tim@gmail.com").await;
+
+    let first = app.get_confirm_subscription(&token).await;
+    let second = app.get_confirm_subscription(&token).await;
+
+    assert_eq!(StatusCode::OK, first.status());
+    assert_eq!(StatusCode::CONFLICT, second.status());
+}
+
+#[tokio::test]
+async fn expired_token_returns_410_and_does_not_confirm() {
+    let app = spawn_app().await;
+    let body = "name=lukar%20tim&email=lukar_tim%40gmail.com";
+    let _ = app.post_subscriptions(body.into()).await;
+    let token = get_token(&app, "lukar_tim@gmail.com").await;
+    sqlx::query("UPDATE subscriptions_tokens SET created_at = now() - interval '30 days'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let response = app.get_confirm_subscription(&token).await;
+
+    assert_eq!(StatusCode::GONE, response.status());
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "Pending");
+}
+
+async fn get_token(app: &TestApp, email: &str) -> String {
+    sqlx::query!(
+        r#"SELECT t.subscription_tokens FROM subscriptions_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id WHERE s.email = $1"#,
+        email
+    )
+    .fetch_one(&app.pool)
+    .await
+    .expect("token should be present")
+    .subscription_tokens
+}
+
+#[tokio::test]
+async fn confirming_with_json_body_returns_confirmed_status() {
+    let app = spawn_app().await;
+    let body = "name=lukar%20tim&email=lukar_tim%40gmail.com";
+    let _ = app.post_subscriptions(body.into()).await;
+    let token = get_token(&app, "lukar_tim@gmail.com").await;
+
+    let response = app.post_confirm_subscription_json(&token).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "confirmed");
+}
+
+#[tokio::test]
+async fn confirming_with_json_body_reports_unknown_token() {
+    let app = spawn_app().await;
+
+    let response = app.post_confirm_subscription_json(&"a".repeat(25)).await;
+
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+}
+
+#[tokio::test]
+async fn browsers_get_a_confirmation_page() {
+    let app = spawn_app().await;
+    let body = "name=lukar%20tim&email=lukar_tim%40gmail.com";
+    let _ = app.post_subscriptions(body.into()).await;
+    let token = get_token(&app, "lukar_tim@gmail.com").await;
+
+    let response = app
+        .client
+        .get(format!("{}/subscribe/confirm", app.address))
+        .query(&[("subscription_token", &token)])
+        .header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Thanks for confirming"));
+}
+
+#[tokio::test]
+async fn browsers_get_an_expired_link_page() {
+    let app = spawn_app().await;
+    let body = "name=lukar%20tim&email=lukar_tim%40gmail.com";
+    let _ = app.post_subscriptions(body.into()).await;
+    let token = get_token(&app, "lukar_tim@gmail.com").await;
+    sqlx::query("UPDATE subscriptions_tokens SET created_at = now() - interval '30 days'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .client
+        .get(format!("{}/subscribe/confirm", app.address))
+        .query(&[("subscription_token", &token)])
+        .header("Accept", "text/html")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::GONE, response.status());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("This link has expired"));
 }
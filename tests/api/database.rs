@@ -0,0 +1,44 @@
+use hyper::StatusCode;
+use zero2prod::spawn_app_with_settings;
+
+#[tokio::test]
+async fn requests_get_a_503_when_no_connection_frees_up() {
+    let app = spawn_app_with_settings(
+        |settings| {
+            settings.database.pool.max_connections = 1;
+            settings.database.pool.acquire_timeout_ms = 200;
+        },
+        |builder| builder,
+    )
+    .await;
+    let _busy = app.pool.acquire().await.unwrap();
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".to_string())
+        .await;
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/service-unavailable");
+}
+
+#[tokio::test]
+async fn connections_use_the_configured_session_settings() {
+    let app = spawn_app_with_settings(
+        |settings| {
+            settings.database.statement_timeout_ms = Some(100);
+            settings.database.application_name = "zero2prod-test".to_string();
+        },
+        |builder| builder,
+    )
+    .await;
+
+    let application_name: Option<String> =
+        sqlx::query_scalar("SELECT current_setting('application_name')")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+    assert_eq!(application_name.as_deref(), Some("zero2prod-test"));
+    let slow = sqlx::query("SELECT pg_sleep(1)").execute(&app.pool).await;
+    assert!(slow.unwrap_err().to_string().contains("statement timeout"));
+}
@@ -1,5 +1,10 @@
+use std::sync::Arc;
+
 use hyper::StatusCode;
-use zero2prod::spawn_app;
+use serde_json::Value;
+use zero2prod::{
+    email_client::FakeBackend, health::record_heartbeat, spawn_app, spawn_app_with, TestApp,
+};
 
 #[tokio::test]
 async fn health_check_test() {
@@ -14,3 +19,126 @@ async fn health_check_test() {
 
     assert_eq!(response.status(), StatusCode::OK);
 }
+
+async fn get_ready(app: &TestApp) -> (StatusCode, Value) {
+    let response = app
+        .client
+        .get(format!("{}/health/ready", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let status = response.status();
+    (status, response.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn liveness_is_always_ok() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .get(format!("{}/health/live", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "live");
+}
+
+#[tokio::test]
+async fn readiness_reports_every_check_with_its_latency() {
+    let app = spawn_app().await;
+
+    let (status, body) = get_ready(&app).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+    let checks = &body["checks"];
+    assert_eq!(checks["database"]["status"], "up");
+    assert_eq!(checks["migrations"]["status"], "up");
+    assert_eq!(checks["email_backend"]["status"], "up");
+    assert_eq!(checks["email_backend"]["detail"], "fake");
+    assert_eq!(checks["workers"]["status"], "skipped");
+    for check in ["database", "migrations", "email_backend", "workers"] {
+        assert!(checks[check]["latency_ms"].is_number(), "{}", check);
+    }
+}
+
+#[tokio::test]
+async fn a_recent_worker_heartbeat_counts_as_up() {
+    let app = spawn_app().await;
+    record_heartbeat(&app.pool, "worker-1", chrono::Duration::seconds(60))
+        .await
+        .unwrap();
+
+    let (_, body) = get_ready(&app).await;
+
+    assert_eq!(body["checks"]["workers"]["status"], "up");
+}
+
+#[tokio::test]
+async fn a_stale_worker_heartbeat_is_reported_without_making_the_app_unready() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        "INSERT INTO worker_heartbeats (worker_id, last_seen_at) VALUES ('worker-1', now() - interval '1 hour')"
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let (status, body) = get_ready(&app).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["checks"]["workers"]["status"], "down");
+}
+
+#[tokio::test]
+async fn heartbeats_of_workers_that_are_gone_are_pruned() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        "INSERT INTO worker_heartbeats (worker_id, last_seen_at) VALUES ('worker-old', now() - interval '1 hour')"
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    record_heartbeat(&app.pool, "worker-1", chrono::Duration::seconds(60))
+        .await
+        .unwrap();
+
+    let workers = sqlx::query_scalar!("SELECT worker_id FROM worker_heartbeats")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(workers, vec!["worker-1"]);
+}
+
+#[tokio::test]
+async fn the_email_backend_check_is_cached() {
+    let backend = FakeBackend::new();
+    let app = spawn_app_with(|builder| builder.with_email_backend(Arc::new(backend.clone()))).await;
+
+    get_ready(&app).await;
+    let (_, body) = get_ready(&app).await;
+
+    assert_eq!(body["checks"]["email_backend"]["status"], "up");
+    assert_eq!(backend.checks(), 1);
+}
+
+#[tokio::test]
+async fn failed_checks_do_not_expose_the_error() {
+    let app = spawn_app().await;
+    sqlx::query("DROP TABLE _sqlx_migrations")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let (status, body) = get_ready(&app).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["checks"]["migrations"]["status"], "down");
+    assert_eq!(body["checks"]["migrations"]["detail"], "check failed");
+}
@@ -0,0 +1,621 @@
+use std::sync::Arc;
+
+use hyper::StatusCode;
+use serde_json::json;
+use zero2prod::{
+    cli::admin, domain::SubscriberEmail, email_client::FakeBackend, spawn_app, spawn_app_with,
+    spawn_app_with_settings, TestApp,
+};
+
+fn issue() -> serde_json::Value {
+    json!({
+        "title": "Issue #1",
+        "message": "Read [the post](https://example.com/post).",
+    })
+}
+
+async fn idempotency_rows(app: &TestApp) -> Option<i64> {
+    sqlx::query!("SELECT COUNT(*) AS count FROM idempotency")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn preview_returns_the_rendered_issue() {
+    let app = spawn_app().await;
+
+    let response = app.post_issue("preview", &issue()).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let email: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(email["subject"], "Issue #1");
+    assert!(email["html"]
+        .as_str()
+        .unwrap()
+        .contains("href=\"https://example.com/post\""));
+    let text = email["text"].as_str().unwrap();
+    assert!(text.starts_with("Hello Ursula Le Guin,"));
+    assert!(text.contains("Read the post [1].\n\n[1] https://example.com/post"));
+    assert_eq!(idempotency_rows(&app).await, Some(0));
+}
+
+#[tokio::test]
+async fn test_send_without_seed_addresses_is_a_conflict() {
+    let app = spawn_app().await;
+
+    let response = app.post_issue("test-send", &issue()).await;
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/no-seed-addresses");
+    assert_eq!(idempotency_rows(&app).await, Some(0));
+}
+
+#[tokio::test]
+async fn test_send_delivers_the_rendered_issue_to_every_seed_address() {
+    let backend = FakeBackend::new().with_failure("bounce@example.com", "MessageRejected");
+    let app = spawn_app_with_settings(
+        |settings| {
+            settings.issues.seed_addresses = ["editor@example.com", "bounce@example.com"]
+                .into_iter()
+                .map(|email| SubscriberEmail::parse(email).unwrap())
+                .collect();
+        },
+        |builder| builder.with_email_backend(Arc::new(backend.clone())),
+    )
+    .await;
+
+    let response = app.post_issue("test-send", &issue()).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let results: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        results,
+        json!([
+            { "email": "editor@example.com", "status": "sent" },
+            { "email": "bounce@example.com", "status": "failed" },
+        ])
+    );
+    let sent = backend.sent();
+    assert_eq!(sent.len(), 1);
+    let email = &sent[0];
+    assert_eq!(email.recipient.as_ref(), "editor@example.com");
+    assert_eq!(email.subject, "Issue #1");
+    assert!(email.html.contains("href=\"https://example.com/post\""));
+    assert!(email.text.starts_with("Hello Ursula Le Guin,"));
+    assert!(email
+        .text
+        .contains("Read the post [1].\n\n[1] https://example.com/post"));
+    assert_eq!(idempotency_rows(&app).await, Some(0));
+}
+
+#[tokio::test]
+async fn preview_rejects_an_incomplete_issue() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_issue("preview", &json!({ "title": "No body" }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn preview_fills_in_merge_fields_and_custom_fields() {
+    let app = spawn_app().await;
+    let _ = app
+        .post_subscriptions_json(&json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "custom_fields": { "company": "Earthsea" },
+        }))
+        .await;
+
+    let response = app
+        .post_issue(
+            "preview",
+            &json!({
+                "title": "News for {{first_name}}",
+                "message": r#"Hi {{name}} of {{custom.company | default: "nowhere"}}"#,
+            }),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let email: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(email["subject"], "News for Ursula");
+    assert!(email["text"]
+        .as_str()
+        .unwrap()
+        .contains("Hi Ursula Le Guin of sample company"));
+}
+
+#[tokio::test]
+async fn unknown_merge_fields_are_rejected_before_sending() {
+    let app = spawn_app().await;
+    let issue = json!({
+        "title": "Hi {{frist_name}}",
+        "message": "Body",
+    });
+
+    for action in ["preview", "test-send"] {
+        let response = app.post_issue(action, &issue).await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", action);
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["type"], "/problems/invalid-issue");
+    }
+    let response = app
+        .client
+        .post(format!("{}/publish", app.address))
+        .json(&issue)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(idempotency_rows(&app).await, Some(0));
+}
+
+async fn add_confirmed_subscriber(app: &TestApp, name: &str, email: &str) {
+    let _ = app
+        .post_subscriptions_json(&json!({ "name": name, "email": email }))
+        .await;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'confirmed', confirmed_at = now() WHERE email = $1",
+        email
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn report_is_built_from_delivery_records() {
+    let backend = FakeBackend::new().with_failure("ursula_le_guin@gmail.com", "MessageRejected");
+    let app = spawn_app_with(|builder| builder.with_email_backend(Arc::new(backend))).await;
+    add_confirmed_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+
+    let accepted = app.publish_and_wait(&issue()).await;
+    let issue_id = accepted["issue_id"].as_str().unwrap();
+
+    let report: serde_json::Value = app
+        .client
+        .get(format!("{}/issues/{}/report", app.address, issue_id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["counts"]["failed"], 1);
+    assert_eq!(report["counts"]["sent"], 0);
+    assert_eq!(report["counts"]["total"], 1);
+    assert_eq!(report["failures"][0]["count"], 1);
+    assert!(report["timing"]["first_sent_at"].is_null());
+
+    admin::create_admin(&app.pool, "admin", ADMIN_PASSWORD)
+        .await
+        .unwrap();
+    let response = app
+        .client
+        .get(format!("{}/issues/{}/report/failed", app.address, issue_id))
+        .basic_auth("admin", Some(ADMIN_PASSWORD))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["content-disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let csv = response.text().await.unwrap();
+    assert!(csv.starts_with("email,name,error_class,last_error,attempts,updated_at"));
+    assert!(csv.contains("\r\nursula_le_guin@gmail.com,"));
+}
+
+const ADMIN_PASSWORD: &str = "correct horse battery staple";
+
+#[tokio::test]
+async fn failed_recipients_are_only_listed_for_admins() {
+    let app = spawn_app().await;
+    admin::create_admin(&app.pool, "admin", ADMIN_PASSWORD)
+        .await
+        .unwrap();
+    let accepted = app.publish_and_wait(&issue()).await;
+    let url = format!(
+        "{}/issues/{}/report/failed",
+        app.address,
+        accepted["issue_id"].as_str().unwrap()
+    );
+    let test_cases = [
+        (None, "no credentials"),
+        (Some(("admin", "wrong password")), "a wrong password"),
+        (Some(("nobody", ADMIN_PASSWORD)), "an unknown admin"),
+    ];
+
+    for (credentials, description) in test_cases {
+        let mut request = app.client.get(&url);
+        if let Some((username, password)) = credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        let response = request.send().await.unwrap();
+
+        assert_eq!(
+            response.status(),
+            StatusCode::UNAUTHORIZED,
+            "The download was not refused with {}.",
+            description
+        );
+        assert_eq!(
+            response.headers()["www-authenticate"],
+            r#"Basic realm="admin""#
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["type"], "/problems/unauthorized");
+    }
+}
+
+/// Recipients of the issue, leaving out confirmation emails.
+fn issues_sent(backend: &FakeBackend) -> Vec<String> {
+    backend
+        .sent()
+        .into_iter()
+        .filter(|email| email.subject == "Issue #1")
+        .map(|email| email.recipient.as_ref().to_string())
+        .collect()
+}
+
+/// Resumes `issue_id` and waits for the send to finish.
+async fn resume(app: &TestApp, issue_id: &str) {
+    let response = app
+        .client
+        .post(format!("{}/issues/{}/resume", app.address, issue_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.wait_for_deliveries().await;
+}
+
+/// The report's delivery counts for `issue_id`.
+async fn delivery_counts(app: &TestApp, issue_id: &str) -> serde_json::Value {
+    let report: serde_json::Value = app
+        .client
+        .get(format!("{}/issues/{}/report", app.address, issue_id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    report["counts"].clone()
+}
+
+#[tokio::test]
+async fn already_delivered_subscribers_are_skipped_on_resume() {
+    let app = spawn_app().await;
+    add_confirmed_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    let accepted = app.publish_and_wait(&issue()).await;
+    sqlx::query!("UPDATE idempotency SET status = 'Sent'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    resume(&app, accepted["issue_id"].as_str().unwrap()).await;
+
+    let attempts = sqlx::query_scalar!("SELECT attempts FROM idempotency")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(attempts, 1);
+}
+
+#[tokio::test]
+async fn a_pending_delivery_does_not_abandon_the_other_subscribers() {
+    let backend = FakeBackend::new().with_failure("ursula_le_guin@gmail.com", "MessageRejected");
+    let app = spawn_app_with(|builder| builder.with_email_backend(Arc::new(backend.clone()))).await;
+    add_confirmed_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    add_confirmed_subscriber(&app, "tolkien", "tolkien@gmail.com").await;
+    let accepted = app.publish_and_wait(&issue()).await;
+    let issue_id = accepted["issue_id"].as_str().unwrap();
+    // Simulate a run that died half-way through a send.
+    sqlx::query!(
+        "UPDATE idempotency SET status = 'Pending', updated_at = now() - interval '1 hour' \
+         WHERE user_id = (SELECT id FROM subscriptions WHERE email = 'tolkien@gmail.com')"
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    resume(&app, issue_id).await;
+
+    let counts = delivery_counts(&app, issue_id).await;
+    assert_eq!(counts["sent"], 1);
+    assert_eq!(counts["failed"], 1);
+    let attempts = sqlx::query!("SELECT attempts FROM idempotency")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert!(attempts.iter().all(|r| r.attempts == 2));
+    assert_eq!(
+        issues_sent(&backend),
+        vec!["tolkien@gmail.com", "tolkien@gmail.com"]
+    );
+}
+
+#[tokio::test]
+async fn resume_retries_only_undelivered_subscribers() {
+    let backend = FakeBackend::new().with_failure("ursula_le_guin@gmail.com", "MessageRejected");
+    let app = spawn_app_with(|builder| builder.with_email_backend(Arc::new(backend.clone()))).await;
+    add_confirmed_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    add_confirmed_subscriber(&app, "tolkien", "tolkien@gmail.com").await;
+    let accepted = app.publish_and_wait(&issue()).await;
+    let issue_id = accepted["issue_id"].as_str().unwrap();
+
+    let response = app
+        .client
+        .post(format!("{}/issues/{}/resume", app.address, issue_id))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(
+        response.headers()["location"],
+        format!("/issues/{}/report", issue_id).as_str()
+    );
+    let accepted: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(accepted["issue_id"], issue_id);
+    app.wait_for_deliveries().await;
+    let counts = delivery_counts(&app, issue_id).await;
+    assert_eq!(counts["sent"], 1);
+    assert_eq!(counts["failed"], 1);
+    let retried = sqlx::query!(
+        "SELECT i.attempts FROM idempotency i JOIN subscriptions s ON s.id = i.user_id \
+         WHERE s.email = 'ursula_le_guin@gmail.com'"
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(retried.attempts, 2);
+    assert_eq!(issues_sent(&backend), vec!["tolkien@gmail.com"]);
+}
+
+#[tokio::test]
+async fn a_delivery_another_run_is_sending_is_left_alone() {
+    let app = spawn_app().await;
+    add_confirmed_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    let accepted = app.publish_and_wait(&issue()).await;
+    let issue_id = accepted["issue_id"].as_str().unwrap();
+    sqlx::query!("UPDATE idempotency SET status = 'Pending'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    resume(&app, issue_id).await;
+
+    assert_eq!(delivery_counts(&app, issue_id).await["pending"], 1);
+    let attempts = sqlx::query_scalar!("SELECT attempts FROM idempotency")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(attempts, 1);
+}
+
+#[tokio::test]
+async fn republishing_a_message_delivers_it_as_a_new_issue() {
+    let app = spawn_app().await;
+    add_confirmed_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    let first = app.publish_and_wait(&issue()).await;
+
+    let second = app.publish_and_wait(&issue()).await;
+
+    assert_ne!(first["issue_id"], second["issue_id"]);
+    let deliveries = sqlx::query!(
+        r#"SELECT issue_id AS "issue_id!", attempts FROM idempotency ORDER BY created_at"#
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(deliveries.len(), 2);
+    assert_eq!(deliveries[0].issue_id.to_string(), first["issue_id"]);
+    assert_eq!(deliveries[1].issue_id.to_string(), second["issue_id"]);
+    assert!(deliveries.iter().all(|d| d.attempts == 1));
+}
+
+async fn publish_with_key(app: &TestApp, body: &serde_json::Value, key: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/publish", app.address))
+        .header("Idempotency-Key", key)
+        .json(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn a_retried_publish_with_the_same_idempotency_key_sends_once() {
+    let backend = FakeBackend::new();
+    let app = spawn_app_with(|builder| builder.with_email_backend(Arc::new(backend.clone()))).await;
+    add_confirmed_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+
+    let first: serde_json::Value = publish_with_key(&app, &issue(), "publish-1")
+        .await
+        .json()
+        .await
+        .unwrap();
+    app.wait_for_deliveries().await;
+    let retry = publish_with_key(&app, &issue(), "publish-1").await;
+
+    assert_eq!(retry.status(), StatusCode::ACCEPTED);
+    let retry: serde_json::Value = retry.json().await.unwrap();
+    assert_eq!(first["issue_id"], retry["issue_id"]);
+    app.wait_for_deliveries().await;
+    assert_eq!(issues_sent(&backend), ["ursula_le_guin@gmail.com"]);
+    let issues = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(issues, 1);
+}
+
+#[tokio::test]
+async fn an_idempotency_key_cannot_be_reused_for_another_issue() {
+    let app = spawn_app().await;
+    let first = publish_with_key(&app, &issue(), "publish-1").await;
+    assert_eq!(first.status(), StatusCode::ACCEPTED);
+
+    let other = json!({ "title": "Issue #2", "message": "Something else." });
+    let response = publish_with_key(&app, &other, "publish-1").await;
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/idempotency-key-reused");
+}
+
+#[tokio::test]
+async fn an_empty_idempotency_key_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = publish_with_key(&app, &issue(), "").await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/invalid-idempotency-key");
+}
+
+#[tokio::test]
+async fn subscribers_with_the_same_name_have_their_own_deliveries() {
+    let backend = FakeBackend::new().with_failure("tolkien@gmail.com", "MessageRejected");
+    let app = spawn_app_with(|builder| builder.with_email_backend(Arc::new(backend))).await;
+    add_confirmed_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    add_confirmed_subscriber(&app, "le guin", "tolkien@gmail.com").await;
+
+    app.publish_and_wait(&issue()).await;
+
+    let statuses = sqlx::query!(
+        r#"SELECT s.email, i.status::TEXT AS "status!" FROM idempotency i
+        JOIN subscriptions s ON s.id = i.user_id ORDER BY s.email"#
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(statuses[0].email, "tolkien@gmail.com");
+    assert_eq!(statuses[0].status, "Failed");
+    assert_eq!(statuses[1].email, "ursula_le_guin@gmail.com");
+    assert_eq!(statuses[1].status, "Sent");
+}
+
+#[tokio::test]
+async fn resuming_an_unknown_issue_is_not_found() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .post(format!(
+            "{}/issues/{}/resume",
+            app.address,
+            uuid::Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn bulk_mode_records_a_result_for_every_destination() {
+    let backend = FakeBackend::new().with_failure("ursula_le_guin@gmail.com", "MessageRejected");
+    let app = spawn_app_with(|builder| builder.with_email_backend(Arc::new(backend.clone()))).await;
+    add_confirmed_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    add_confirmed_subscriber(&app, "tolkien", "tolkien@gmail.com").await;
+
+    let response = app
+        .client
+        .post(format!("{}/publish?mode=bulk", app.address))
+        .json(&issue())
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.wait_for_deliveries().await;
+    assert_eq!(backend.batches(), 1);
+    let deliveries = sqlx::query!(
+        r#"SELECT s.email, i.status::TEXT AS "status!", i.error_class FROM idempotency i
+        JOIN subscriptions s ON s.id = i.user_id ORDER BY s.email"#
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(deliveries[0].email, "tolkien@gmail.com");
+    assert_eq!(deliveries[0].status, "Sent");
+    assert_eq!(deliveries[0].error_class, None);
+    assert_eq!(deliveries[1].email, "ursula_le_guin@gmail.com");
+    assert_eq!(deliveries[1].status, "Failed");
+    assert_eq!(
+        deliveries[1].error_class.as_deref(),
+        Some("MessageRejected")
+    );
+}
+
+#[tokio::test]
+async fn an_unknown_delivery_mode_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .post(format!("{}/publish?mode=carrier-pigeon", app.address))
+        .json(&issue())
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(idempotency_rows(&app).await, Some(0));
+}
+
+#[tokio::test]
+async fn a_sent_email_that_cannot_be_recorded_is_not_sent_again() {
+    let backend = FakeBackend::new();
+    let app = spawn_app_with(|builder| builder.with_email_backend(Arc::new(backend.clone()))).await;
+    add_confirmed_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    sqlx::raw_sql(
+        r#"
+        CREATE FUNCTION refuse_sent() RETURNS trigger AS $$
+        BEGIN RAISE EXCEPTION 'database went away'; END;
+        $$ LANGUAGE plpgsql;
+        CREATE TRIGGER refuse_sent BEFORE UPDATE ON idempotency
+            FOR EACH ROW WHEN (NEW.status = 'Sent') EXECUTE FUNCTION refuse_sent();
+        "#,
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let accepted = app.publish_and_wait(&issue()).await;
+    resume(&app, accepted["issue_id"].as_str().unwrap()).await;
+
+    assert_eq!(issues_sent(&backend).len(), 1);
+    let status = sqlx::query_scalar!(r#"SELECT status::TEXT AS "status!" FROM idempotency"#)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "Pending");
+}
+
+#[tokio::test]
+async fn resuming_an_issue_skips_subscribers_who_confirmed_after_it() {
+    let backend = FakeBackend::new();
+    let app = spawn_app_with(|builder| builder.with_email_backend(Arc::new(backend.clone()))).await;
+    add_confirmed_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    let accepted = app.publish_and_wait(&issue()).await;
+    add_confirmed_subscriber(&app, "tolkien", "tolkien@gmail.com").await;
+
+    resume(&app, accepted["issue_id"].as_str().unwrap()).await;
+
+    assert_eq!(issues_sent(&backend), ["ursula_le_guin@gmail.com"]);
+}
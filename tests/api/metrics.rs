@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use hyper::StatusCode;
+use zero2prod::{email_client::FakeBackend, spawn_app_with};
+
+#[tokio::test]
+async fn metrics_are_exposed_per_route_and_funnel_stage() {
+    let backend = FakeBackend::new().with_failure("ursula_le_guin@gmail.com", "MessageRejected");
+    let app = spawn_app_with(|builder| builder.with_email_backend(Arc::new(backend))).await;
+    let _ = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let issue_id = uuid::Uuid::new_v4();
+    let _ = app
+        .client
+        .get(format!("{}/issues/{}/report", app.address, issue_id))
+        .send()
+        .await
+        .unwrap();
+
+    let response = app
+        .client
+        .get(format!("{}/metrics", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let text = response.text().await.unwrap();
+    assert!(text.contains(
+        r#"http_requests_total{method="GET",route="/issues/{issue_id}/report",status="404"} 1"#
+    ));
+    assert!(text.contains(r#"subscription_funnel_total{stage="subscribed"} 1"#));
+    assert!(text.contains(r#"emails_failed_total{backend="fake""#));
+    assert!(text.contains("db_pool_connections"));
+    assert!(text.contains("email_queue_depth 0"));
+}
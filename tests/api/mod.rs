@@ -1,3 +1,13 @@
+mod cli;
 mod confirm;
+mod database;
 mod health_check;
+mod issues;
+mod metrics;
+mod request_id;
+mod shutdown;
 mod subscribe;
+mod telemetry;
+mod templates;
+mod tracking;
+mod unsubscribe;
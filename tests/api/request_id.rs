@@ -0,0 +1,94 @@
+use zero2prod::spawn_app;
+
+#[tokio::test]
+async fn a_valid_incoming_request_id_is_echoed() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .get(format!("{}/health_check", app.address))
+        .header("X-Request-Id", "edge-1234")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.headers()["x-request-id"], "edge-1234");
+}
+
+#[tokio::test]
+async fn a_request_id_is_generated_when_missing_or_invalid() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .get(format!("{}/health_check", app.address))
+        .header("X-Request-Id", "not valid!")
+        .send()
+        .await
+        .unwrap();
+
+    let id = response.headers()["x-request-id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(id).is_ok());
+}
+
+#[tokio::test]
+async fn error_bodies_carry_the_request_id() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .get(format!(
+            "{}/issues/{}/report",
+            app.address,
+            uuid::Uuid::new_v4()
+        ))
+        .header("X-Request-Id", "support-case-77")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(response.headers()["x-request-id"], "support-case-77");
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["request_id"], "support-case-77");
+}
+
+#[tokio::test]
+async fn routing_and_path_errors_are_problem_details_with_the_request_id() {
+    let app = spawn_app().await;
+    let test_cases = [
+        (
+            "GET",
+            "/issues/not-a-uuid/report",
+            400,
+            "/problems/invalid-path",
+        ),
+        (
+            "GET",
+            "/track/open/1/2?sig=x",
+            400,
+            "/problems/invalid-path",
+        ),
+        ("GET", "/no/such/route", 404, "/problems/not-found"),
+        ("DELETE", "/subscribe", 405, "/problems/method-not-allowed"),
+    ];
+
+    for (method, path, status, kind) in test_cases {
+        let response = app
+            .client
+            .request(method.parse().unwrap(), format!("{}{}", app.address, path))
+            .header("X-Request-Id", "support-case-78")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), status, "{} {}", method, path);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/problem+json"
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["type"], kind, "{} {}", method, path);
+        assert_eq!(problem["request_id"], "support-case-78");
+    }
+}
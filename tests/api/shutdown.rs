@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use hyper::StatusCode;
+use serde_json::json;
+use sqlx::{Connection, PgConnection};
+use uuid::Uuid;
+use zero2prod::{spawn_app, TestApp};
+
+async fn add_confirmed_subscribers(app: &TestApp, count: usize) {
+    for i in 0..count {
+        sqlx::query!(
+            r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token, confirmed_at)
+            VALUES ($1, $2, $3, now(), 'confirmed', $4, now())"#,
+            Uuid::new_v4(),
+            format!("reader{}@example.com", i),
+            format!("Reader {}", i),
+            Uuid::new_v4().to_string(),
+        )
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    }
+}
+
+/// A connection of its own, since shutting down closes the app's pool.
+async fn connect(app: &TestApp) -> PgConnection {
+    PgConnection::connect_with(&app.pool.connect_options())
+        .await
+        .expect("Failed to connect to Postgres")
+}
+
+#[tokio::test]
+async fn shutting_down_stops_the_server_and_closes_the_pool() {
+    let app = spawn_app().await;
+
+    app.shutdown.cancel();
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !app.pool.is_closed() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("The pool was not closed");
+    assert!(app
+        .client
+        .get(format!("{}/health/live", app.address))
+        .send()
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn a_send_interrupted_by_shutdown_leaves_nothing_pending() {
+    let app = spawn_app().await;
+    add_confirmed_subscribers(&app, 60).await;
+
+    // Sends are paced at 10/s by the test config, so this takes seconds.
+    let response = app
+        .post_publish(&json!({ "title": "Issue #1", "message": "Hello" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    app.shutdown.cancel();
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !app.pool.is_closed() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("The pool was not closed");
+
+    let mut connection = connect(&app).await;
+    let counts = sqlx::query!(
+        r#"SELECT
+            COUNT(*) FILTER (WHERE status = 'Pending') AS "pending!",
+            COUNT(*) AS "claimed!"
+        FROM idempotency"#
+    )
+    .fetch_one(&mut connection)
+    .await
+    .unwrap();
+    assert_eq!(counts.pending, 0);
+    assert!(counts.claimed > 0 && counts.claimed < 60);
+}
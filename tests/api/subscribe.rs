@@ -1,5 +1,8 @@
+use std::sync::Arc;
+
 use hyper::StatusCode;
-use zero2prod::spawn_app;
+use serde_json::json;
+use zero2prod::{email_client::FakeBackend, spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn subscribe_return_200_for_valid_form_data() {
@@ -12,15 +15,70 @@ async fn subscribe_return_200_for_valid_form_data() {
 }
 
 #[tokio::test]
-async fn subscribe_return_500_unique_failed() {
+async fn subscribing_twice_is_idempotent_and_resends_confirmation() {
     let test_app = spawn_app().await;
 
-    let body = "name=luka%tim&email=luka_tim%40gmail.com";
+    let body = "name=luka%20tim&email=luka_tim%40gmail.com";
+
+    let first = test_app.post_subscriptions(body.to_string()).await;
+    let second = test_app.post_subscriptions(body.to_string()).await;
+
+    assert_eq!(first.status(), StatusCode::OK);
+    assert_eq!(second.status(), StatusCode::OK);
+    let subscribers = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&test_app.pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.len(), 1);
+    let tokens = sqlx::query!("SELECT subscription_tokens FROM subscriptions_tokens")
+        .fetch_all(&test_app.pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.len(), 2);
+}
+
+#[tokio::test]
+async fn the_confirmation_email_links_to_the_subscribers_unsubscribe_page() {
+    let backend = FakeBackend::new();
+    let test_app =
+        spawn_app_with(|builder| builder.with_email_backend(Arc::new(backend.clone()))).await;
+
+    let body = "name=luka%20tim&email=luka_tim%40gmail.com";
+    test_app.post_subscriptions(body.to_string()).await;
+    test_app.post_subscriptions(body.to_string()).await;
+
+    let token = sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&test_app.pool)
+        .await
+        .unwrap()
+        .unsubscribe_token;
+    let link = format!("/unsubscribe?unsubscribe_token={}", token);
+    let sent = backend.sent();
+    assert_eq!(sent.len(), 2);
+    for email in sent {
+        assert!(email.html.contains(&link), "{}", email.html);
+    }
+}
+
+#[tokio::test]
+async fn subscribing_when_already_confirmed_looks_like_a_new_subscription() {
+    let test_app = spawn_app().await;
+
+    let body = "name=luka%20tim&email=luka_tim%40gmail.com";
+    test_app.post_subscriptions(body.to_string()).await;
+    sqlx::query("UPDATE subscriptions SET status = 'confirmed', confirmed_at = now()")
+        .execute(&test_app.pool)
+        .await
+        .unwrap();
 
-    let _response = test_app.post_subscriptions(body.to_string()).await;
     let response = test_app.post_subscriptions(body.to_string()).await;
 
-    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.status(), StatusCode::OK);
+    let tokens = sqlx::query!("SELECT subscription_tokens FROM subscriptions_tokens")
+        .fetch_all(&test_app.pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.len(), 1);
 }
 
 #[tokio::test]
@@ -94,12 +152,146 @@ AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA&email=luka%40g
     }
 }
 
+#[tokio::test]
 async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     let app = spawn_app().await;
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    let _ = sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;",)
+    let _ = sqlx::query("ALTER TABLE subscriptions_tokens DROP COLUMN subscription_tokens;")
         .execute(&app.pool)
         .await;
     let response = app.post_subscriptions(body.into()).await;
     assert_eq!(response.status().as_u16(), 500);
+
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/internal-error");
+    assert!(problem["request_id"].is_string());
+    assert!(
+        !problem.to_string().contains("subscription_tokens"),
+        "Database details leaked to the client: {}",
+        problem
+    );
+}
+
+#[tokio::test]
+async fn subscribe_returns_400_with_suggestion_for_mistyped_domain() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmial.com";
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let message = response.text().await.unwrap();
+    assert!(
+        message.contains("Did you mean gmail.com?"),
+        "Unexpected body: {}",
+        message
+    );
+}
+
+#[tokio::test]
+async fn subscribe_accepts_domain_with_only_address_records() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40example.com";
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn subscribe_validation_errors_are_problem_details() {
+    let app = spawn_app().await;
+    let body = "name=%3Cscript%3E&email=ursula_le_guin%40gmail.com";
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/validation-error");
+    assert_eq!(problem["status"], 400);
+    assert_eq!(problem["errors"][0]["field"], "name");
+    assert!(problem["request_id"].is_string());
+}
+
+#[tokio::test]
+async fn subscribe_accepts_json_and_returns_status() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"});
+
+    let response = app.post_subscriptions_json(&body).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribe_json_validates_like_the_form() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({"name": "", "email": "ursula_le_guin@gmail.com"});
+
+    let response = app.post_subscriptions_json(&body).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["errors"][0]["field"], "name");
+}
+
+#[tokio::test]
+async fn subscribe_form_returns_json_when_accepted() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let response = app
+        .client
+        .post(format!("{}/subscribe", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept", "application/json")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribe_rejects_custom_fields_outside_the_configured_schema() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (json!({ "plan": "gold" }), "custom_fields.plan"),
+        (
+            json!({ "company": "x".repeat(257) }),
+            "custom_fields.company",
+        ),
+        (
+            json!({ "company": "Earthsea", "plan": "gold" }),
+            "custom_fields",
+        ),
+    ];
+
+    for (custom_fields, field) in test_cases {
+        let response = app
+            .post_subscriptions_json(&json!({
+                "name": "le guin",
+                "email": "ursula_le_guin@gmail.com",
+                "custom_fields": custom_fields,
+            }))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", field);
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["errors"][0]["field"], field);
+    }
+    let saved = sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(0));
 }
@@ -0,0 +1,77 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{body::Bytes, extract::State, http::HeaderMap, routing::post, Router};
+use tokio::net::TcpListener;
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+use zero2prod::{configuration::TelemetrySettings, telemetry};
+
+type Received = Arc<Mutex<Vec<(HeaderMap, Bytes)>>>;
+
+/// Stands in for an OpenTelemetry collector's OTLP/HTTP receiver.
+async fn spawn_collector() -> (String, Received) {
+    let received: Received = Default::default();
+    let app = Router::new()
+        .route(
+            "/v1/traces",
+            post(
+                |State(received): State<Received>, headers: HeaderMap, body: Bytes| async move {
+                    received.lock().unwrap().push((headers, body));
+                },
+            ),
+        )
+        .with_state(received.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    (address, received)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_are_exported_to_the_collector() {
+    let (address, received) = spawn_collector().await;
+    let settings = TelemetrySettings {
+        otlp_endpoint: Some(address),
+        service_name: "zero2prod-under-test".to_string(),
+        sampling_ratio: 1.0,
+    };
+    let provider = telemetry::tracer_provider(&settings).unwrap().unwrap();
+    let subscriber = Registry::default().with(telemetry::layer(&provider));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let _span = tracing::info_span!("publish newsletter").entered();
+    });
+    tokio::task::spawn_blocking(move || provider.force_flush())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    let (headers, body) = &received[0];
+    assert_eq!(headers["content-type"], "application/x-protobuf");
+    let body = String::from_utf8_lossy(body);
+    assert!(body.contains("zero2prod-under-test"));
+    assert!(body.contains("publish newsletter"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn nothing_is_exported_when_sampling_is_off() {
+    let (address, received) = spawn_collector().await;
+    let settings = TelemetrySettings {
+        otlp_endpoint: Some(address),
+        service_name: "zero2prod-under-test".to_string(),
+        sampling_ratio: 0.0,
+    };
+    let provider = telemetry::tracer_provider(&settings).unwrap().unwrap();
+    let subscriber = Registry::default().with(telemetry::layer(&provider));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let _span = tracing::info_span!("publish newsletter").entered();
+    });
+    tokio::task::spawn_blocking(move || provider.force_flush())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(received.lock().unwrap().is_empty());
+}
@@ -0,0 +1,110 @@
+use hyper::StatusCode;
+use serde_json::json;
+use zero2prod::spawn_app;
+
+#[tokio::test]
+async fn saving_a_template_creates_a_new_version() {
+    let app = spawn_app().await;
+    let template = json!({
+        "name": "newsletter.txt",
+        "list": "weekly",
+        "body": "Hi {{subscriber_name}}, {{content_text}}",
+    });
+
+    let first = app.post_template(&template).await;
+    let second = app.post_template(&template).await;
+
+    assert_eq!(first.status(), StatusCode::CREATED);
+    assert_eq!(second.status(), StatusCode::CREATED);
+    let first: serde_json::Value = first.json().await.unwrap();
+    let second: serde_json::Value = second.json().await.unwrap();
+    assert_eq!(first["version"], 1);
+    assert_eq!(second["version"], 2);
+    assert_eq!(second["list"], "weekly");
+}
+
+#[tokio::test]
+async fn invalid_templates_are_rejected_and_not_stored() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("{{#if subscriber_name}}unclosed", "syntax error"),
+        ("Hello {{subscriber_nmae}}", "unknown variable"),
+        ("{{> missing_partial}}", "unknown partial"),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app
+            .post_template(&json!({ "name": "newsletter.html", "body": body }))
+            .await;
+
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "The API did not reject a template with a {}.",
+            description
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["type"], "/problems/invalid-template");
+    }
+
+    let saved = sqlx::query!("SELECT COUNT(*) AS count FROM email_templates")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(0));
+}
+
+#[tokio::test]
+async fn a_partial_that_breaks_other_templates_is_rejected() {
+    let app = spawn_app().await;
+    let override_response = app
+        .post_template(&json!({
+            "name": "newsletter.html",
+            "list": "weekly",
+            "body": "{{#> layout}}{{{content_html}}}{{/layout}}",
+        }))
+        .await;
+    assert_eq!(override_response.status(), StatusCode::CREATED);
+
+    let response = app
+        .post_template(&json!({
+            "name": "layout",
+            "body": "{{subscriber_nmae}} {{> @partial-block}}",
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/invalid-template");
+    let saved = sqlx::query!("SELECT COUNT(*) AS count FROM email_templates WHERE name = 'layout'")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(0));
+}
+
+#[tokio::test]
+async fn concurrent_saves_cannot_combine_into_a_broken_set() {
+    let app = spawn_app().await;
+    let footer = app
+        .post_template(&json!({ "name": "footer", "body": "Thanks for reading" }))
+        .await;
+    assert_eq!(footer.status(), StatusCode::CREATED);
+
+    // Each is valid on its own, but the override renders the new footer,
+    // which uses a variable nothing provides.
+    let broken_footer = json!({ "name": "footer", "body": "{{missing}}" });
+    let uses_footer = json!({
+        "name": "newsletter.html",
+        "list": "weekly",
+        "body": "{{{content_html}}} {{> footer}}",
+    });
+    let (first, second) = tokio::join!(
+        app.post_template(&broken_footer),
+        app.post_template(&uses_footer)
+    );
+
+    let statuses = [first.status(), second.status()];
+    assert!(statuses.contains(&StatusCode::CREATED));
+    assert!(statuses.contains(&StatusCode::BAD_REQUEST));
+}
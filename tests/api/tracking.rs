@@ -0,0 +1,189 @@
+use hyper::StatusCode;
+use serde_json::json;
+use uuid::Uuid;
+use zero2prod::{
+    configuration::{load_configuration, Environment},
+    spawn_app,
+    tracking::Tracker,
+    TestApp,
+};
+
+struct Recipient {
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    unsubscribe_token: String,
+}
+
+async fn publish_to_subscriber(app: &TestApp) -> Recipient {
+    let _ = app
+        .post_subscriptions_json(&json!({ "name": "le guin", "email": "ursula_le_guin@gmail.com" }))
+        .await;
+    app.publish_and_wait(
+        &json!({ "title": "Issue", "message": "[Read](https://example.com/post)" }),
+    )
+    .await;
+
+    let subscriber = sqlx::query!("SELECT id, unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    let issue = sqlx::query!("SELECT id FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    Recipient {
+        issue_id: issue.id,
+        subscriber_id: subscriber.id,
+        unsubscribe_token: subscriber.unsubscribe_token,
+    }
+}
+
+fn tracker() -> Tracker {
+    let configuration = load_configuration(Environment::Test).unwrap();
+    Tracker::new(
+        configuration.tracking.secret.expose().as_bytes(),
+        &configuration.application.base_url(),
+    )
+    .unwrap()
+}
+
+/// Tracking URLs point at the configured base URL; replay them against the test app.
+fn local(app: &TestApp, url: &str) -> String {
+    let base_url = load_configuration(Environment::Test)
+        .unwrap()
+        .application
+        .base_url();
+    url.replacen(&base_url, &app.address, 1)
+}
+
+async fn stats(app: &TestApp, issue_id: Uuid) -> serde_json::Value {
+    app.client
+        .get(format!("{}/issues/{}/stats", app.address, issue_id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn signed_click_redirects_and_is_counted() {
+    let app = spawn_app().await;
+    let recipient = publish_to_subscriber(&app).await;
+    let url = tracker().click_url(
+        recipient.issue_id,
+        recipient.subscriber_id,
+        "https://example.com/post",
+    );
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let response = client.get(local(&app, &url)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()["location"], "https://example.com/post");
+    let stats = stats(&app, recipient.issue_id).await;
+    assert_eq!(stats["clicks"], 1);
+    assert_eq!(stats["unique_clicks"], 1);
+    assert_eq!(stats["links"][0]["url"], "https://example.com/post");
+}
+
+#[tokio::test]
+async fn open_pixel_is_counted() {
+    let app = spawn_app().await;
+    let recipient = publish_to_subscriber(&app).await;
+    let url = tracker().open_url(recipient.issue_id, recipient.subscriber_id);
+
+    for _ in 0..2 {
+        let response = app.client.get(local(&app, &url)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "image/gif");
+    }
+
+    let stats = stats(&app, recipient.issue_id).await;
+    assert_eq!(stats["opens"], 2);
+    assert_eq!(stats["unique_opens"], 1);
+}
+
+#[tokio::test]
+async fn tampered_click_url_is_rejected() {
+    let app = spawn_app().await;
+    let recipient = publish_to_subscriber(&app).await;
+    let url = tracker()
+        .click_url(
+            recipient.issue_id,
+            recipient.subscriber_id,
+            "https://example.com/post",
+        )
+        .replace("example.com", "evil.com");
+
+    let response = app.client.get(local(&app, &url)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(stats(&app, recipient.issue_id).await["clicks"], 0);
+}
+
+#[tokio::test]
+async fn opted_out_subscribers_are_not_tracked() {
+    let app = spawn_app().await;
+    let recipient = publish_to_subscriber(&app).await;
+
+    let response = app
+        .client
+        .post(format!("{}/tracking/opt-out", app.address))
+        .query(&[("unsubscribe_token", &recipient.unsubscribe_token)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let url = tracker().open_url(recipient.issue_id, recipient.subscriber_id);
+    let response = app.client.get(local(&app, &url)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(stats(&app, recipient.issue_id).await["opens"], 0);
+}
+
+#[tokio::test]
+async fn stats_for_an_unknown_issue_are_not_found() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .get(format!("{}/issues/{}/stats", app.address, Uuid::new_v4()))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn the_opt_out_link_only_asks_for_confirmation() {
+    let app = spawn_app().await;
+    let recipient = publish_to_subscriber(&app).await;
+
+    let response = app
+        .client
+        .get(format!("{}/tracking/opt-out", app.address))
+        .query(&[("unsubscribe_token", &recipient.unsubscribe_token)])
+        .header("Accept", "text/html")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains(r#"<form method="post">"#));
+    let opted_out = sqlx::query_scalar!("SELECT tracking_opt_out FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert!(!opted_out);
+}
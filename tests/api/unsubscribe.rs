@@ -0,0 +1,84 @@
+use hyper::StatusCode;
+use zero2prod::{spawn_app, TestApp};
+
+async fn subscribe(app: &TestApp) -> String {
+    let body = "name=lukar%20tim&email=lukar_tim%40gmail.com";
+    let _ = app.post_subscriptions(body.into()).await;
+    sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .unsubscribe_token
+}
+
+async fn status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn unsubscribe_link_asks_for_confirmation_without_unsubscribing() {
+    let app = spawn_app().await;
+    let token = subscribe(&app).await;
+
+    let response = app
+        .client
+        .get(format!("{}/unsubscribe", app.address))
+        .query(&[("unsubscribe_token", &token)])
+        .header("Accept", "text/html")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains(r#"<form method="post">"#));
+    assert_eq!(status(&app).await, "Pending");
+}
+
+#[tokio::test]
+async fn posting_the_confirmation_marks_subscriber_as_unsubscribed() {
+    let app = spawn_app().await;
+    let token = subscribe(&app).await;
+
+    let response = app
+        .client
+        .post(format!("{}/unsubscribe", app.address))
+        .query(&[("unsubscribe_token", &token)])
+        .header("Accept", "text/html")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("You will not receive any more emails"));
+    assert_eq!(status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unknown_unsubscribe_token_returns_404() {
+    let app = spawn_app().await;
+
+    for request in [
+        app.client.get(format!("{}/unsubscribe", app.address)),
+        app.client.post(format!("{}/unsubscribe", app.address)),
+    ] {
+        let response = request
+            .query(&[("unsubscribe_token", "nope")])
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}